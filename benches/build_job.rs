@@ -0,0 +1,119 @@
+//! Times `CAMJOB::build` on a generated mesh of about 100k triangles, which converts
+//! the mesh to a `TriMesh` once and shares it across tasks, against processing the
+//! same tasks with a conversion each, as they did before. Run with
+//! `cargo bench --bench build_job`.
+
+use std::time::{Duration, Instant};
+use carver::prelude::*;
+use nalgebra::{Point3, Vector3};
+
+/// Quads per side of the slab's wavy top; two triangles each.
+const GRID: usize = 224;
+const SIZE: f32 = 100.0;
+const HEIGHT: f32 = 20.0;
+const RUNS: usize = 3;
+
+/// A closed slab whose top is a rippled height field: `2 * GRID^2` triangles on top,
+/// plus the sides and a bottom fanned from its center.
+fn rippled_slab() -> IndexedMesh {
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    let height_at = |x: f32, y: f32| HEIGHT + 2.0 * (x * 0.2).sin() * (y * 0.15).cos();
+    let coordinate = |i: usize| -SIZE / 2.0 + SIZE * i as f32 / GRID as f32;
+    let mut vertex = |point: Point3<f32>| {
+        vertices.push(point);
+        vertices.len() - 1
+    };
+
+    let top: Vec<Vec<usize>> = (0..=GRID)
+        .map(|i| (0..=GRID).map(|j| vertex(Point3::new(coordinate(i), coordinate(j), height_at(coordinate(i), coordinate(j))))).collect())
+        .collect();
+    // Bottom vertices under the top's boundary, going once around it
+    let boundary: Vec<(usize, usize)> = (0..GRID)
+        .map(|k| (k, 0))
+        .chain((0..GRID).map(|k| (GRID, k)))
+        .chain((0..GRID).map(|k| (GRID - k, GRID)))
+        .chain((0..GRID).map(|k| (0, GRID - k)))
+        .collect();
+    let bottom: Vec<usize> = boundary.iter().map(|&(i, j)| vertex(Point3::new(coordinate(i), coordinate(j), 0.0))).collect();
+    let bottom_center = vertex(Point3::origin());
+
+    let mut face = |a: usize, b: usize, c: usize, outward: Vector3<f32>| faces.push((a, b, c, outward));
+    for i in 0..GRID {
+        for j in 0..GRID {
+            let (a, b, c, d) = (top[i][j], top[i + 1][j], top[i + 1][j + 1], top[i][j + 1]);
+            face(a, b, c, Vector3::z());
+            face(a, c, d, Vector3::z());
+        }
+    }
+    let n = boundary.len();
+    for k in 0..n {
+        let (i, j) = boundary[k];
+        let (next_i, next_j) = boundary[(k + 1) % n];
+        let midpoint = Vector3::new(coordinate(i) + coordinate(next_i), coordinate(j) + coordinate(next_j), 0.0);
+        face(top[i][j], top[next_i][next_j], bottom[(k + 1) % n], midpoint);
+        face(top[i][j], bottom[(k + 1) % n], bottom[k], midpoint);
+        face(bottom_center, bottom[(k + 1) % n], bottom[k], -Vector3::z());
+    }
+
+    let faces = faces
+        .into_iter()
+        .map(|(a, b, c, outward)| {
+            let normal = (vertices[b] - vertices[a]).cross(&(vertices[c] - vertices[a]));
+            let (b, c, normal) = if normal.dot(&outward) < 0.0 { (c, b, -normal) } else { (b, c, normal) };
+            let normal = normal.normalize();
+            IndexedTriangle { normal: Vector::new([normal.x, normal.y, normal.z]), vertices: [a, b, c] }
+        })
+        .collect();
+    let vertices = vertices.iter().map(|v| Vector::new([v.x, v.y, v.z])).collect();
+    IndexedMesh { vertices, faces }
+}
+
+fn tasks(mesh: &IndexedMesh) -> Vec<Box<dyn CAMTask>> {
+    let mut tasks: Vec<Box<dyn CAMTask>> = (1..=4)
+        .map(|layer| Box::new(ContourTrace::new(256, Point3::new(0.0, 0.0, layer as f32 * 4.0), Vector3::z(), mesh, 0)) as Box<dyn CAMTask>)
+        .collect();
+    tasks.push(Box::new(MultiContourTrace::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, 18.0), 16, 128, 0)));
+    tasks.push(Box::new(RasterSurface::new(2.0, 30.0, 40.0, mesh, 0)));
+    tasks
+}
+
+fn job(mesh: &IndexedMesh) -> CAMJOB {
+    let mut job = CAMJOB::new();
+    job.set_mesh(mesh.clone()).expect("generated mesh is valid");
+    job.add_tool(Tool::new(0, "Endmill".to_string(), 30.0, 6.0)).unwrap();
+    for task in tasks(mesh) {
+        job.add_task(task);
+    }
+    job
+}
+
+/// Shortest of `RUNS` timings of `run`.
+fn time(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn main() {
+    let mesh = rippled_slab();
+    println!("mesh: {} triangles, job: {} tasks", mesh.faces.len(), tasks(&mesh).len());
+
+    let conversion = time(|| drop(indexed_mesh_to_trimesh(&mesh)));
+    println!("TriMesh conversion:            {:>10.2?}", conversion);
+
+    let per_task = time(|| {
+        for mut task in tasks(&mesh) {
+            task.process(&mesh, &indexed_mesh_to_trimesh(&mesh), &Tolerances::default(), &mut |_| {}).expect("task processes");
+        }
+    });
+    println!("tasks, one conversion each:    {:>10.2?}", per_task);
+
+    let build = time(|| job(&mesh).build().expect("job builds"));
+    println!("build (one shared conversion): {:>10.2?}", build);
+}
@@ -1,13 +1,17 @@
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
 use kiss3d::window::Window;
 use kiss3d::scene::SceneNode;
-use kiss3d::nalgebra::{Point3, Vector3, Translation3, UnitQuaternion, Isometry3};
+use kiss3d::nalgebra::{Point3, Vector3, Translation3, Isometry3};
 use kiss3d::conrod::{color, widget, Colorable, Labelable, Positionable, Sizeable, Widget, UiCell};
 use kiss3d::conrod::widget_ids;
 use stl_io::IndexedMesh;
-use crate::cam_job::{CAMJOB, Keypoint};
-use crate::tool::Tool;
+use crate::cam_job::{CAMJOB, Keypoint, Units};
+use crate::errors::CAMError;
+use crate::stl_operations::{box_wireframe_edges, face_normal_lines, get_bounds};
+use crate::report::dimension_labels;
 
 widget_ids! {
     pub struct Ids {
@@ -17,6 +21,7 @@ widget_ids! {
         toggle_stock_mesh_button,
         toggle_keypoints_button,
         toggle_keypoint_lines_button,
+        toggle_face_normals_button,
         layers_text,
         current_layer_text,
         rays_text,
@@ -31,12 +36,19 @@ widget_ids! {
         time_step_text,
         time_step_slider,
         toggle_simulation_mesh_button,
+        toggle_bounds_button,
+        flip_tool_posture_button,
+        bounds_dimension_texts[],
+        mesh_opacity_text,
+        mesh_opacity_slider,
+        tasks_header_text,
+        task_texts[],
     }
 }
 
 pub struct AppState {
     pub mesh: IndexedMesh,
-    pub cam_job: Arc<Mutex<CAMJOB>>,
+    pub cam_job: Rc<RefCell<CAMJOB>>,
     pub num_layers: usize,
     pub num_rays: usize,
     pub ray_length: f32,
@@ -44,24 +56,52 @@ pub struct AppState {
     pub current_layer: usize,
     pub animation_speed: f32,
     pub show_mesh: bool,
+    pub mesh_opacity: f32,
     pub show_stock_mesh: bool,
     pub show_keypoints: bool,
     pub show_keypoint_lines: bool,
+    /// Draws the mesh's own face normals, to spot flipped or garbage normals.
+    pub show_face_normals: bool,
+    face_normal_lines: Vec<(Point3<f32>, Point3<f32>)>,
     pub current_keypoint: usize,
     pub job_origin: Isometry3<f32>,
+    /// The origin sliders run from `-origin_slider_range` to `origin_slider_range`.
+    pub origin_slider_range: f32,
+    /// Set when the origin moves during playback; the next animation step retracts the
+    /// tool to clearance instead of jumping straight to the shifted toolpath.
+    pub retract_pending: bool,
+    /// Height above the top of the stock the tool retracts to.
+    pub retract_clearance: f32,
+    last_tool_position: Option<Point3<f32>>,
+    active_tool_id: Option<usize>,
+    /// Whether playback brightens the keypoint lines of the layer being cut.
+    pub highlight_current_layer: bool,
+    /// Brightness (0 to 1) of the other layers' lines while highlighting.
+    pub dimmed_layer_brightness: f32,
+    /// Points the tool along the reversed keypoint normal, i.e. approaching the
+    /// surface from outside, instead of along the outward normal itself.
+    pub flip_tool_posture: bool,
     pub keypoint_spheres: Vec<SceneNode>,
     pub stock_mesh: SceneNode,
     pub current_time_step: usize,
     pub max_time_steps: usize,
     pub show_simulation_mesh: bool,
     pub simulation_mesh: Option<SceneNode>,
+    simulation_mesh_dirty: bool,
+    pub show_bounds: bool,
+    /// Bounds of the part as loaded, before `center_and_scale_mesh` moved it.
+    pub original_bounds: Option<(Point3<f32>, Point3<f32>)>,
+    /// Set while `start_build` has a build running on a worker thread.
+    pub build_in_progress: bool,
+    build_result: Option<Receiver<Result<Vec<Vec<Keypoint>>, CAMError>>>,
     ids: Ids,
 }
 impl AppState {
     pub fn new(mesh: IndexedMesh, cam_job: CAMJOB, stock_mesh: SceneNode, ui: &mut UiCell) -> Self {
+        let face_normal_length = get_bounds(&mesh).map_or(1.0, |(min, max)| (max - min).norm() * FACE_NORMAL_LENGTH_FRACTION);
         AppState {
             mesh: mesh.clone(),
-            cam_job: Arc::new(Mutex::new(cam_job)),
+            cam_job: Rc::new(RefCell::new(cam_job)),
             num_layers: 40,
             num_rays: 100,
             ray_length: 0.9,
@@ -69,83 +109,256 @@ impl AppState {
             current_layer: 0,
             animation_speed: 1.0,
             show_mesh: true,
+            mesh_opacity: 0.0,
             show_stock_mesh: true,
             show_keypoints: true,
             show_keypoint_lines: true,
+            show_face_normals: false,
+            face_normal_lines: face_normal_lines(&mesh, face_normal_length),
             current_keypoint: 0,
             job_origin: Isometry3::identity(),
+            origin_slider_range: origin_slider_range(&mesh),
+            retract_pending: false,
+            retract_clearance: 5.0,
+            last_tool_position: None,
+            active_tool_id: None,
+            highlight_current_layer: true,
+            dimmed_layer_brightness: 0.25,
+            flip_tool_posture: false,
             keypoint_spheres: Vec::new(),
             stock_mesh,
             current_time_step: 0,
             max_time_steps: 100,
             show_simulation_mesh: false,
             simulation_mesh: None,
+            simulation_mesh_dirty: false,
+            show_bounds: false,
+            original_bounds: None,
+            build_in_progress: false,
+            build_result: None,
             ids: Ids::new(ui.widget_id_generator()),
         }
     }
 
     pub fn animate(&mut self) {
-        let keypoints = self.cam_job.lock().unwrap().gather_keypoints();
-        if !keypoints.is_empty() {
-            self.current_keypoint = (self.current_keypoint + 1) % keypoints.len();
-            let keypoint = &keypoints[self.current_keypoint];
-            let transformed_position = self.job_origin * keypoint.position;
-            
-            let mut cam_job = self.cam_job.lock().unwrap();
-            let task = cam_job.get_tasks().get(0).unwrap();
-            let tool_id = task.get_tool_id();
-            if let Some(tool) = cam_job.get_tool_mut(tool_id) {
+        let keypoints = self.cam_job.borrow().gather_keypoints();
+        match next_playback_step(&mut self.retract_pending, self.current_keypoint, keypoints.len()) {
+            Some(PlaybackStep::Retract) => self.retract_tool(),
+            Some(PlaybackStep::MoveTo(index)) => self.move_tool_to(&keypoints, index),
+            None => {}
+        }
+    }
+
+    /// Jumps playback to keypoint `index` of the whole job, e.g. to render a chosen
+    /// frame. Out of range indices are ignored.
+    pub fn seek_keypoint(&mut self, index: usize) {
+        let keypoints = self.cam_job.borrow().gather_keypoints();
+        if index < keypoints.len() {
+            self.move_tool_to(&keypoints, index);
+        }
+    }
+
+    fn move_tool_to(&mut self, keypoints: &[Keypoint], index: usize) {
+        self.current_keypoint = index;
+        let keypoint = &keypoints[index];
+        let transformed_position = self.job_origin * keypoint.position;
+
+        let cam_job = self.cam_job.borrow();
+        if let Some(tool_id) = keypoint.tool_id {
+            // Crossing into a task with another tool: swap the visible tool
+            if let Some(previous) = self.active_tool_id.filter(|&previous| previous != tool_id) {
+                if let Some(tool) = cam_job.get_tool(previous) {
+                    tool.set_visible(false);
+                }
+            }
+            if let Some(tool) = cam_job.get_tool(tool_id) {
                 tool.set_position(transformed_position);
-                tool.set_orientation(keypoint.normal);
+                tool.set_orientation(self.tool_axis(keypoint));
                 tool.set_visible(true);
             }
         }
+        self.last_tool_position = Some(transformed_position);
+        self.active_tool_id = keypoint.tool_id;
+        self.current_layer = cam_job.layer_index_at(index).unwrap_or(0);
+    }
+
+    /// Lifts the active tool straight up to clearance above the stock (under the
+    /// current job origin).
+    fn retract_tool(&mut self) {
+        let (last_position, tool_id) = match (self.last_tool_position, self.active_tool_id) {
+            (Some(position), Some(tool_id)) => (position, tool_id),
+            _ => return,
+        };
+
+        let cam_job = self.cam_job.borrow();
+        let stock_top = cam_job.get_stock_mesh()
+            .and_then(|stock| get_bounds(stock).ok())
+            .map(|(_, max)| (self.job_origin * max).z);
+        let retracted = retracted_position(&last_position, stock_top, self.retract_clearance);
+
+        if let Some(tool) = cam_job.get_tool(tool_id) {
+            tool.set_position(retracted);
+        }
+        self.last_tool_position = Some(retracted);
+    }
+
+    /// The tool cutting at the current point of playback.
+    pub fn active_tool_id(&self) -> Option<usize> {
+        self.active_tool_id
+    }
+
+    /// Direction the tool is drawn along at `keypoint`, under the current job origin.
+    /// Every task stores outward normals, so flipping applies the same way to all.
+    pub fn tool_axis(&self, keypoint: &Keypoint) -> Vector3<f32> {
+        tool_axis(&self.job_origin, &keypoint.normal, self.flip_tool_posture)
+    }
+
+    /// Moves the job origin. During playback this first schedules a retract.
+    pub fn set_job_origin(&mut self, origin: Isometry3<f32>) {
+        if origin == self.job_origin {
+            return;
+        }
+        self.retract_pending = retract_after_origin_change(self.retract_pending, &self.job_origin, &origin, self.is_playing);
+        self.job_origin = origin;
+        self.place_keypoint_spheres();
     }
 
+    /// Replaces the keypoint spheres with one per keypoint of the current build, in its
+    /// task's color, shown according to `show_keypoints`.
+    pub fn rebuild_keypoint_spheres(&mut self, window: &mut Window) {
+        for sphere in &mut self.keypoint_spheres {
+            window.remove_node(sphere);
+        }
+        let keypoints = self.cam_job.borrow().gather_keypoints_with_task();
+        self.keypoint_spheres = keypoints
+            .iter()
+            .map(|(task_index, _)| {
+                let mut sphere = window.add_sphere(KEYPOINT_SPHERE_RADIUS);
+                let [r, g, b] = get_task_color(*task_index);
+                sphere.set_color(r, g, b);
+                sphere.set_visible(self.show_keypoints);
+                sphere
+            })
+            .collect();
+        self.place_keypoint_spheres();
+    }
+
+    /// Moves each sphere onto its keypoint under the current job origin.
+    fn place_keypoint_spheres(&mut self) {
+        if self.keypoint_spheres.is_empty() {
+            return;
+        }
+        let keypoints = self.cam_job.borrow().gather_keypoints();
+        for (sphere, keypoint) in self.keypoint_spheres.iter_mut().zip(&keypoints) {
+            let position = self.job_origin * keypoint.position;
+            sphere.set_local_translation(Translation3::from(position.coords));
+        }
+    }
+
+    /// Draws each keypoint's normal in its task's color. During playback with
+    /// `highlight_current_layer` set, the layer being cut is brightened and every
+    /// other layer dimmed.
     pub fn draw_keypoint_lines(&self, window: &mut Window) {
         if !self.show_keypoint_lines {
             return;
         }
-    
-        let cam_job = self.cam_job.lock().unwrap();
-        let tasks = cam_job.get_tasks();
-        for (task_index, task) in tasks.iter().enumerate() {
-            let keypoints = task.get_keypoints();
-            let color = get_task_color(task_index);
-            for keypoint in keypoints {
-                let start = self.job_origin * keypoint.position;
-                let end = start + self.job_origin.rotation * (keypoint.normal * self.ray_length);
-                window.draw_line(&start, &end, &Point3::from(color));
+
+        let cam_job = self.cam_job.borrow();
+        let keypoints = cam_job.gather_keypoints_with_task();
+        let current_layer = if self.highlight_current_layer && self.is_playing {
+            layer_being_cut(&keypoints, self.current_keypoint)
+        } else {
+            None
+        };
+
+        for (task_index, keypoint) in &keypoints {
+            let color = layer_line_color(*task_index, keypoint.layer, current_layer, self.dimmed_layer_brightness);
+            let start = self.job_origin * keypoint.position;
+            let end = start + self.job_origin.rotation * (keypoint.normal * self.ray_length);
+            window.draw_line(&start, &end, &Point3::from(color));
+        }
+    }
+
+    /// Extent labels for the part's current bounds, shown next to the box while
+    /// `show_bounds` is set.
+    pub fn bounds_dimension_labels(&self, units: Units) -> Vec<String> {
+        match get_bounds(&self.mesh) {
+            Ok((min, max)) if self.show_bounds => dimension_labels(&min, &max, units).to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Draws each face normal of the mesh in yellow while `show_face_normals` is set.
+    pub fn draw_face_normals(&self, window: &mut Window) {
+        if !self.show_face_normals {
+            return;
+        }
+        let color = Point3::new(1.0, 1.0, 0.0);
+        for (start, end) in &self.face_normal_lines {
+            window.draw_line(start, end, &color);
+        }
+    }
+
+    /// Draws the part's original bounds (orange) and current bounds (cyan) as wireframe
+    /// boxes, to show where the CAD origin was relative to the centered part.
+    pub fn draw_bounds(&self, window: &mut Window) {
+        if !self.show_bounds {
+            return;
+        }
+
+        let mut boxes = Vec::new();
+        if let Some((min, max)) = self.original_bounds {
+            boxes.push((min, max, Point3::new(1.0, 0.5, 0.0)));
+        }
+        if let Ok((min, max)) = get_bounds(&self.mesh) {
+            boxes.push((min, max, Point3::new(0.0, 1.0, 1.0)));
+        }
+        for (min, max, color) in boxes {
+            for (start, end) in box_wireframe_edges(&min, &max) {
+                window.draw_line(&start, &end, &color);
             }
         }
     }
 
     pub fn update_simulation(&mut self) {
         println!("Updating simulation for time step: {}", self.current_time_step);
-        let mut cam_job = self.cam_job.lock().unwrap();
-        cam_job.update_to_time_step(self.current_time_step);
+        let mut cam_job = self.cam_job.borrow_mut();
+        if let Err(e) = cam_job.update_to_time_step(self.current_time_step) {
+            eprintln!("Failed to update simulation: {}", e);
+        }
     }
 
-    pub fn generate_simulation_mesh(&mut self) {
-        println!("Generating simulation mesh for time step: {}", self.current_time_step);
-        let cam_job = self.cam_job.lock().unwrap();
-        if let Some(sim_mesh) = &mut self.simulation_mesh {
-            cam_job.update_simulation_mesh(sim_mesh, self.current_time_step);
-        } else {
-            let new_mesh = cam_job.create_simulation_mesh(self.current_time_step);
-            self.simulation_mesh = Some(new_mesh);
+    /// Rebuilds the simulation mesh in `window` if the time step changed while it was
+    /// shown, or it was just turned on.
+    pub fn generate_simulation_mesh(&mut self, window: &mut Window) {
+        if !self.simulation_mesh_dirty {
+            return;
+        }
+        self.simulation_mesh_dirty = false;
+
+        let mut cam_job = self.cam_job.borrow_mut();
+        let result = match self.simulation_mesh.as_mut() {
+            Some(sim_mesh) => cam_job.update_simulation_mesh(sim_mesh, window.scene_mut(), self.current_time_step),
+            None => match cam_job.create_simulation_mesh(window.scene_mut(), self.current_time_step) {
+                Ok(new_mesh) => {
+                    self.simulation_mesh = Some(new_mesh);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to generate simulation mesh: {}", e);
         }
     }
 
     pub fn update_tool_position(&mut self) {
-        let mut cam_job = self.cam_job.lock().unwrap();
-        if let Some(tool_position) = cam_job.get_tool_position_at_time_step(self.current_time_step) {
-            let transformed_position = self.job_origin * tool_position;
-            let task = cam_job.get_tasks().get(0).unwrap();
-            let tool_id = task.get_tool_id();
-            if let Some(tool) = cam_job.get_tool_mut(tool_id) {
+        let cam_job = self.cam_job.borrow();
+        if let Some(keypoint) = cam_job.keypoint_at_time_step(self.current_time_step) {
+            let transformed_position = self.job_origin * keypoint.position;
+            if let Some(tool) = keypoint.tool_id.and_then(|tool_id| cam_job.get_tool(tool_id)) {
                 tool.set_position(transformed_position);
-                // You might also want to update the tool orientation here
+                tool.set_orientation(self.tool_axis(&keypoint));
             }
         }
     }
@@ -155,6 +368,10 @@ impl AppState {
         // Implement the logic to show/hide the mesh in your rendering engine
     }
 
+    pub fn set_mesh_opacity(&mut self, opacity: f32) {
+        self.mesh_opacity = opacity.clamp(0.0, 1.0);
+    }
+
     pub fn toggle_stock_mesh_visibility(&mut self) {
         self.show_stock_mesh = !self.show_stock_mesh;
         self.stock_mesh.set_visible(self.show_stock_mesh);
@@ -171,10 +388,23 @@ impl AppState {
         self.show_keypoint_lines = !self.show_keypoint_lines;
     }
 
+    pub fn toggle_face_normals_visibility(&mut self) {
+        self.show_face_normals = !self.show_face_normals;
+    }
+
+    pub fn toggle_bounds_visibility(&mut self) {
+        self.show_bounds = !self.show_bounds;
+    }
+
+    pub fn toggle_tool_posture(&mut self) {
+        self.flip_tool_posture = !self.flip_tool_posture;
+        self.update_tool_position();
+    }
+
     pub fn toggle_simulation_mesh_visibility(&mut self) {
         self.show_simulation_mesh = !self.show_simulation_mesh;
         if self.show_simulation_mesh {
-            self.generate_simulation_mesh();
+            self.simulation_mesh_dirty = true;
         }
         if let Some(sim_mesh) = &mut self.simulation_mesh {
             sim_mesh.set_visible(self.show_simulation_mesh);
@@ -182,14 +412,161 @@ impl AppState {
     }
 
     pub fn update_job_origin(&mut self, x: f32, y: f32, z: f32) {
-        self.job_origin.translation.vector.x = x;
-        self.job_origin.translation.vector.y = y;
-        self.job_origin.translation.vector.z = z;
+        let mut origin = self.job_origin;
+        origin.translation.vector.x = x;
+        origin.translation.vector.y = y;
+        origin.translation.vector.z = z;
+        self.set_job_origin(origin);
+    }
+
+    /// Builds the job on a worker thread, leaving the job unlocked so the window keeps
+    /// rendering. Does nothing while a build is already running; `poll_build` picks
+    /// up the result.
+    pub fn start_build(&mut self) {
+        if self.build_in_progress {
+            return;
+        }
+        let request = match self.cam_job.borrow().build_request() {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Failed to build CAM job: {}", e);
+                return;
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The viewer may have closed by the time the build finishes
+            sender.send(request.run()).ok();
+        });
+        self.build_result = Some(receiver);
+        self.build_in_progress = true;
+    }
+
+    /// Hands a finished background build to the job. Call once per frame; keypoint
+    /// lines keep showing the previous build until this applies the new one.
+    pub fn poll_build(&mut self, window: &mut Window) {
+        let result = match self.build_result.as_ref().map(Receiver::try_recv) {
+            Some(Ok(result)) => result,
+            Some(Err(TryRecvError::Disconnected)) => Err(CAMError::ProcessingError("build thread panicked".into())),
+            Some(Err(TryRecvError::Empty)) | None => return,
+        };
+        self.build_result = None;
+        self.build_in_progress = false;
+
+        let mut cam_job = self.cam_job.borrow_mut();
+        match result.and_then(|task_keypoints| cam_job.apply_build(task_keypoints)) {
+            Ok(()) => {
+                println!("{}", cam_job.report());
+                drop(cam_job);
+                self.rebuild_keypoint_spheres(window);
+            }
+            Err(e) => eprintln!("Failed to build CAM job: {}", e),
+        }
     }
 
     pub fn set_current_time_step(&mut self, time_step: usize) {
         self.current_time_step = time_step.min(self.max_time_steps);
         self.update_simulation();
+        self.update_tool_position();
+        if self.show_simulation_mesh {
+            self.simulation_mesh_dirty = true;
+        }
+    }
+}
+
+const MESH_COLOR: [f32; 3] = [0.8, 0.8, 0.8];
+
+const KEYPOINT_SPHERE_RADIUS: f32 = 0.01;
+
+/// Length of the drawn face normals, as a fraction of the mesh's bounding box diagonal.
+const FACE_NORMAL_LENGTH_FRACTION: f32 = 0.02;
+
+/// Reach of the origin sliders past the mesh's largest extent.
+const ORIGIN_SLIDER_EXTENT_FACTOR: f32 = 1.5;
+
+/// Default `origin_slider_range` for `mesh`: `ORIGIN_SLIDER_EXTENT_FACTOR` times its
+/// largest extent, so the origin can be moved well clear of the part at any scale.
+/// Falls back to 1 for an empty or flat mesh.
+pub fn origin_slider_range(mesh: &IndexedMesh) -> f32 {
+    match get_bounds(mesh) {
+        Ok((min, max)) if (max - min).max() > 0.0 => (max - min).max() * ORIGIN_SLIDER_EXTENT_FACTOR,
+        _ => 1.0,
+    }
+}
+
+/// kiss3d has no per-node alpha, so opacity is approximated by fading the surface
+/// color towards the (black) background. At zero opacity only the wireframe is drawn.
+pub fn apply_mesh_opacity(node: &mut SceneNode, opacity: f32) {
+    let [r, g, b] = faded_mesh_color(opacity);
+    node.set_surface_rendering_activation(opacity > 0.0);
+    node.set_color(r, g, b);
+    node.set_lines_color(Some(Point3::from(MESH_COLOR)));
+}
+
+/// What the next animation step does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlaybackStep {
+    /// Lift the tool clear before following an origin that moved during playback.
+    Retract,
+    /// Move the tool to this keypoint of the whole job.
+    MoveTo(usize),
+}
+
+/// The step after `current_keypoint` of `keypoint_count`, wrapping to the start. A
+/// pending retract comes first and is consumed; the move follows on the next step.
+fn next_playback_step(retract_pending: &mut bool, current_keypoint: usize, keypoint_count: usize) -> Option<PlaybackStep> {
+    if std::mem::take(retract_pending) {
+        return Some(PlaybackStep::Retract);
+    }
+    (keypoint_count > 0).then(|| PlaybackStep::MoveTo((current_keypoint + 1) % keypoint_count))
+}
+
+/// `retract_pending` once the origin moves from `current` to `origin`. Moving it during
+/// playback schedules a retract, and one already scheduled stays pending.
+fn retract_after_origin_change(retract_pending: bool, current: &Isometry3<f32>, origin: &Isometry3<f32>, is_playing: bool) -> bool {
+    retract_pending || (is_playing && origin != current)
+}
+
+/// Straight above `position`, at least `clearance` over `stock_top` (the top of the
+/// stock under the current job origin), or `clearance` over `position` without stock.
+fn retracted_position(position: &Point3<f32>, stock_top: Option<f32>, clearance: f32) -> Point3<f32> {
+    let top = stock_top.unwrap_or(position.z);
+    Point3::new(position.x, position.y, position.z.max(top + clearance))
+}
+
+/// Surface color of the mesh at `opacity`, clamped to `0.0..=1.0`.
+fn faded_mesh_color(opacity: f32) -> [f32; 3] {
+    let opacity = opacity.clamp(0.0, 1.0);
+    MESH_COLOR.map(|channel| channel * opacity)
+}
+
+/// `normal` under `job_origin`, reversed when `flip` is set so the tool approaches the
+/// surface from outside.
+fn tool_axis(job_origin: &Isometry3<f32>, normal: &Vector3<f32>, flip: bool) -> Vector3<f32> {
+    let normal = job_origin.rotation * normal;
+    if flip {
+        -normal
+    } else {
+        normal
+    }
+}
+
+/// The task and task layer of the keypoint at `current_keypoint` in `keypoints`, as
+/// returned by `CAMJOB::gather_keypoints_with_task`.
+fn layer_being_cut(keypoints: &[(usize, Keypoint)], current_keypoint: usize) -> Option<(usize, usize)> {
+    keypoints.get(current_keypoint).map(|(task_index, keypoint)| (*task_index, keypoint.layer))
+}
+
+/// Line color for a keypoint on `layer` of task `task_index`. While `highlighted` (a
+/// task and layer) is being cut, that layer is brightened and every other one dimmed
+/// to `dimmed_brightness`.
+fn layer_line_color(task_index: usize, layer: usize, highlighted: Option<(usize, usize)>, dimmed_brightness: f32) -> [f32; 3] {
+    let color = get_task_color(task_index);
+    match highlighted {
+        Some(highlighted) if highlighted == (task_index, layer) => color.map(|channel| channel + (1.0 - channel) * 0.5),
+        Some(_) => color.map(|channel| channel * dimmed_brightness),
+        None => color,
     }
 }
 
@@ -205,27 +582,39 @@ fn get_task_color(task_index: usize) -> [f32; 3] {
     COLORS[task_index % COLORS.len()]
 }
 pub fn handle_ui(app_state: &mut AppState, ui: &mut UiCell) -> bool {
+    let (task_descriptions, units) = {
+        let cam_job = app_state.cam_job.borrow();
+        let descriptions: Vec<String> = cam_job.get_tasks().iter().map(|task| task.describe(cam_job.units)).collect();
+        (descriptions, cam_job.units)
+    };
+    app_state.ids.task_texts.resize(task_descriptions.len(), &mut ui.widget_id_generator());
+    let dimension_texts = app_state.bounds_dimension_labels(units);
+    app_state.ids.bounds_dimension_texts.resize(dimension_texts.len(), &mut ui.widget_id_generator());
+
     let ids = &app_state.ids;
     let mut ui_changed = false;
     let mut toggle_mesh = false;
     let mut toggle_stock_mesh = false;
     let mut toggle_keypoints = false;
     let mut toggle_keypoint_lines = false;
+    let mut toggle_face_normals = false;
     let mut toggle_simulation_mesh = false;
+    let mut toggle_bounds = false;
+    let mut toggle_tool_posture = false;
     let mut new_is_playing = app_state.is_playing;
     let mut new_job_origin = app_state.job_origin;
     let mut new_time_step = app_state.current_time_step;
+    let mut new_mesh_opacity = app_state.mesh_opacity;
+    let mut start_build = false;
 
     // Process button
     for _click in widget::Button::new()
         .top_left_with_margin(20.0)
         .w_h(100.0, 30.0)
-        .label("Process")
+        .label(if app_state.build_in_progress { "Processing..." } else { "Process" })
         .set(ids.process_button, ui)
     {
-        if let Err(e) = app_state.cam_job.lock().unwrap().build() {
-            eprintln!("Failed to build CAM job: {}", e);
-        } 
+        start_build = true;
         ui_changed = true;
     }
 
@@ -284,6 +673,17 @@ pub fn handle_ui(app_state: &mut AppState, ui: &mut UiCell) -> bool {
         ui_changed = true;
     }
 
+    // Toggle Face Normals button
+    for _click in widget::Button::new()
+        .right_from(ids.toggle_keypoint_lines_button, 10.0)
+        .w_h(150.0, 30.0)
+        .label(if app_state.show_face_normals { "Hide Face Normals" } else { "Show Face Normals" })
+        .set(ids.toggle_face_normals_button, ui)
+    {
+        toggle_face_normals = true;
+        ui_changed = true;
+    }
+
     // Display current values
     widget::Text::new(&format!("Layers: {}", app_state.num_layers))
         .down_from(ids.toggle_keypoint_lines_button, 10.0)
@@ -311,12 +711,17 @@ pub fn handle_ui(app_state: &mut AppState, ui: &mut UiCell) -> bool {
         .set(ids.animation_speed_text, ui);
 
     // Job Origin controls
-    widget::Text::new(&format!("Origin X: {:.2}", app_state.job_origin.translation.vector.x))
+    widget::Text::new(&format!(
+        "Origin X: {:.2} {}",
+        units.from_mm(app_state.job_origin.translation.vector.x),
+        units.suffix()
+    ))
         .down_from(ids.animation_speed_text, 10.0)
         .color(color::BLACK)
         .set(ids.origin_x_text, ui);
 
-    for value in widget::Slider::new(app_state.job_origin.translation.vector.x, -1.0, 1.0)
+    let origin_range = app_state.origin_slider_range;
+    if let Some(value) = widget::Slider::new(app_state.job_origin.translation.vector.x, -origin_range, origin_range)
         .down_from(ids.origin_x_text, 5.0)
         .w_h(200.0, 30.0)
         .set(ids.origin_x_slider, ui)
@@ -333,7 +738,7 @@ pub fn handle_ui(app_state: &mut AppState, ui: &mut UiCell) -> bool {
         .color(color::BLACK)
         .set(ids.time_step_text, ui);
 
-    for value in widget::Slider::new(app_state.current_time_step as f32, 0.0, app_state.max_time_steps as f32)
+    if let Some(value) = widget::Slider::new(app_state.current_time_step as f32, 0.0, app_state.max_time_steps as f32)
         .down_from(ids.time_step_text, 5.0)
         .w_h(200.0, 30.0)
         .set(ids.time_step_slider, ui)
@@ -353,8 +758,74 @@ pub fn handle_ui(app_state: &mut AppState, ui: &mut UiCell) -> bool {
         ui_changed = true;
     }
 
+    // Toggle Bounds button
+    for _click in widget::Button::new()
+        .right_from(ids.toggle_simulation_mesh_button, 10.0)
+        .w_h(100.0, 30.0)
+        .label(if app_state.show_bounds { "Hide Bounds" } else { "Show Bounds" })
+        .set(ids.toggle_bounds_button, ui)
+    {
+        toggle_bounds = true;
+        ui_changed = true;
+    }
+
+    // Flip Tool Posture button
+    for _click in widget::Button::new()
+        .right_from(ids.toggle_bounds_button, 10.0)
+        .w_h(100.0, 30.0)
+        .label(if app_state.flip_tool_posture { "Unflip Tool" } else { "Flip Tool" })
+        .set(ids.flip_tool_posture_button, ui)
+    {
+        toggle_tool_posture = true;
+        ui_changed = true;
+    }
+
+    // Bounding box extents, stacked right of the buttons
+    let mut previous = None;
+    for (label, &id) in dimension_texts.iter().zip(ids.bounds_dimension_texts.iter()) {
+        let text = widget::Text::new(label).color(color::BLACK);
+        match previous {
+            None => text.right_from(ids.flip_tool_posture_button, 10.0).set(id, ui),
+            Some(previous) => text.down_from(previous, 5.0).set(id, ui),
+        }
+        previous = Some(id);
+    }
+
+    // Mesh opacity control
+    widget::Text::new(&format!("Mesh Opacity: {:.2}", app_state.mesh_opacity))
+        .down_from(ids.toggle_simulation_mesh_button, 10.0)
+        .color(color::BLACK)
+        .set(ids.mesh_opacity_text, ui);
+
+    if let Some(value) = widget::Slider::new(app_state.mesh_opacity, 0.0, 1.0)
+        .down_from(ids.mesh_opacity_text, 5.0)
+        .w_h(200.0, 30.0)
+        .set(ids.mesh_opacity_slider, ui)
+    {
+        new_mesh_opacity = value;
+        ui_changed = true;
+    }
+
+    // Task list
+    widget::Text::new("Tasks:")
+        .down_from(ids.mesh_opacity_slider, 10.0)
+        .color(color::BLACK)
+        .set(ids.tasks_header_text, ui);
+
+    let mut previous = ids.tasks_header_text;
+    for (description, &id) in task_descriptions.iter().zip(ids.task_texts.iter()) {
+        widget::Text::new(description)
+            .down_from(previous, 5.0)
+            .color(color::BLACK)
+            .set(id, ui);
+        previous = id;
+    }
+
     // Apply all changes at once
     if ui_changed {
+        if start_build {
+            app_state.start_build();
+        }
         if toggle_mesh {
             app_state.toggle_mesh_visibility();
         }
@@ -367,13 +838,127 @@ pub fn handle_ui(app_state: &mut AppState, ui: &mut UiCell) -> bool {
         if toggle_keypoint_lines {
             app_state.toggle_keypoint_lines_visibility();
         }
+        if toggle_face_normals {
+            app_state.toggle_face_normals_visibility();
+        }
         if toggle_simulation_mesh {
             app_state.toggle_simulation_mesh_visibility();
         }
+        if toggle_bounds {
+            app_state.toggle_bounds_visibility();
+        }
+        if toggle_tool_posture {
+            app_state.toggle_tool_posture();
+        }
         app_state.is_playing = new_is_playing;
-        app_state.job_origin = new_job_origin;
+        app_state.set_job_origin(new_job_origin);
+        app_state.set_mesh_opacity(new_mesh_opacity);
         app_state.set_current_time_step(new_time_step);
     }
 
     ui_changed
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cam_job::CAMTask;
+    use crate::tasks::{ContourTrace, RasterSurface};
+    use crate::test_support::{box_mesh, trimesh};
+    use crate::tolerances::Tolerances;
+
+    #[test]
+    fn mesh_fades_toward_the_background_with_opacity() {
+        assert_eq!(faded_mesh_color(1.0), MESH_COLOR);
+        assert_eq!(faded_mesh_color(0.0), [0.0; 3]);
+        assert_eq!(faded_mesh_color(0.5), MESH_COLOR.map(|channel| channel * 0.5));
+        // Out of range slider values are clamped
+        assert_eq!(faded_mesh_color(3.0), MESH_COLOR);
+        assert_eq!(faded_mesh_color(-1.0), [0.0; 3]);
+    }
+
+    #[test]
+    fn retract_lifts_the_tool_clear_of_the_stock() {
+        let position = Point3::new(1.0, 2.0, -3.0);
+        assert_eq!(retracted_position(&position, Some(0.5), 5.0), Point3::new(1.0, 2.0, 5.5));
+        // Already clear: stays where it is
+        assert_eq!(retracted_position(&Point3::new(1.0, 2.0, 9.0), Some(0.5), 5.0), Point3::new(1.0, 2.0, 9.0));
+        assert_eq!(retracted_position(&position, None, 5.0), Point3::new(1.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn moving_the_origin_during_playback_retracts_before_the_next_move() {
+        let (start, moved) = (Isometry3::identity(), Isometry3::translation(1.0, 0.0, 0.0));
+        // Paused, or not actually moved: nothing to retract
+        assert!(!retract_after_origin_change(false, &start, &moved, false));
+        assert!(!retract_after_origin_change(false, &start, &start, true));
+
+        let mut retract_pending = retract_after_origin_change(false, &start, &moved, true);
+        assert!(retract_pending);
+        // Moving again before the retract has run keeps it pending
+        retract_pending = retract_after_origin_change(retract_pending, &moved, &start, true);
+        assert!(retract_pending);
+
+        assert_eq!(next_playback_step(&mut retract_pending, 2, 5), Some(PlaybackStep::Retract));
+        assert!(!retract_pending);
+        assert_eq!(next_playback_step(&mut retract_pending, 2, 5), Some(PlaybackStep::MoveTo(3)));
+        assert_eq!(next_playback_step(&mut retract_pending, 4, 5), Some(PlaybackStep::MoveTo(0)));
+        assert_eq!(next_playback_step(&mut retract_pending, 0, 0), None);
+    }
+
+    #[test]
+    fn playback_highlights_the_layer_being_cut() {
+        let layered = |task_index: usize, layer: usize| {
+            (task_index, Keypoint { layer, ..Keypoint::new(Point3::origin(), Vector3::z()) })
+        };
+        let keypoints = vec![layered(0, 0), layered(0, 0), layered(0, 1), layered(1, 0)];
+        assert_eq!(layer_being_cut(&keypoints, 1), Some((0, 0)));
+        assert_eq!(layer_being_cut(&keypoints, 2), Some((0, 1)));
+        // Layer 0 of the next task is a different layer
+        assert_eq!(layer_being_cut(&keypoints, 3), Some((1, 0)));
+        assert_eq!(layer_being_cut(&keypoints, 4), None);
+
+        let base = get_task_color(0);
+        let brightened = layer_line_color(0, 1, Some((0, 1)), 0.25);
+        assert!((0..3).all(|i| brightened[i] >= base[i]));
+        assert_eq!(layer_line_color(0, 0, Some((0, 1)), 0.25), base.map(|channel| channel * 0.25));
+        assert_eq!(layer_line_color(1, 1, Some((0, 1)), 0.25), get_task_color(1).map(|channel| channel * 0.25));
+        assert_eq!(layer_line_color(0, 1, None, 0.25), base);
+    }
+
+    #[test]
+    fn flipped_tool_axis_points_into_the_surface() {
+        let mesh = box_mesh(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 0.0));
+        let tri_mesh = trimesh(&mesh);
+        let mut tasks: Vec<Box<dyn CAMTask>> = vec![
+            Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -2.0), Vector3::z(), &mesh, 0)),
+            Box::new(RasterSurface::new(2.0, 5.0, 20.0, &mesh, 0)),
+        ];
+        let origin = Isometry3::new(Vector3::new(1.0, 2.0, 3.0), Vector3::z() * 0.5);
+        for task in &mut tasks {
+            task.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+            let keypoints = task.get_keypoints();
+            assert!(!keypoints.is_empty());
+            for keypoint in &keypoints {
+                let normal = origin.rotation * keypoint.normal;
+                assert!((tool_axis(&origin, &keypoint.normal, false) - normal).norm() < 1e-6);
+                assert!((tool_axis(&origin, &keypoint.normal, true) + normal).norm() < 1e-6);
+                // Outward normals on every task, so flipped the tool faces into the part
+                let inward = origin.rotation * (Point3::new(0.0, 0.0, -2.5) - keypoint.position);
+                assert!(tool_axis(&origin, &keypoint.normal, true).dot(&inward) > 0.0, "{}", task.name());
+            }
+        }
+    }
+
+    #[test]
+    fn origin_sliders_reach_past_the_part() {
+        let part = box_mesh(Point3::new(-50.0, -20.0, 0.0), Point3::new(50.0, 20.0, 30.0));
+        let range = origin_slider_range(&part);
+        assert_eq!(range, 150.0);
+        let (min, max) = get_bounds(&part).unwrap();
+        assert!(min.coords.iter().chain(max.coords.iter()).all(|coordinate| coordinate.abs() <= range));
+
+        let empty = IndexedMesh { vertices: Vec::new(), faces: Vec::new() };
+        assert_eq!(origin_slider_range(&empty), 1.0);
+    }
+}
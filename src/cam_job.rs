@@ -1,26 +1,215 @@
-use kiss3d::nalgebra::{Point3, Vector3};
-use stl_io::{IndexedMesh, IndexedTriangle, Triangle, Vector, Vertex};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use nalgebra::{Isometry3, Point3, Vector3};
+use stl_io::{IndexedMesh, IndexedTriangle, Vector};
 use crate::errors::CAMError;
-use crate::stl_operations::get_bounds;
-use crate::tool::{Tool, ToolLibrary};
+use crate::stl_operations::{get_bounds, indexed_mesh_to_trimesh, validate_mesh};
+#[cfg(feature = "viewer")]
+use crate::stl_operations::{add_mesh_chunks, scene_node_to_indexed_mesh};
+#[cfg(feature = "viewer")]
+use kiss3d::scene::SceneNode;
+use ncollide3d::shape::TriMesh;
+use crate::tool::{Tool, ToolConfig, ToolGeometry, ToolLibrary};
+use crate::tasks::TaskConfig;
+use crate::simulation::{grid_dims, MaterialSimulation, SimulationResolution, ToolCut, VoxelGrid, VOXEL_BUDGET};
+use crate::tolerances::Tolerances;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct Keypoint {
     pub position: Point3<f32>,
+    /// Unit surface normal of the target where the keypoint touches it, pointing out
+    /// of the material; the tool is oriented along it. Tasks that don't sample the
+    /// surface store the nearest equivalent: the outward radial direction for spiral
+    /// clearing and for clearing rings with no surface inside them, and the tool axis
+    /// for drilling.
     pub normal: Vector3<f32>,
+    /// Position of this keypoint in the job's full toolpath, which is also the
+    /// simulation time step at which the tool reaches it. Assigned by `gather_keypoints`.
+    pub sequence: usize,
+    /// Index of the layer (slice) within its task that produced this keypoint.
+    pub layer: usize,
+    /// Tool cutting at this keypoint: the tool of the task that produced it. Stamped
+    /// by `CAMJOB::task_keypoints`, so it's `None` on a task's raw keypoints.
+    pub tool_id: Option<usize>,
+    /// Set on keypoints the tool reaches clear of the material, such as the entry above
+    /// each drill hole. G-code export retracts to safe Z and rapids to them instead of
+    /// feeding straight there from the previous keypoint.
+    pub rapid: bool,
+    /// Set on finishing passes, which G-code export can feed at
+    /// `GcodeOptions::finishing_feed_rate`.
+    pub finishing: bool,
+}
+
+impl Keypoint {
+    pub fn new(position: Point3<f32>, normal: Vector3<f32>) -> Self {
+        Keypoint {
+            position,
+            normal,
+            sequence: 0,
+            layer: 0,
+            tool_id: None,
+            rapid: false,
+            finishing: false,
+        }
+    }
 }
 
 pub trait CAMTask {
-    fn process(&mut self, mesh: &IndexedMesh) -> Result<(), CAMError>;
+    /// `tri_mesh` is the ray-castable form of `mesh`. It is built once per job build and
+    /// shared by every task, since converting a large mesh is expensive. `tolerances`
+    /// are the job's, or the task's own override. Tasks that work layer by layer call
+    /// `progress` with the fraction of the task done as each layer completes.
+    fn process(
+        &mut self,
+        mesh: &IndexedMesh,
+        tri_mesh: &TriMesh<f32>,
+        tolerances: &Tolerances,
+        progress: &mut dyn FnMut(f32),
+    ) -> Result<(), CAMError>;
     fn get_keypoints(&self) -> Vec<Keypoint>;
+    /// Replaces the generated keypoints, e.g. with a reordered copy.
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>);
     fn get_tool_id(&self) -> usize;
+    fn set_tool_id(&mut self, tool_id: usize);
+    fn config(&self) -> TaskConfig;
+    /// Short task type name, e.g. "CircularClearing".
+    fn name(&self) -> &str;
+    /// One-line summary of the task's parameters for logs and the UI, with lengths
+    /// shown in `units`.
+    fn describe(&self, units: Units) -> String;
+    /// Rough number of keypoints `process` will generate, from the parameters alone.
+    /// Used to weight the task's share of the overall build progress.
+    fn estimated_keypoint_count(&self) -> usize;
+    /// A copy of the task, generated keypoints included.
+    fn clone_box(&self) -> Box<dyn CAMTask>;
+}
+
+/// Length unit the job is reported and exported in. Geometry, keypoints and feed
+/// rates are always stored in millimetres (the STL convention) and only converted
+/// on the way out, so switching units never mixes values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Units {
+    #[default]
+    Millimeters,
+    Inches,
+}
+
+impl Units {
+    /// Converts a length (or a length per minute) from millimetres into these units.
+    pub fn from_mm(self, value: f32) -> f32 {
+        match self {
+            Units::Millimeters => value,
+            Units::Inches => value / 25.4,
+        }
+    }
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Units::Millimeters => "mm",
+            Units::Inches => "in",
+        }
+    }
 }
 
+/// Shape of the raw material the part is cut from.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StockShape {
+    /// Padded axis-aligned box around the target.
+    #[default]
+    Box,
+    /// Round bar stock along the Z axis, made of `segments` flat sides. `radius` and
+    /// `height` are grown when needed so the stock always encloses the target.
+    Cylinder { radius: f32, height: f32, segments: usize },
+}
+
+/// A job's build inputs, from `CAMJOB::build_request`. Tasks and tool models aren't
+/// `Send`, so a build on another thread runs on a copy of the job recreated from
+/// these, and its keypoints are handed back with `CAMJOB::apply_build`.
+pub struct BuildRequest {
+    target_mesh: IndexedMesh,
+    tasks: Vec<TaskConfig>,
+    tools: Vec<ToolConfig>,
+    tolerances: Tolerances,
+    task_tolerances: HashMap<usize, Tolerances>,
+    optimize_keypoint_order: bool,
+}
+
+impl BuildRequest {
+    /// Builds the copy and returns each task's keypoints, in task order.
+    pub fn run(self) -> Result<Vec<Vec<Keypoint>>, CAMError> {
+        let mut job = CAMJOB::new();
+        for tool in self.tools {
+            job.add_tool(Tool::from_config(tool))?;
+        }
+        for task in self.tasks {
+            job.add_task(task.into_task(&self.target_mesh));
+        }
+        job.target_mesh = Some(self.target_mesh);
+        job.tolerances = self.tolerances;
+        job.task_tolerances = self.task_tolerances;
+        job.optimize_keypoint_order = self.optimize_keypoint_order;
+
+        job.build()?;
+        Ok(job.tasks.iter().map(|task| task.get_keypoints()).collect())
+    }
+}
+
+/// Where `CAMJOB::build_with_progress` is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildProgress {
+    pub task_index: usize,
+    pub task_name: String,
+    /// How much of this task is done, from 0 to 1.
+    pub fraction: f32,
+    /// How much of the whole build is done, from 0 to 1, with each task weighted by
+    /// its `estimated_keypoint_count`.
+    pub overall: f32,
+}
+
+/// Default `CAMJOB::stock_padding` on every axis.
+pub const DEFAULT_STOCK_PADDING: f32 = 0.1;
+
+/// How much `CAMJOB::preview_task_fast` divides a task's ray, layer and point counts by.
+pub const PREVIEW_RESOLUTION_DIVISOR: usize = 4;
+
 pub struct CAMJOB {
     tasks: Vec<Box<dyn CAMTask>>,
     pub target_mesh: Option<IndexedMesh>,
     pub stock_mesh: Option<IndexedMesh>,
+    /// Set while the stock is one handed to the job (see `set_stock_mesh`) rather
+    /// than generated, so setting the target or stock options doesn't replace it.
+    provided_stock: bool,
+    pub stock_shape: StockShape,
+    /// Margin generated stock gets around the target on each axis, as a fraction of
+    /// the target's extent along it. See `set_stock_padding`.
+    pub stock_padding: Vector3<f32>,
     pub tool_library: ToolLibrary,
+    /// Where the tool is parked for tool changes. The exporter never parks below safe Z.
+    pub tool_change_position: Point3<f32>,
+    /// Speed of non-cutting moves between tasks, in units per minute.
+    pub rapid_rate: f32,
+    pub units: Units,
+    /// Whether `build` reorders each task's keypoints with `optimize_order`. Off by
+    /// default so tasks keep their natural (e.g. spiral) order.
+    pub optimize_keypoint_order: bool,
+    /// Precision settings for every task without its own (see `set_task_tolerances`)
+    /// and for collision checks.
+    pub tolerances: Tolerances,
+    task_tolerances: HashMap<usize, Tolerances>,
+    /// Voxel resolution of the material removal simulation. See `set_simulation_resolution`.
+    pub simulation_resolution: SimulationResolution,
+    part_instances: Vec<Isometry3<f32>>,
+    /// Per-task datums, by task index. See `set_task_origin_offset`.
+    task_offsets: HashMap<usize, Isometry3<f32>>,
+    simulation: Option<MaterialSimulation>,
+    built: bool,
+}
+
+impl Default for CAMJOB {
+    fn default() -> Self {
+        CAMJOB::new()
+    }
 }
 
 impl CAMJOB {
@@ -29,27 +218,299 @@ impl CAMJOB {
             tasks: Vec::new(),
             target_mesh: None,
             stock_mesh: None,
+            provided_stock: false,
+            stock_shape: StockShape::default(),
+            stock_padding: Vector3::repeat(DEFAULT_STOCK_PADDING),
             tool_library: ToolLibrary::new(),
+            tool_change_position: Point3::origin(),
+            rapid_rate: 5000.0,
+            units: Units::default(),
+            optimize_keypoint_order: false,
+            tolerances: Tolerances::default(),
+            task_tolerances: HashMap::new(),
+            simulation_resolution: SimulationResolution::default(),
+            part_instances: Vec::new(),
+            task_offsets: HashMap::new(),
+            simulation: None,
+            built: false,
         }
     }
 
+    /// Uses `tool_library` as the job's tools. Tools need no window; the viewer gives
+    /// them render models afterwards with `ToolLibrary::attach_visuals`.
+    pub fn with_tool_library(mut self, tool_library: ToolLibrary) -> Self {
+        self.tool_library = tool_library;
+        self
+    }
+
+    pub fn set_rapid_rate(&mut self, rapid_rate: f32) {
+        self.rapid_rate = rapid_rate;
+    }
+
+    /// Changes the units used for readouts and export. Everything generated so far
+    /// stays valid because it is held in millimetres.
+    pub fn set_units(&mut self, units: Units) {
+        self.units = units;
+    }
+
+    /// Changes the job-wide tolerances. Takes effect at the next `build`.
+    pub fn set_tolerances(&mut self, tolerances: Tolerances) {
+        self.tolerances = tolerances;
+        self.built = false;
+    }
+
+    /// Overrides the tolerances task `task_index` is processed with.
+    pub fn set_task_tolerances(&mut self, task_index: usize, tolerances: Tolerances) -> Result<(), CAMError> {
+        if task_index >= self.tasks.len() {
+            return Err(CAMError::ProcessingError(format!("No task at index {}", task_index)));
+        }
+        self.task_tolerances.insert(task_index, tolerances);
+        self.built = false;
+        Ok(())
+    }
+
+    /// The tolerances task `task_index` is processed with.
+    pub fn tolerances_for(&self, task_index: usize) -> &Tolerances {
+        self.task_tolerances.get(&task_index).unwrap_or(&self.tolerances)
+    }
+
+    /// Changes how finely the simulation divides the stock. The simulation restarts
+    /// from uncut stock at the next update.
+    pub fn set_simulation_resolution(&mut self, resolution: SimulationResolution) {
+        self.simulation_resolution = resolution;
+        self.simulation = None;
+    }
+
+    pub fn set_tool_change_position(&mut self, position: Point3<f32>) {
+        self.tool_change_position = position;
+    }
+
+    /// Sets the part to machine. Meshes with zero-area faces are rejected, since ray
+    /// casts against them return unusable normals; run `repair_mesh` on them first.
     pub fn set_mesh(&mut self, mesh: IndexedMesh) -> Result<(), CAMError> {
+        let report = validate_mesh(&mesh)?;
+        if report.degenerate_faces > 0 {
+            return Err(CAMError::InvalidMesh(format!(
+                "Mesh has {} zero-area faces; repair it with repair_mesh first",
+                report.degenerate_faces
+            )));
+        }
+        for warning in report.warnings() {
+            eprintln!("Warning: {}", warning);
+        }
         self.target_mesh = Some(mesh);
-        self.create_stock_mesh()
+        self.built = false;
+        self.refresh_stock()
+    }
+
+    /// Uses `mesh` as the stock instead of generating one, e.g. the material left by a
+    /// previous operation. It stays until `create_stock_mesh` is called. Stock that
+    /// doesn't enclose the target only gets a warning, since a second operation may
+    /// deliberately cover part of it.
+    pub fn set_stock_mesh(&mut self, mesh: IndexedMesh) -> Result<(), CAMError> {
+        validate_mesh(&mesh)?;
+        self.stock_mesh = Some(mesh);
+        self.provided_stock = true;
+        self.simulation = None;
+        self.warn_if_stock_misses_target()
+    }
+
+    /// Changes the stock margin and regenerates the stock. Each component is a
+    /// fraction of the target's extent along that axis, added on both sides; zero
+    /// gives stock matching the target's bounds. Cylinder stock pads its radius by the
+    /// larger of X and Y.
+    pub fn set_stock_padding(&mut self, padding: Vector3<f32>) -> Result<(), CAMError> {
+        self.stock_padding = padding;
+        if self.target_mesh.is_some() {
+            self.refresh_stock()?;
+        }
+        Ok(())
+    }
+
+    /// Changes the stock shape, regenerating the stock if a target mesh is already set.
+    pub fn set_stock_shape(&mut self, shape: StockShape) -> Result<(), CAMError> {
+        self.stock_shape = shape;
+        if self.target_mesh.is_some() {
+            self.refresh_stock()?;
+        }
+        Ok(())
     }
 
+    /// Generates stock around the target from the stock shape and padding, replacing
+    /// any stock set with `set_stock_mesh`.
     pub fn create_stock_mesh(&mut self) -> Result<(), CAMError> {
         if let Some(target_mesh) = &self.target_mesh {
-            let stock_mesh = generate_stock_mesh(target_mesh)?;
+            let stock_mesh = if self.part_instances.is_empty() {
+                generate_stock_mesh(target_mesh, self.stock_shape, &self.stock_padding)?
+            } else {
+                let instances = instanced_mesh(target_mesh, &self.part_instances);
+                generate_stock_mesh(&instances, self.stock_shape, &self.stock_padding)?
+            };
             self.stock_mesh = Some(stock_mesh);
+            self.provided_stock = false;
+            self.simulation = None;
             Ok(())
         } else {
             Err(CAMError::MeshNotSet)
         }
     }
 
+    /// Regenerates the stock after the target or stock options change, unless it was
+    /// provided, in which case it is only checked against the new target.
+    fn refresh_stock(&mut self) -> Result<(), CAMError> {
+        if self.provided_stock {
+            self.warn_if_stock_misses_target()
+        } else {
+            self.create_stock_mesh()
+        }
+    }
+
+    /// Warns when the stock's bounding box doesn't contain the target's, including
+    /// every part instance. Does nothing until both are set.
+    fn warn_if_stock_misses_target(&self) -> Result<(), CAMError> {
+        let (target_mesh, stock_mesh) = match (&self.target_mesh, &self.stock_mesh) {
+            (Some(target_mesh), Some(stock_mesh)) => (target_mesh, stock_mesh),
+            _ => return Ok(()),
+        };
+        let (target_min, target_max) = if self.part_instances.is_empty() {
+            get_bounds(target_mesh)?
+        } else {
+            get_bounds(&instanced_mesh(target_mesh, &self.part_instances))?
+        };
+        let (stock_min, stock_max) = get_bounds(stock_mesh)?;
+        if target_min != target_min.sup(&stock_min) || target_max != target_max.inf(&stock_max) {
+            eprintln!(
+                "Warning: stock bounds {:?} to {:?} don't enclose the target bounds {:?} to {:?}",
+                stock_min.coords.as_slice(),
+                stock_max.coords.as_slice(),
+                target_min.coords.as_slice(),
+                target_max.coords.as_slice()
+            );
+        }
+        Ok(())
+    }
+
+    /// Replaces the stock with the material remaining in a simulation mesh, so a
+    /// follow-up job (e.g. finishing after roughing) only targets leftover material.
+    /// See `rebuild_stock_at_time_step` to do the same without a viewer.
+    #[cfg(feature = "viewer")]
+    pub fn rebuild_stock_from_simulation(&mut self, simulation_mesh: &SceneNode) -> Result<(), CAMError> {
+        let stock_mesh = scene_node_to_indexed_mesh(simulation_mesh)?;
+        self.set_stock_mesh(stock_mesh)
+    }
+
+    /// Runs the simulation to `time_step` and makes the surface of the remaining
+    /// material the stock, as `rebuild_stock_from_simulation` does with the mesh
+    /// `create_simulation_mesh` draws.
+    pub fn rebuild_stock_at_time_step(&mut self, time_step: usize) -> Result<(), CAMError> {
+        self.update_to_time_step(time_step)?;
+        let surface = self.simulation.as_ref().unwrap().grid().surface_mesh();
+        self.set_stock_mesh(surface)
+    }
+
+    /// Registers a copy of the part at `transform`, for nesting several parts in one
+    /// stock. Tasks are computed once on the target mesh and their keypoints replayed
+    /// for every instance. With no instances registered the part is machined once as
+    /// is; the first instance replaces that placement rather than adding to it, so
+    /// register `Isometry3::identity()` too to keep a copy where the part is.
+    pub fn add_part_instance(&mut self, transform: Isometry3<f32>) -> Result<(), CAMError> {
+        self.part_instances.push(transform);
+        if self.target_mesh.is_some() {
+            self.refresh_stock()?;
+        }
+        Ok(())
+    }
+
+    pub fn get_part_instances(&self) -> &[Isometry3<f32>] {
+        &self.part_instances
+    }
+
+    /// Moves task `task_index`'s keypoints by `offset`, so the task references its own
+    /// datum (e.g. a bore center) rather than the part's. Applied before part instances,
+    /// and the viewer's job origin applies on top of both.
+    pub fn set_task_origin_offset(&mut self, task_index: usize, offset: Isometry3<f32>) -> Result<(), CAMError> {
+        if task_index >= self.tasks.len() {
+            return Err(CAMError::ProcessingError(format!("No task at index {}", task_index)));
+        }
+        self.task_offsets.insert(task_index, offset);
+        Ok(())
+    }
+
+    pub fn task_origin_offset(&self, task_index: usize) -> Option<&Isometry3<f32>> {
+        self.task_offsets.get(&task_index)
+    }
+
+    /// Task `task_index`'s keypoints moved by its origin offset and repeated for each
+    /// part instance, instance by instance. Empty if there's no such task.
+    pub fn task_keypoints(&self, task_index: usize) -> Vec<Keypoint> {
+        match self.tasks.get(task_index) {
+            Some(task) => self.place_keypoints(task_index, task.get_keypoints()),
+            None => Vec::new(),
+        }
+    }
+
+    fn place_keypoints(&self, task_index: usize, mut keypoints: Vec<Keypoint>) -> Vec<Keypoint> {
+        let tool_id = self.tasks.get(task_index).map(|task| task.get_tool_id());
+        let offset = self.task_offsets.get(&task_index);
+        for keypoint in &mut keypoints {
+            keypoint.tool_id = tool_id;
+            if let Some(offset) = offset {
+                keypoint.position = offset * keypoint.position;
+                keypoint.normal = offset * keypoint.normal;
+            }
+        }
+        if self.part_instances.is_empty() {
+            return keypoints;
+        }
+
+        self.part_instances
+            .iter()
+            .flat_map(|instance| {
+                keypoints.iter().map(move |keypoint| Keypoint {
+                    position: instance * keypoint.position,
+                    normal: instance * keypoint.normal,
+                    ..keypoint.clone()
+                })
+            })
+            .collect()
+    }
+
     pub fn add_task(&mut self, task: Box<dyn CAMTask>) {
         self.tasks.push(task);
+        self.built = false;
+    }
+
+    /// Copies of every task with their tool switched to `tool_id`, e.g. to add back
+    /// to a job and compare the same strategy with another cutter. The copies keep
+    /// the originals' keypoints until the job is built again.
+    pub fn clone_tasks_with_new_tool(&self, tool_id: usize) -> Vec<Box<dyn CAMTask>> {
+        self.tasks
+            .iter()
+            .map(|task| {
+                let mut clone = task.clone_box();
+                clone.set_tool_id(tool_id);
+                clone
+            })
+            .collect()
+    }
+
+    /// Adds `task` with its tool chosen from the library: the largest tool whose
+    /// radius still fits the smallest internal radius the task has to machine. Any
+    /// tool id the task was constructed with is replaced. Returns the chosen id.
+    pub fn add_task_for_min_radius(&mut self, mut task: Box<dyn CAMTask>, min_radius: f32) -> Result<usize, CAMError> {
+        let tool_id = self
+            .tool_library
+            .select_for_min_radius(min_radius)
+            .map(|tool| tool.id)
+            .ok_or_else(|| CAMError::ProcessingError(format!("No tool fits a minimum internal radius of {}", min_radius)))?;
+        task.set_tool_id(tool_id);
+        self.add_task(task);
+        Ok(tool_id)
+    }
+
+    /// Whether `build` has run since the mesh was set or a task added.
+    pub fn is_built(&self) -> bool {
+        self.built
     }
 
     pub fn get_next_task(&self) -> Option<&dyn CAMTask> {
@@ -60,10 +521,67 @@ impl CAMJOB {
         !self.tasks.is_empty()
     }
 
+    /// Checks that every task's tool is in the tool library.
+    pub fn validate_tools(&self) -> Result<(), CAMError> {
+        for task in &self.tasks {
+            let tool_id = task.get_tool_id();
+            if self.get_tool(tool_id).is_none() {
+                return Err(CAMError::ToolNotFound(tool_id));
+            }
+        }
+        Ok(())
+    }
+
     pub fn build(&mut self) -> Result<(), CAMError> {
+        self.build_with_progress(|_| {})
+    }
+
+    /// `build`, calling `on_progress` as each task starts, finishes, and in between
+    /// as it completes layers, e.g. to drive a progress bar.
+    pub fn build_with_progress(&mut self, mut on_progress: impl FnMut(BuildProgress)) -> Result<(), CAMError> {
+        self.validate_tools()?;
         if let Some(mesh) = &self.target_mesh {
-            for task in &mut self.tasks {
-                task.process(mesh)?;
+            let tri_mesh = indexed_mesh_to_trimesh(mesh);
+            // Every task weighs at least one keypoint, so an empty estimate still advances
+            let weights: Vec<f32> = self.tasks.iter().map(|task| task.estimated_keypoint_count().max(1) as f32).collect();
+            let total_weight: f32 = weights.iter().sum();
+            let mut done_weight = 0.0;
+            for (task_index, task) in self.tasks.iter_mut().enumerate() {
+                let tolerances = self.task_tolerances.get(&task_index).unwrap_or(&self.tolerances);
+                let task_name = task.name().to_string();
+                let weight = weights[task_index];
+                let mut report = |fraction: f32| {
+                    let overall = (done_weight + weight * fraction) / total_weight;
+                    on_progress(BuildProgress { task_index, task_name: task_name.clone(), fraction, overall })
+                };
+                report(0.0);
+                task.process(mesh, &tri_mesh, tolerances, &mut report)?;
+                report(1.0);
+                done_weight += weight;
+                if tolerances.min_keypoint_spacing > 0.0 {
+                    let keypoints = task.get_keypoints();
+                    let generated = keypoints.len();
+                    let kept = thin_keypoints(keypoints, tolerances.min_keypoint_spacing);
+                    if kept.len() < generated {
+                        println!("{}: dropped {} keypoints closer than {}", task.name(), generated - kept.len(), tolerances.min_keypoint_spacing);
+                    }
+                    task.set_keypoints(kept);
+                }
+            }
+            self.simulation = None;
+            self.built = true;
+            if self.optimize_keypoint_order {
+                let (before, after) = self.optimize_order();
+                println!("Optimized keypoint order: travel {:.3} -> {:.3}", before, after);
+            }
+            let out_of_stock = self.check_stock_containment();
+            if let Some(first) = out_of_stock.first() {
+                eprintln!(
+                    "Warning: {} keypoints lie outside the stock, first at {:?} (task {}); check the stock and task setup",
+                    out_of_stock.len(),
+                    first.position,
+                    first.task_index
+                );
             }
             Ok(())
         } else {
@@ -71,8 +589,217 @@ impl CAMJOB {
         }
     }
 
+    /// Everything `build` reads, as plain data that can be moved to another thread. See
+    /// `BuildRequest`.
+    pub fn build_request(&self) -> Result<BuildRequest, CAMError> {
+        let target_mesh = self.target_mesh.clone().ok_or(CAMError::MeshNotSet)?;
+        Ok(BuildRequest {
+            target_mesh,
+            tasks: self.tasks.iter().map(|task| task.config()).collect(),
+            tools: self.tool_library.iter().map(Tool::config).collect(),
+            tolerances: self.tolerances,
+            task_tolerances: self.task_tolerances.clone(),
+            optimize_keypoint_order: self.optimize_keypoint_order,
+        })
+    }
+
+    /// Takes the per-task keypoints from `BuildRequest::run` as if `build` had
+    /// produced them. Fails if the tasks changed in the meantime.
+    pub fn apply_build(&mut self, task_keypoints: Vec<Vec<Keypoint>>) -> Result<(), CAMError> {
+        if task_keypoints.len() != self.tasks.len() {
+            return Err(CAMError::ProcessingError(format!(
+                "Build produced keypoints for {} tasks but the job has {}",
+                task_keypoints.len(),
+                self.tasks.len()
+            )));
+        }
+        for (task, keypoints) in self.tasks.iter_mut().zip(task_keypoints) {
+            task.set_keypoints(keypoints);
+        }
+        self.simulation = None;
+        self.built = true;
+        Ok(())
+    }
+
+    /// Reorders the keypoints within each task with a nearest-neighbour walk from the
+    /// task's first keypoint. Task order is left alone. Returns the job's total travel
+    /// distance between consecutive keypoints before and after.
+    pub fn optimize_order(&mut self) -> (f32, f32) {
+        let before = travel_distance(&self.gather_keypoints());
+        for task in &mut self.tasks {
+            let keypoints = nearest_neighbor_order(task.get_keypoints());
+            task.set_keypoints(keypoints);
+        }
+        let after = travel_distance(&self.gather_keypoints());
+        (before, after)
+    }
+
+    /// Keypoints of the first task at reduced resolution; see `preview_task_fast`.
+    pub fn preview_first_task_fast(&self) -> Result<Vec<Keypoint>, CAMError> {
+        self.preview_task_fast(0)
+    }
+
+    /// Processes a copy of one task with its resolution divided by
+    /// `PREVIEW_RESOLUTION_DIVISOR` and returns its keypoints, for a quick look before a
+    /// full `build`. The job's own tasks are left untouched.
+    pub fn preview_task_fast(&self, task_index: usize) -> Result<Vec<Keypoint>, CAMError> {
+        let mesh = self.target_mesh.as_ref().ok_or(CAMError::MeshNotSet)?;
+        let task = self
+            .tasks
+            .get(task_index)
+            .ok_or_else(|| CAMError::ProcessingError(format!("No task at index {}", task_index)))?;
+
+        let mut preview = task.config().with_reduced_resolution(PREVIEW_RESOLUTION_DIVISOR).into_task(mesh);
+        preview.process(mesh, &indexed_mesh_to_trimesh(mesh), self.tolerances_for(task_index), &mut |_| {})?;
+        Ok(self.place_keypoints(task_index, preview.get_keypoints()))
+    }
+
     pub fn gather_keypoints(&self) -> Vec<Keypoint> {
-        self.tasks.iter().flat_map(|task| task.get_keypoints()).collect()
+        self.gather_keypoints_with_task()
+            .into_iter()
+            .map(|(_, keypoint)| keypoint)
+            .collect()
+    }
+
+    /// The tools loaded over the job's toolpath, in order: one entry per tool change,
+    /// so a tool used again after another one appears again. Tasks without keypoints
+    /// don't load their tool, so build the job first.
+    pub fn tool_change_sequence(&self) -> Vec<usize> {
+        let mut sequence: Vec<usize> = Vec::new();
+        for task in self.tasks.iter().filter(|task| !task.get_keypoints().is_empty()) {
+            let tool_id = task.get_tool_id();
+            if sequence.last() != Some(&tool_id) {
+                sequence.push(tool_id);
+            }
+        }
+        sequence
+    }
+
+    /// Like `gather_keypoints`, with each keypoint paired with the index of its task.
+    pub(crate) fn gather_keypoints_with_task(&self) -> Vec<(usize, Keypoint)> {
+        (0..self.tasks.len())
+            .flat_map(|task_index| {
+                self.task_keypoints(task_index).into_iter().map(move |keypoint| (task_index, keypoint))
+            })
+            .enumerate()
+            .map(|(sequence, (task_index, mut keypoint))| {
+                keypoint.sequence = sequence;
+                (task_index, keypoint)
+            })
+            .collect()
+    }
+
+    /// Number of distinct layers in the job. Layers are numbered across the whole job in
+    /// the order the toolpath first reaches them, so each task's layers are distinct.
+    pub fn layer_count(&self) -> usize {
+        self.layer_keys().len()
+    }
+
+    /// The keypoints of job layer `index` (see `layer_count`), in toolpath order.
+    pub fn keypoints_in_layer(&self, index: usize) -> Vec<Keypoint> {
+        let (layer_task, layer) = match self.layer_keys().get(index) {
+            Some(&key) => key,
+            None => return Vec::new(),
+        };
+
+        self.gather_keypoints_with_task()
+            .into_iter()
+            .filter(|(task_index, keypoint)| *task_index == layer_task && keypoint.layer == layer)
+            .map(|(_, keypoint)| keypoint)
+            .collect()
+    }
+
+    /// (task index, task layer) of every job layer, in order of first appearance.
+    fn layer_keys(&self) -> Vec<(usize, usize)> {
+        let mut seen = HashSet::new();
+        self.gather_keypoints_with_task()
+            .into_iter()
+            .map(|(task_index, keypoint)| (task_index, keypoint.layer))
+            .filter(|key| seen.insert(*key))
+            .collect()
+    }
+
+    /// The job layer (as numbered by `layer_count`) of the keypoint at `keypoint_index`
+    /// in `gather_keypoints`.
+    pub fn layer_index_at(&self, keypoint_index: usize) -> Option<usize> {
+        let (task_index, keypoint) = self.gather_keypoints_with_task().into_iter().nth(keypoint_index)?;
+        self.layer_keys().iter().position(|&key| key == (task_index, keypoint.layer))
+    }
+
+    /// The keypoint the tool reaches at `time_step`, or `None` past the end of the toolpath.
+    pub fn keypoint_at_time_step(&self, time_step: usize) -> Option<Keypoint> {
+        (0..self.tasks.len())
+            .flat_map(|task_index| self.task_keypoints(task_index))
+            .nth(time_step)
+            .map(|mut keypoint| {
+                keypoint.sequence = time_step;
+                keypoint
+            })
+    }
+
+    /// Offsets the contour traced by task `task_index` by `offset` along each keypoint's
+    /// in-plane (XY) normal: positive values move away from the surface, negative values
+    /// into it. Where offsetting a concave region makes the curve cross itself, the
+    /// offset points end up closer than `offset` to the original contour; those are dropped.
+    /// Each point is only checked against the contour of its own layer.
+    pub fn offset_contour(&self, task_index: usize, offset: f32) -> Vec<Keypoint> {
+        let keypoints = self.task_keypoints(task_index);
+        let clearance = offset.abs() - 1e-3;
+        let mut layers: HashMap<usize, Vec<Point3<f32>>> = HashMap::new();
+        for keypoint in &keypoints {
+            layers.entry(keypoint.layer).or_default().push(keypoint.position);
+        }
+
+        keypoints
+            .iter()
+            .filter_map(|keypoint| {
+                let in_plane = Vector3::new(keypoint.normal.x, keypoint.normal.y, 0.0).try_normalize(1e-6)?;
+                let position = keypoint.position + in_plane * offset;
+                let gouges = layers[&keypoint.layer].iter().any(|other| (other - position).norm() < clearance);
+                if gouges {
+                    None
+                } else {
+                    Some(Keypoint { position, ..keypoint.clone() })
+                }
+            })
+            .collect()
+    }
+
+    /// Rough time to run the whole job. See `estimate_duration_per_task`.
+    pub fn estimate_duration(&self) -> Duration {
+        self.estimate_duration_per_task().iter().map(|(_, duration)| *duration).sum()
+    }
+
+    /// Rough time spent on each task, keyed by the task's tool id. A task's time is the
+    /// travel between its consecutive keypoints at the tool's feed rate, plus the rapid
+    /// from the previous task's last keypoint to its first one at `rapid_rate`.
+    pub fn estimate_duration_per_task(&self) -> Vec<(usize, Duration)> {
+        let mut previous_end: Option<Point3<f32>> = None;
+
+        self.tasks
+            .iter()
+            .enumerate()
+            .map(|(task_index, task)| {
+                let tool_id = task.get_tool_id();
+                let keypoints = self.task_keypoints(task_index);
+                let feed_rate = self.get_tool(tool_id).map_or(0.0, |tool| tool.feed_rate);
+
+                let cut_distance: f32 = keypoints
+                    .windows(2)
+                    .map(|pair| (pair[1].position - pair[0].position).norm())
+                    .sum();
+                let rapid_distance = match (previous_end, keypoints.first()) {
+                    (Some(end), Some(first)) => (first.position - end).norm(),
+                    _ => 0.0,
+                };
+                if let Some(last) = keypoints.last() {
+                    previous_end = Some(last.position);
+                }
+
+                let duration = travel_time(cut_distance, feed_rate) + travel_time(rapid_distance, self.rapid_rate);
+                (tool_id, duration)
+            })
+            .collect()
     }
 
     pub fn get_stock_mesh(&self) -> Option<&IndexedMesh> {
@@ -83,8 +810,8 @@ impl CAMJOB {
         &self.tasks
     }
 
-    pub fn add_tool(&mut self, tool: Tool) {
-        self.tool_library.add_tool(tool);
+    pub fn add_tool(&mut self, tool: Tool) -> Result<(), CAMError> {
+        self.tool_library.add_tool(tool)
     }
 
     pub fn get_tool(&self, id: usize) -> Option<&Tool> {
@@ -95,45 +822,188 @@ impl CAMJOB {
         self.tool_library.get_tool_mut(id)
     }
 
-    pub fn update_to_time_step(&mut self, time_step: usize) {
-        // Implement the logic to update the CAM job to a specific time step
-        println!("Updating CAM job to time step: {}", time_step);
+    /// Advances (or rewinds) the material removal simulation so that every keypoint up
+    /// to and including `time_step` has cut the stock. Only the keypoints between the
+    /// current step and the target are applied or undone.
+    pub fn update_to_time_step(&mut self, time_step: usize) -> Result<(), CAMError> {
+        let cuts = self.gather_cuts();
+        let target_step = (time_step + 1).min(cuts.len());
+
+        if self.simulation.is_none() {
+            let stock = self.stock_mesh.as_ref().ok_or(CAMError::MeshNotSet)?;
+            let (min, max) = get_bounds(stock)?;
+            let voxel_size = self.simulation_resolution.voxel_size(&min, &max);
+            let voxels = grid_dims(&min, &max, voxel_size)
+                .iter()
+                .try_fold(1usize, |total, &dim| total.checked_mul(dim))
+                .unwrap_or(usize::MAX);
+            if voxels > VOXEL_BUDGET {
+                eprintln!(
+                    "Warning: simulation grid has {} voxels (budget {}); consider a coarser simulation resolution",
+                    voxels, VOXEL_BUDGET
+                );
+            }
+            self.simulation = Some(MaterialSimulation::new(VoxelGrid::from_mesh(stock, voxel_size)?));
+        }
+        let simulation = self.simulation.as_mut().unwrap();
+
+        while simulation.current_step() > target_step {
+            simulation.undo_cut();
+        }
+        while simulation.current_step() < target_step {
+            simulation.apply_cut(&cuts[simulation.current_step()]);
+        }
+        Ok(())
+    }
+
+    pub fn get_simulation(&self) -> Option<&MaterialSimulation> {
+        self.simulation.as_ref()
     }
 
+    /// The solid cleared at every keypoint in toolpath order (see `Tool::cut`). A task
+    /// whose tool isn't in the library clears nothing but still takes its steps.
+    fn gather_cuts(&self) -> Vec<ToolCut> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .flat_map(|(task_index, task)| {
+                let tool = self.get_tool(task.get_tool_id());
+                self.task_keypoints(task_index).into_iter().map(move |keypoint| match tool {
+                    Some(tool) => tool.cut(&keypoint.position, &keypoint.normal),
+                    None => ToolCut { tip: keypoint.position, axis: keypoint.normal, radius: 0.0, length: 0.0, geometry: ToolGeometry::FlatEnd },
+                })
+            })
+            .collect()
+    }
+
+    /// Where the tool is at `time_step`, or `None` once the toolpath has finished.
     pub fn get_tool_position_at_time_step(&self, time_step: usize) -> Option<Point3<f32>> {
-        // Implement the logic to get the tool position at a specific time step
-        println!("Getting tool position at time step: {}", time_step);
-        Some(Point3::new(0.0, 0.0, 0.0)) // Placeholder return value
+        self.keypoint_at_time_step(time_step).map(|keypoint| keypoint.position)
+    }
+
+    /// Runs the simulation to `time_step` and adds the surface of the remaining
+    /// material to `parent`.
+    #[cfg(feature = "viewer")]
+    pub fn create_simulation_mesh(&mut self, parent: &mut SceneNode, time_step: usize) -> Result<SceneNode, CAMError> {
+        self.update_to_time_step(time_step)?;
+        let surface = self.simulation.as_ref().unwrap().grid().surface_mesh();
+        let mut node = add_mesh_chunks(parent, &surface)?;
+        node.set_color(0.3, 0.6, 0.9);
+        Ok(node)
     }
 
-    pub fn create_simulation_mesh(&self, time_step: usize) -> kiss3d::scene::SceneNode {
-        // Implement the logic to create a new simulation mesh
-        println!("Creating simulation mesh for time step: {}", time_step);
-        // Placeholder: You'll need to actually create and return a SceneNode here
-        unimplemented!("create_simulation_mesh not yet implemented")
+    /// Replaces `mesh`, previously made by `create_simulation_mesh`, with the
+    /// remaining material at `time_step`.
+    #[cfg(feature = "viewer")]
+    pub fn update_simulation_mesh(&mut self, mesh: &mut SceneNode, parent: &mut SceneNode, time_step: usize) -> Result<(), CAMError> {
+        let visible = mesh.is_visible();
+        let mut updated = self.create_simulation_mesh(parent, time_step)?;
+        updated.set_visible(visible);
+        mesh.unlink();
+        *mesh = updated;
+        Ok(())
     }
+}
+
+/// Copies of `mesh` placed at each of `instances`, merged into one mesh.
+fn instanced_mesh(mesh: &IndexedMesh, instances: &[Isometry3<f32>]) -> IndexedMesh {
+    let mut vertices = Vec::with_capacity(mesh.vertices.len() * instances.len());
+    let mut faces = Vec::with_capacity(mesh.faces.len() * instances.len());
+
+    for instance in instances {
+        let base = vertices.len();
+        vertices.extend(mesh.vertices.iter().map(|v| {
+            let p = instance * Point3::new(v[0], v[1], v[2]);
+            Vector::new([p.x, p.y, p.z])
+        }));
+        faces.extend(mesh.faces.iter().map(|face| {
+            let n = instance * Vector3::new(face.normal[0], face.normal[1], face.normal[2]);
+            IndexedTriangle {
+                normal: Vector::new([n.x, n.y, n.z]),
+                vertices: [base + face.vertices[0], base + face.vertices[1], base + face.vertices[2]],
+            }
+        }));
+    }
+
+    IndexedMesh { vertices, faces }
+}
+
+/// Summed straight-line distance between consecutive keypoints.
+fn travel_distance(keypoints: &[Keypoint]) -> f32 {
+    keypoints
+        .windows(2)
+        .map(|pair| (pair[1].position - pair[0].position).norm())
+        .sum()
+}
+
+/// Greedy tour: starting from the first keypoint, repeatedly visits the closest one
+/// not yet visited.
+fn nearest_neighbor_order(mut remaining: Vec<Keypoint>) -> Vec<Keypoint> {
+    let mut ordered = Vec::with_capacity(remaining.len());
+    if remaining.is_empty() {
+        return ordered;
+    }
+
+    let mut current = remaining.remove(0);
+    while !remaining.is_empty() {
+        let (closest, _) = remaining
+            .iter()
+            .enumerate()
+            .map(|(index, keypoint)| (index, (keypoint.position - current.position).norm_squared()))
+            .fold((0, f32::INFINITY), |best, candidate| if candidate.1 < best.1 { candidate } else { best });
+        let next = remaining.swap_remove(closest);
+        ordered.push(std::mem::replace(&mut current, next));
+    }
+    ordered.push(current);
+    ordered
+}
+
+/// Drops every keypoint closer than `min_spacing` to the last keypoint kept on its
+/// layer, so repeated passes over the same contour don't turn into sub-tolerance moves.
+/// The first keypoint of each layer is always kept.
+fn thin_keypoints(keypoints: Vec<Keypoint>, min_spacing: f32) -> Vec<Keypoint> {
+    let mut last_kept: HashMap<usize, Point3<f32>> = HashMap::new();
+    keypoints
+        .into_iter()
+        .filter(|keypoint| {
+            let keep = last_kept
+                .get(&keypoint.layer)
+                .is_none_or(|last| (keypoint.position - last).norm() >= min_spacing);
+            if keep {
+                last_kept.insert(keypoint.layer, keypoint.position);
+            }
+            keep
+        })
+        .collect()
+}
 
-    pub fn update_simulation_mesh(&self, mesh: &mut kiss3d::scene::SceneNode, time_step: usize) {
-        // Implement the logic to update an existing simulation mesh
-        println!("Updating simulation mesh for time step: {}", time_step);
+/// Time to cover `distance` at `rate` units per minute. Zero for a non-positive rate.
+fn travel_time(distance: f32, rate: f32) -> Duration {
+    if rate > 0.0 && distance.is_finite() {
+        Duration::from_secs_f32(distance / rate * 60.0)
+    } else {
+        Duration::ZERO
     }
 }
 
-fn generate_stock_mesh(target_mesh: &IndexedMesh) -> Result<IndexedMesh, CAMError> {
+/// Stock around `target_mesh` with a margin of `padding` times its extent, per axis.
+fn generate_stock_mesh(target_mesh: &IndexedMesh, shape: StockShape, padding: &Vector3<f32>) -> Result<IndexedMesh, CAMError> {
+    match shape {
+        StockShape::Box => generate_box_stock_mesh(target_mesh, padding),
+        StockShape::Cylinder { radius, height, segments } => {
+            generate_cylinder_stock_mesh(target_mesh, radius, height, segments, padding)
+        }
+    }
+}
+
+fn generate_box_stock_mesh(target_mesh: &IndexedMesh, padding: &Vector3<f32>) -> Result<IndexedMesh, CAMError> {
     let (min, max) = get_bounds(target_mesh)?;
     
-    // Add some padding to ensure the stock fully encapsulates the target
-    let padding = 0.1; // 10% padding
-    let min = Point3::new(
-        min.x - (max.x - min.x) * padding,
-        min.y - (max.y - min.y) * padding,
-        min.z - (max.z - min.z) * padding
-    );
-    let max = Point3::new(
-        max.x + (max.x - min.x) * padding,
-        max.y + (max.y - min.y) * padding,
-        max.z + (max.z - min.z) * padding
-    );
+    // Add some padding to ensure the stock fully encapsulates the target.
+    // The extents are taken before either corner moves so both sides get the same margin.
+    let margin = (max - min).component_mul(padding);
+    let min = min - margin;
+    let max = max + margin;
 
     // Define the vertices of the cube
     let vertices: Vec<Vector<f32>> = vec![
@@ -147,27 +1017,494 @@ fn generate_stock_mesh(target_mesh: &IndexedMesh) -> Result<IndexedMesh, CAMErro
         Vector::new([min.x, max.y, max.z]),  // 7
     ];
 
-    // Define the faces using IndexedTriangle with normals
+    // Define the faces using IndexedTriangle with normals, wound counter-clockwise
+    // seen from outside
     let faces: Vec<IndexedTriangle> = vec![
         // Front face (normal: 0, 0, -1)
-        IndexedTriangle { normal: Vector::new([0.0, 0.0, -1.0]), vertices: [0, 1, 2] },
-        IndexedTriangle { normal: Vector::new([0.0, 0.0, -1.0]), vertices: [0, 2, 3] },
+        IndexedTriangle { normal: Vector::new([0.0, 0.0, -1.0]), vertices: [0, 2, 1] },
+        IndexedTriangle { normal: Vector::new([0.0, 0.0, -1.0]), vertices: [0, 3, 2] },
         // Right face (normal: 1, 0, 0)
-        IndexedTriangle { normal: Vector::new([1.0, 0.0, 0.0]), vertices: [1, 5, 6] },
-        IndexedTriangle { normal: Vector::new([1.0, 0.0, 0.0]), vertices: [1, 6, 2] },
+        IndexedTriangle { normal: Vector::new([1.0, 0.0, 0.0]), vertices: [1, 6, 5] },
+        IndexedTriangle { normal: Vector::new([1.0, 0.0, 0.0]), vertices: [1, 2, 6] },
         // Back face (normal: 0, 0, 1)
-        IndexedTriangle { normal: Vector::new([0.0, 0.0, 1.0]), vertices: [5, 4, 7] },
-        IndexedTriangle { normal: Vector::new([0.0, 0.0, 1.0]), vertices: [5, 7, 6] },
+        IndexedTriangle { normal: Vector::new([0.0, 0.0, 1.0]), vertices: [5, 7, 4] },
+        IndexedTriangle { normal: Vector::new([0.0, 0.0, 1.0]), vertices: [5, 6, 7] },
         // Left face (normal: -1, 0, 0)
-        IndexedTriangle { normal: Vector::new([-1.0, 0.0, 0.0]), vertices: [4, 0, 3] },
-        IndexedTriangle { normal: Vector::new([-1.0, 0.0, 0.0]), vertices: [4, 3, 7] },
+        IndexedTriangle { normal: Vector::new([-1.0, 0.0, 0.0]), vertices: [4, 3, 0] },
+        IndexedTriangle { normal: Vector::new([-1.0, 0.0, 0.0]), vertices: [4, 7, 3] },
         // Top face (normal: 0, 1, 0)
-        IndexedTriangle { normal: Vector::new([0.0, 1.0, 0.0]), vertices: [3, 2, 6] },
-        IndexedTriangle { normal: Vector::new([0.0, 1.0, 0.0]), vertices: [3, 6, 7] },
+        IndexedTriangle { normal: Vector::new([0.0, 1.0, 0.0]), vertices: [3, 6, 2] },
+        IndexedTriangle { normal: Vector::new([0.0, 1.0, 0.0]), vertices: [3, 7, 6] },
         // Bottom face (normal: 0, -1, 0)
-        IndexedTriangle { normal: Vector::new([0.0, -1.0, 0.0]), vertices: [4, 5, 1] },
-        IndexedTriangle { normal: Vector::new([0.0, -1.0, 0.0]), vertices: [4, 1, 0] },
+        IndexedTriangle { normal: Vector::new([0.0, -1.0, 0.0]), vertices: [4, 1, 5] },
+        IndexedTriangle { normal: Vector::new([0.0, -1.0, 0.0]), vertices: [4, 0, 1] },
     ];
     
     Ok(IndexedMesh { vertices, faces })
-}
\ No newline at end of file
+}
+
+fn generate_cylinder_stock_mesh(target_mesh: &IndexedMesh, radius: f32, height: f32, segments: usize, padding: &Vector3<f32>) -> Result<IndexedMesh, CAMError> {
+    if segments < 3 {
+        return Err(CAMError::ProcessingError(format!("Cylinder stock needs at least 3 segments, got {}", segments)));
+    }
+    let (min, max) = get_bounds(target_mesh)?;
+
+    // Same margin as the box stock, applied to the radius and both ends
+    let target_radius = target_mesh.vertices.iter()
+        .map(|v| (v[0] * v[0] + v[1] * v[1]).sqrt())
+        .fold(0.0, f32::max);
+    let margin = (max.z - min.z) * padding.z;
+    let required_radius = target_radius * (1.0 + padding.x.max(padding.y));
+    let required_height = max.z - min.z + 2.0 * margin;
+
+    if radius < required_radius || height < required_height {
+        eprintln!(
+            "Cylinder stock r={} h={} doesn't enclose the target, growing it to r={} h={}",
+            radius, height, radius.max(required_radius), height.max(required_height)
+        );
+    }
+    let radius = radius.max(required_radius);
+    let height = height.max(required_height);
+    let bottom = min.z - margin;
+    let top = bottom + height;
+
+    // Push the corners out so the flat sides, not just the corners, clear the radius
+    let step = 2.0 * std::f32::consts::PI / segments as f32;
+    let corner_radius = radius / (step / 2.0).cos();
+
+    // 0: bottom center, 1: top center, then alternating bottom/top ring vertices
+    let mut vertices: Vec<Vector<f32>> = vec![
+        Vector::new([0.0, 0.0, bottom]),
+        Vector::new([0.0, 0.0, top]),
+    ];
+    for i in 0..segments {
+        let angle = i as f32 * step;
+        let (x, y) = (corner_radius * angle.cos(), corner_radius * angle.sin());
+        vertices.push(Vector::new([x, y, bottom]));
+        vertices.push(Vector::new([x, y, top]));
+    }
+
+    let bottom_ring = |i: usize| 2 + 2 * (i % segments);
+    let top_ring = |i: usize| 3 + 2 * (i % segments);
+
+    let mut faces = Vec::with_capacity(segments * 4);
+    for i in 0..segments {
+        let mid_angle = (i as f32 + 0.5) * step;
+        let side_normal = Vector::new([mid_angle.cos(), mid_angle.sin(), 0.0]);
+
+        faces.push(IndexedTriangle { normal: Vector::new([0.0, 0.0, -1.0]), vertices: [0, bottom_ring(i + 1), bottom_ring(i)] });
+        faces.push(IndexedTriangle { normal: Vector::new([0.0, 0.0, 1.0]), vertices: [1, top_ring(i), top_ring(i + 1)] });
+        faces.push(IndexedTriangle { normal: side_normal, vertices: [bottom_ring(i), bottom_ring(i + 1), top_ring(i + 1)] });
+        faces.push(IndexedTriangle { normal: side_normal, vertices: [bottom_ring(i), top_ring(i + 1), top_ring(i)] });
+    }
+
+    Ok(IndexedMesh { vertices, faces })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stl_operations::mesh_volume;
+    use crate::tasks::{CircularClearing, ContourTrace, DrillHole, DrillHoles, MultiContourTrace};
+    use crate::test_support::{box_mesh, cube_mesh, cylinder_mesh};
+
+    /// An unbuilt job on a 10 x 10 x 5 block whose top is at Z = 0, with tool 0.
+    fn block_job() -> CAMJOB {
+        let mut job = CAMJOB::new();
+        job.set_mesh(box_mesh(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 0.0))).unwrap();
+        job.add_tool(Tool::new(0, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        job
+    }
+
+    #[test]
+    fn generated_stock_faces_outward_around_the_part() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(cube_mesh(10.0)).unwrap();
+        // Inward-wound stock would measure a negative volume
+        let stock_volume = mesh_volume(job.get_stock_mesh().unwrap());
+        assert!(stock_volume > 1000.0, "{}", stock_volume);
+    }
+
+    #[test]
+    fn keypoints_are_numbered_along_the_whole_toolpath() {
+        let mut job = block_job();
+        let mesh = job.target_mesh.clone().unwrap();
+        job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -4.0), Vector3::z(), &mesh, 0)));
+        job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -1.0), Vector3::z(), &mesh, 0)));
+        job.build().unwrap();
+
+        let keypoints = job.gather_keypoints();
+        let first_task = job.get_tasks()[0].get_keypoints().len();
+        assert!(first_task > 0);
+        assert_eq!(keypoints.len(), first_task + job.get_tasks()[1].get_keypoints().len());
+        for (step, keypoint) in keypoints.iter().enumerate() {
+            assert_eq!(keypoint.sequence, step);
+            let at_step = job.keypoint_at_time_step(step).unwrap();
+            assert_eq!((at_step.sequence, at_step.position), (step, keypoint.position));
+        }
+        // Numbering carries on into the second task
+        assert_eq!(keypoints[first_task].position.z, -1.0);
+        assert!(job.keypoint_at_time_step(keypoints.len()).is_none());
+    }
+
+    #[test]
+    fn stock_padding_is_the_same_on_both_sides() {
+        let job = block_job();
+        let (min, max) = get_bounds(job.get_stock_mesh().unwrap()).unwrap();
+        assert_eq!(min, Point3::new(-6.0, -6.0, -5.5));
+        assert_eq!(max, Point3::new(6.0, 6.0, 0.5));
+    }
+
+    #[test]
+    fn tool_position_follows_the_toolpath() {
+        let mut job = block_job();
+        let mesh = job.target_mesh.clone().unwrap();
+        job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -4.0), Vector3::z(), &mesh, 0)));
+        job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -1.0), Vector3::z(), &mesh, 0)));
+        job.build().unwrap();
+
+        let positions: Vec<Point3<f32>> = (0..).map_while(|step| job.get_tool_position_at_time_step(step)).collect();
+        assert_eq!(positions, job.gather_keypoints().iter().map(|keypoint| keypoint.position).collect::<Vec<_>>());
+        let first_task = job.get_tasks()[0].get_keypoints();
+        assert_eq!(positions[0], first_task[0].position);
+        assert_eq!(positions[first_task.len()].z, -1.0);
+        assert!(job.get_tool_position_at_time_step(positions.len()).is_none());
+    }
+
+    #[test]
+    fn roughing_leaves_less_stock() {
+        let mut job = block_job();
+        job.add_task(Box::new(CircularClearing::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, -1.0), 2, 12.0, 32, 2.0, 0.1, 0)));
+        job.build().unwrap();
+        let stock_volume = mesh_volume(job.stock_mesh.as_ref().unwrap());
+        let last_step = job.gather_keypoints().len() - 1;
+
+        job.rebuild_stock_at_time_step(last_step).unwrap();
+        let roughed_volume = mesh_volume(job.stock_mesh.as_ref().unwrap());
+
+        // 12 x 12 x 6 with the default padding
+        assert!((stock_volume - 864.0).abs() < 1e-2);
+        assert!(job.get_simulation().is_none());
+        // The block itself is 500 and stays, the clearing took most of the rest
+        assert!(roughed_volume < stock_volume);
+        assert!(roughed_volume > 500.0);
+    }
+
+    #[test]
+    fn provided_stock_survives_target_changes() {
+        let mut job = block_job();
+        let stock = box_mesh(Point3::new(-6.0, -6.0, -6.0), Point3::new(6.0, 6.0, 1.0));
+        job.set_stock_mesh(stock.clone()).unwrap();
+
+        job.set_stock_padding(Vector3::repeat(0.5)).unwrap();
+        job.set_mesh(box_mesh(Point3::new(-4.0, -4.0, -4.0), Point3::new(4.0, 4.0, 0.0))).unwrap();
+        assert_eq!(job.get_stock_mesh().unwrap().vertices, stock.vertices);
+
+        // Generating stock again hands it back to the options
+        job.create_stock_mesh().unwrap();
+        assert_ne!(job.get_stock_mesh().unwrap().vertices, stock.vertices);
+        job.set_stock_padding(Vector3::zeros()).unwrap();
+        assert!((mesh_volume(job.get_stock_mesh().unwrap()) - 256.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn rebuilt_stock_is_kept_like_provided_stock() {
+        let mut job = block_job();
+        job.add_task(Box::new(CircularClearing::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, -1.0), 2, 12.0, 32, 2.0, 0.1, 0)));
+        job.build().unwrap();
+        job.rebuild_stock_at_time_step(job.gather_keypoints().len() - 1).unwrap();
+        let roughed_volume = mesh_volume(job.get_stock_mesh().unwrap());
+
+        job.set_stock_padding(Vector3::zeros()).unwrap();
+        assert_eq!(mesh_volume(job.get_stock_mesh().unwrap()), roughed_volume);
+    }
+
+    #[test]
+    fn simulation_follows_the_time_step_back_and_forth() {
+        let mut job = block_job();
+        let mesh = job.target_mesh.clone().unwrap();
+        job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -2.0), Vector3::z(), &mesh, 0)));
+        job.build().unwrap();
+        let total = job.gather_keypoints().len();
+        assert!(total > 3);
+
+        let mut step_after = |step: usize| {
+            job.update_to_time_step(step).unwrap();
+            job.get_simulation().unwrap().current_step()
+        };
+        assert_eq!(step_after(2), 3);
+        assert_eq!(step_after(0), 1);
+        // Past the end the whole toolpath has cut
+        assert_eq!(step_after(100), total);
+    }
+
+    #[test]
+    fn first_part_instance_replaces_the_original_placement() {
+        let mut job = block_job();
+        let mesh = job.target_mesh.clone().unwrap();
+        job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -2.0), Vector3::z(), &mesh, 0)));
+        job.build().unwrap();
+        let positions = |job: &CAMJOB| -> Vec<Point3<f32>> {
+            job.task_keypoints(0).iter().map(|keypoint| keypoint.position).collect()
+        };
+        let original = positions(&job);
+        assert!(!original.is_empty());
+
+        let shift = Vector3::new(20.0, 0.0, 0.0);
+        job.add_part_instance(Isometry3::translation(shift.x, shift.y, shift.z)).unwrap();
+        let shifted: Vec<Point3<f32>> = original.iter().map(|position| position + shift).collect();
+        assert_eq!(positions(&job), shifted);
+
+        job.add_part_instance(Isometry3::identity()).unwrap();
+        assert_eq!(positions(&job), shifted.iter().chain(&original).copied().collect::<Vec<_>>());
+        // The stock grows to cover both copies
+        let (min, max) = get_bounds(job.get_stock_mesh().unwrap()).unwrap();
+        assert!(min.x <= -5.0 && max.x >= 25.0);
+    }
+
+    #[test]
+    fn offsetting_a_round_contour_inward_shrinks_its_radius() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(cylinder_mesh(8.0, 0.0, 10.0, 128)).unwrap();
+        job.add_tool(Tool::new(0, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        let mesh = job.target_mesh.clone().unwrap();
+        job.add_task(Box::new(ContourTrace::new(48, Point3::new(0.0, 0.0, 5.0), Vector3::z(), &mesh, 0)));
+        job.build().unwrap();
+        let traced = job.get_tasks()[0].get_keypoints();
+        let radius = |keypoint: &Keypoint| keypoint.position.xy().coords.norm();
+
+        let inward = job.offset_contour(0, -1.0);
+        assert_eq!(inward.len(), traced.len());
+        for (offset, keypoint) in inward.iter().zip(&traced) {
+            assert!((radius(keypoint) - radius(offset) - 1.0).abs() < 1e-3, "{} to {}", radius(keypoint), radius(offset));
+            assert!((offset.position.z - 5.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn offset_contour_checks_each_layer_against_itself() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(box_mesh(Point3::new(-5.0, -5.0, 0.0), Point3::new(5.0, 5.0, 10.0))).unwrap();
+        job.add_tool(Tool::new(0, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        // Layers 0.5 apart, closer than the offset
+        job.add_task(Box::new(MultiContourTrace::new(Point3::new(0.0, 0.0, 4.0), Point3::new(0.0, 0.0, 6.0), 4, 16, 0)));
+        job.build().unwrap();
+        let traced = job.get_tasks()[0].get_keypoints();
+        assert_eq!(traced.len(), 5 * 16);
+
+        let outward = job.offset_contour(0, 1.0);
+        assert_eq!(outward.len(), traced.len());
+        for (offset, keypoint) in outward.iter().zip(&traced) {
+            assert_eq!(offset.layer, keypoint.layer);
+            assert!((offset.position.z - keypoint.position.z).abs() < 1e-6);
+            assert!((offset.position.x.abs().max(offset.position.y.abs()) - 6.0).abs() < 1e-3);
+        }
+        // Pushed past the middle, every point comes closer to the far side than the offset
+        assert!(job.offset_contour(0, -6.0).is_empty());
+    }
+
+    #[test]
+    fn build_rejects_tasks_whose_tool_is_missing() {
+        let mut job = CAMJOB::new();
+        let mesh = cube_mesh(10.0);
+        job.set_mesh(mesh.clone()).unwrap();
+        job.add_task(Box::new(ContourTrace::new(8, Point3::origin(), Vector3::z(), &mesh, 0)));
+        assert!(matches!(job.build(), Err(CAMError::ToolNotFound(0))));
+
+        job.add_tool(Tool::new(0, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        job.build().unwrap();
+        assert!(!job.gather_keypoints().is_empty());
+    }
+
+    #[test]
+    fn layers_are_numbered_across_tasks() {
+        let mut job = block_job();
+        let mesh = job.target_mesh.clone().unwrap();
+        job.add_task(Box::new(MultiContourTrace::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, -1.0), 2, 8, 0)));
+        job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -0.5), Vector3::z(), &mesh, 0)));
+        job.add_task(Box::new(ContourTrace::new(4, Point3::new(0.0, 0.0, -3.0), Vector3::z(), &mesh, 0)));
+        job.build().unwrap();
+
+        assert_eq!(job.layer_count(), 5);
+        for layer in 0..3 {
+            let keypoints = job.keypoints_in_layer(layer);
+            assert_eq!(keypoints.len(), 8);
+            assert!(keypoints.iter().all(|keypoint| (keypoint.position.z - (-4.0 + 1.5 * layer as f32)).abs() < 1e-4));
+        }
+        // Each contour is a layer of its own, even at the height of another task's layer
+        let last = job.keypoints_in_layer(4);
+        assert_eq!(last.len(), job.get_tasks()[2].get_keypoints().len());
+        assert!(last.iter().all(|keypoint| keypoint.position.z == -3.0));
+        assert!(job.keypoints_in_layer(5).is_empty());
+
+        // The layers partition the toolpath: every keypoint is in exactly one layer
+        let all = job.gather_keypoints();
+        let layers: Vec<Vec<Keypoint>> = (0..job.layer_count()).map(|layer| job.keypoints_in_layer(layer)).collect();
+        assert_eq!(layers.iter().map(Vec::len).sum::<usize>(), all.len());
+        for keypoint in &all {
+            let containing = layers.iter().filter(|layer| layer.iter().any(|k| k.sequence == keypoint.sequence)).count();
+            assert_eq!(containing, 1, "keypoint {} is in {} layers", keypoint.sequence, containing);
+        }
+    }
+
+    #[test]
+    fn fast_preview_is_coarser_over_the_same_extent() {
+        let mut job = block_job();
+        job.add_task(Box::new(MultiContourTrace::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, -1.0), 8, 32, 0)));
+        let preview = job.preview_first_task_fast().unwrap();
+        // The job's own task isn't processed
+        assert!(job.gather_keypoints().is_empty());
+        job.build().unwrap();
+        let full = job.gather_keypoints();
+
+        assert!(!preview.is_empty() && preview.len() < full.len());
+        let extent = |keypoints: &[Keypoint]| {
+            keypoints.iter().fold((Point3::from([f32::MAX; 3]), Point3::from([f32::MIN; 3])), |(min, max), keypoint| {
+                (min.inf(&keypoint.position), max.sup(&keypoint.position))
+            })
+        };
+        let ((preview_min, preview_max), (full_min, full_max)) = (extent(&preview), extent(&full));
+        assert!((preview_min - full_min).norm() < 1e-3 && (preview_max - full_max).norm() < 1e-3);
+
+        assert!(matches!(job.preview_task_fast(1), Err(CAMError::ProcessingError(_))));
+    }
+
+    #[test]
+    fn tasks_take_the_largest_tool_fitting_their_radius() {
+        let mut job = block_job();
+        job.add_tool(Tool::new(1, "Endmill".to_string(), 20.0, 6.0)).unwrap();
+        let mesh = job.target_mesh.clone().unwrap();
+        let contour = |tool_id: usize| Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -4.0), Vector3::z(), &mesh, tool_id));
+        // The 3 mm endmill fits, the 6 mm one doesn't; the id given is replaced
+        assert_eq!(job.add_task_for_min_radius(contour(1), 2.0).unwrap(), 0);
+        assert_eq!(job.get_tasks()[0].get_tool_id(), 0);
+        assert_eq!(job.add_task_for_min_radius(contour(0), 3.0).unwrap(), 1);
+        assert!(matches!(job.add_task_for_min_radius(contour(0), 1.0), Err(CAMError::ProcessingError(_))));
+        assert_eq!(job.get_tasks().len(), 2);
+    }
+
+    #[test]
+    fn task_origin_offset_moves_only_its_task() {
+        let mut job = block_job();
+        let mesh = job.target_mesh.clone().unwrap();
+        for _ in 0..2 {
+            job.add_task(Box::new(ContourTrace::new(8, Point3::new(0.0, 0.0, -2.0), Vector3::z(), &mesh, 0)));
+        }
+        let offset = Isometry3::new(Vector3::new(2.0, -3.0, 0.5), Vector3::z() * std::f32::consts::FRAC_PI_2);
+        job.set_task_origin_offset(1, offset).unwrap();
+        assert!(matches!(job.set_task_origin_offset(2, offset), Err(CAMError::ProcessingError(_))));
+        job.build().unwrap();
+
+        let (plain, offset_task) = (job.task_keypoints(0), job.task_keypoints(1));
+        assert_eq!(plain.len(), offset_task.len());
+        assert!(!plain.is_empty());
+        for (plain, moved) in plain.iter().zip(&offset_task) {
+            assert!((offset * plain.position - moved.position).norm() < 1e-5);
+            assert!((offset * plain.normal - moved.normal).norm() < 1e-5);
+        }
+        // A quarter turn about Z, then the shift
+        let first = plain[0].position;
+        assert!((offset_task[0].position - Point3::new(2.0 - first.y, first.x - 3.0, first.z + 0.5)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn overall_progress_is_weighted_by_estimated_keypoints() {
+        let mut job = block_job();
+        // 3 keypoints of drilling, then 4 layers of 8 rays
+        job.add_task(Box::new(DrillHoles::new(vec![DrillHole::new(Point3::new(1.0, 1.0, 0.0), 2.0)], 2.0, 0)));
+        job.add_task(Box::new(MultiContourTrace::new(Point3::new(0.0, 0.0, -3.5), Point3::new(0.0, 0.0, -0.5), 3, 8, 0)));
+        let mut reports = Vec::new();
+        job.build_with_progress(|progress| reports.push(progress)).unwrap();
+
+        // Weighting each task the same would be halfway as the drilling ends
+        let drilled = reports.iter().rev().find(|progress| progress.task_index == 0).unwrap();
+        assert!((drilled.overall - 3.0 / 35.0).abs() < 1e-6);
+        let halfway = reports.iter().find(|progress| progress.overall >= 0.5).unwrap();
+        assert_eq!((halfway.task_index, halfway.fraction), (1, 0.5));
+        assert!((halfway.overall - 19.0 / 35.0).abs() < 1e-6);
+        assert!(reports.windows(2).all(|pair| pair[0].overall <= pair[1].overall));
+        assert_eq!(reports.last().unwrap().overall, 1.0);
+    }
+
+    #[test]
+    fn tool_changes_follow_the_task_order() {
+        let mut job = block_job();
+        job.add_tool(Tool::new(1, "Drill".to_string(), 20.0, 2.0)).unwrap();
+        for (i, tool_id) in [0, 1, 1, 0].iter().copied().enumerate() {
+            let hole = DrillHole::new(Point3::new(i as f32 - 2.0, 1.0, 0.0), 2.0);
+            job.add_task(Box::new(DrillHoles::new(vec![hole], 2.0, tool_id)));
+        }
+        // Nothing is loaded before the toolpath exists
+        assert!(job.tool_change_sequence().is_empty());
+        job.build().unwrap();
+        // Back-to-back tasks on one tool share its change; going back to a tool is another
+        assert_eq!(job.tool_change_sequence(), vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn cloned_tasks_switch_tool_and_leave_the_originals() {
+        let mut job = block_job();
+        job.add_tool(Tool::new(1, "Drill".to_string(), 20.0, 2.0)).unwrap();
+        job.add_task(Box::new(CircularClearing::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, -1.0), 2, 12.0, 16, 2.0, 0.1, 1)));
+        job.add_task(Box::new(DrillHoles::new(vec![DrillHole::new(Point3::new(1.0, 1.0, 0.0), 2.0)], 2.0, 0)));
+        job.build().unwrap();
+
+        let clones = job.clone_tasks_with_new_tool(7);
+        assert_eq!(clones.iter().map(|task| task.get_tool_id()).collect::<Vec<_>>(), vec![7, 7]);
+        assert_eq!(job.get_tasks().iter().map(|task| task.get_tool_id()).collect::<Vec<_>>(), vec![1, 0]);
+        for (clone, task) in clones.iter().zip(job.get_tasks()) {
+            assert_eq!(clone.name(), task.name());
+            let positions = |task: &dyn CAMTask| task.get_keypoints().iter().map(|keypoint| keypoint.position).collect::<Vec<_>>();
+            assert!(!task.get_keypoints().is_empty());
+            assert_eq!(positions(clone.as_ref()), positions(task.as_ref()));
+        }
+    }
+
+    #[test]
+    fn near_duplicate_keypoints_are_thinned_per_layer() {
+        let at = |x: f32, layer: usize| Keypoint { layer, ..Keypoint::new(Point3::new(x, 0.0, 0.0), Vector3::z()) };
+        let keypoints = vec![at(0.0, 0), at(0.01, 0), at(0.05, 0), at(0.1, 0), at(0.02, 1), at(0.15, 0), at(0.3, 0)];
+        let kept: Vec<(f32, usize)> = thin_keypoints(keypoints, 0.1).iter().map(|keypoint| (keypoint.position.x, keypoint.layer)).collect();
+        // Each layer is spaced against its own last kept keypoint
+        assert_eq!(kept, vec![(0.0, 0), (0.1, 0), (0.02, 1), (0.3, 0)]);
+
+        let mut job = block_job();
+        job.add_task(Box::new(MultiContourTrace::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, -1.0), 1, 64, 0)));
+        job.set_tolerances(Tolerances { min_keypoint_spacing: 2.0, ..Tolerances::default() });
+        job.build().unwrap();
+        let keypoints = job.task_keypoints(0);
+        assert!(keypoints.len() < 2 * 64);
+        for pair in keypoints.windows(2).filter(|pair| pair[0].layer == pair[1].layer) {
+            assert!((pair[1].position - pair[0].position).norm() >= 2.0);
+        }
+    }
+
+    #[test]
+    fn job_builds_with_a_headless_tool_library() {
+        let mut tool_library = ToolLibrary::new();
+        tool_library.add_tool(Tool::new(4, "Drill".to_string(), 20.0, 3.0)).unwrap();
+        let mut job = CAMJOB::new().with_tool_library(tool_library);
+        job.set_mesh(box_mesh(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 0.0))).unwrap();
+        // One peck 2 deep: entry, bottom and retract
+        job.add_task(Box::new(DrillHoles::new(vec![DrillHole::new(Point3::origin(), 2.0)], 2.0, 4)));
+        job.build().unwrap();
+
+        assert_eq!(job.get_tool(4).map(|tool| tool.diameter), Some(3.0));
+        let keypoints = job.task_keypoints(0);
+        assert_eq!(keypoints.len(), 3);
+        assert!(keypoints.iter().all(|keypoint| keypoint.tool_id == Some(4)));
+    }
+
+    #[test]
+    fn zero_stock_padding_matches_the_target_bounds() {
+        let mut job = block_job();
+        job.set_stock_padding(Vector3::zeros()).unwrap();
+        let target_bounds = get_bounds(job.target_mesh.as_ref().unwrap()).unwrap();
+        assert_eq!(get_bounds(job.get_stock_mesh().unwrap()).unwrap(), target_bounds);
+
+        // Padding only Z, by a fifth of the block's 5 height
+        job.set_stock_padding(Vector3::new(0.0, 0.0, 0.2)).unwrap();
+        let (min, max) = get_bounds(job.get_stock_mesh().unwrap()).unwrap();
+        assert_eq!((min, max), (Point3::new(-5.0, -5.0, -6.0), Point3::new(5.0, 5.0, 1.0)));
+    }
+}
@@ -0,0 +1,307 @@
+use std::f32::consts::PI;
+use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
+use ncollide3d::query;
+use ncollide3d::math::Point;
+use ncollide3d::shape::{Ball, ConvexHull, Shape, TriMesh};
+use crate::cam_job::CAMJOB;
+use crate::stl_operations::{get_bounds, indexed_mesh_to_trimesh};
+use crate::tool::{Tool, ToolGeometry};
+
+/// Sides of the prisms standing in for round tool bodies.
+const HULL_SEGMENTS: usize = 32;
+
+/// A keypoint at which the tool body cuts into the target.
+#[derive(Debug, Clone)]
+pub struct CollisionReport {
+    /// Position of the keypoint in the job's toolpath (its `Keypoint::sequence`).
+    pub keypoint_index: usize,
+    pub tool_id: usize,
+    /// How far the tool reaches into the target.
+    pub penetration_depth: f32,
+}
+
+/// A keypoint outside the stock, which would have the tool cutting air from the start.
+/// Usually means the stock or a task is set up in the wrong place.
+#[derive(Debug, Clone)]
+pub struct OutOfStockReport {
+    /// Position of the keypoint in the job's toolpath (its `Keypoint::sequence`).
+    pub keypoint_index: usize,
+    pub task_index: usize,
+    pub position: Point3<f32>,
+}
+
+impl CAMJOB {
+    /// Every keypoint outside the stock's bounding box. Keypoints on its surface count
+    /// as inside, and rapids, which are reached clear of the material, are skipped.
+    /// Returns nothing when no stock is set.
+    pub fn check_stock_containment(&self) -> Vec<OutOfStockReport> {
+        let (min, max) = match self.get_stock_mesh().map(get_bounds) {
+            Some(Ok(bounds)) => bounds,
+            _ => return Vec::new(),
+        };
+        self.gather_keypoints_with_task()
+            .into_iter()
+            .filter(|(_, keypoint)| {
+                let p = keypoint.position;
+                !keypoint.rapid && p != p.sup(&min).inf(&max)
+            })
+            .map(|(task_index, keypoint)| OutOfStockReport {
+                keypoint_index: keypoint.sequence,
+                task_index,
+                position: keypoint.position,
+            })
+            .collect()
+    }
+
+    /// Checks every keypoint for the tool body intersecting the target. The tool
+    /// stands on the keypoint along the keypoint normal, with the tip of its geometry
+    /// on the surface and a cylindrical shank above that. Returns nothing
+    /// when no target mesh is set; keypoints whose tool isn't in the library are skipped.
+    pub fn check_collisions(&self) -> Vec<CollisionReport> {
+        let target = match &self.target_mesh {
+            Some(mesh) => indexed_mesh_to_trimesh(mesh),
+            None => return Vec::new(),
+        };
+        let tasks = self.get_tasks();
+        let mut reports = Vec::new();
+
+        for (task_index, keypoint) in self.gather_keypoints_with_task() {
+            let tool_id = tasks[task_index].get_tool_id();
+            let tool = match self.get_tool(tool_id) {
+                Some(tool) => tool,
+                None => continue,
+            };
+            let axis = match keypoint.normal.try_normalize(f32::EPSILON) {
+                Some(axis) => axis,
+                None => continue,
+            };
+
+            if let Some(depth) = tool_contact_depth(tool, &keypoint.position, &axis, &target) {
+                if depth > tool.diameter * self.tolerances_for(task_index).gouge_fraction {
+                    reports.push(CollisionReport {
+                        keypoint_index: keypoint.sequence,
+                        tool_id,
+                        penetration_depth: depth,
+                    });
+                }
+            }
+        }
+
+        reports
+    }
+}
+
+/// Deepest contact between `target` and `tool` standing on `tip` along the unit
+/// `axis`, or `None` when they don't touch.
+fn tool_contact_depth(tool: &Tool, tip: &Point3<f32>, axis: &Vector3<f32>, target: &TriMesh<f32>) -> Option<f32> {
+    let radius = tool.diameter / 2.0;
+    let tip_length = tool.tip_length();
+    let shank_length = tool.length - tip_length;
+
+    let mut depth: Option<f32> = None;
+    let mut touch = |depth_here: Option<f32>| {
+        if let Some(depth_here) = depth_here {
+            depth = Some(depth.map_or(depth_here, |depth| depth.max(depth_here)));
+        }
+    };
+    if shank_length > 0.0 {
+        let center = tip + axis * (tip_length + shank_length / 2.0);
+        if let Some(shank) = frustum_hull(shank_length / 2.0, radius, radius) {
+            touch(contact_depth(&shank, &along(&center, axis), target));
+        }
+    }
+    match tool.geometry {
+        ToolGeometry::BallNose => {
+            touch(contact_depth(&Ball::new(radius), &along(&(tip + axis * radius), axis), target));
+        }
+        ToolGeometry::Vbit { .. } if tip_length > 0.0 => {
+            // Narrowing toward +Y, so aim it back down the axis at the tip
+            let center = tip + axis * (tip_length / 2.0);
+            if let Some(cone) = frustum_hull(tip_length / 2.0, radius, 0.0) {
+                touch(contact_depth(&cone, &along(&center, &-axis), target));
+            }
+        }
+        ToolGeometry::FlatEnd | ToolGeometry::Vbit { .. } => {}
+    }
+    depth
+}
+
+fn contact_depth<S: Shape<f32>>(shape: &S, pose: &Isometry3<f32>, target: &TriMesh<f32>) -> Option<f32> {
+    query::contact(pose, shape, &Isometry3::identity(), target, 0.0).map(|contact| contact.depth)
+}
+
+/// Pose at `center` turning the Y axis, along which ncollide builds its shapes, onto `axis`.
+fn along(center: &Point3<f32>, axis: &Vector3<f32>) -> Isometry3<f32> {
+    let rotation = UnitQuaternion::rotation_between(&Vector3::y(), axis)
+        .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::x_axis(), PI));
+    Isometry3::from_parts(Translation3::from(center.coords), rotation)
+}
+
+/// Convex hull of a frustum centered on the origin along Y, as ncollide builds its
+/// shapes, `bottom_radius` wide at -Y and `top_radius` at +Y. Stands in for ncollide's
+/// `Cylinder` and `Cone`, which can't be tested for contact against a mesh. `None`
+/// for one without height or width.
+fn frustum_hull(half_height: f32, bottom_radius: f32, top_radius: f32) -> Option<ConvexHull<f32>> {
+    if half_height <= 0.0 || bottom_radius <= 0.0 || top_radius < 0.0 {
+        return None;
+    }
+    let mut points: Vec<Point<f32>> = Vec::new();
+    for i in 0..HULL_SEGMENTS {
+        let (sin, cos) = (i as f32 * 2.0 * PI / HULL_SEGMENTS as f32).sin_cos();
+        points.push(Point::new(bottom_radius * cos, -half_height, bottom_radius * sin));
+        if top_radius > 0.0 {
+            points.push(Point::new(top_radius * cos, half_height, top_radius * sin));
+        }
+    }
+    if top_radius == 0.0 {
+        points.push(Point::new(0.0, half_height, 0.0));
+    }
+    ConvexHull::try_from_points(&points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cam_job::{CAMTask, Keypoint, Units};
+    use crate::errors::CAMError;
+    use crate::tasks::{DrillHoles, HeightSampling, Lead, TaskConfig, TraceMode};
+    use crate::test_support::{box_mesh, trimesh};
+    use crate::tolerances::Tolerances;
+    use crate::tool::Tool;
+    use nalgebra::Point3;
+    use ncollide3d::shape::TriMesh;
+    use stl_io::IndexedMesh;
+
+    /// Keypoints given up front, so tests can place the tool exactly.
+    #[derive(Clone)]
+    struct FixedKeypoints {
+        keypoints: Vec<Keypoint>,
+        tool_id: usize,
+    }
+
+    impl CAMTask for FixedKeypoints {
+        fn process(&mut self, _mesh: &IndexedMesh, _tri_mesh: &TriMesh<f32>, _tolerances: &Tolerances, _progress: &mut dyn FnMut(f32)) -> Result<(), CAMError> {
+            Ok(())
+        }
+        fn get_keypoints(&self) -> Vec<Keypoint> {
+            self.keypoints.clone()
+        }
+        fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+            self.keypoints = keypoints;
+        }
+        fn get_tool_id(&self) -> usize {
+            self.tool_id
+        }
+        fn set_tool_id(&mut self, tool_id: usize) {
+            self.tool_id = tool_id;
+        }
+        fn config(&self) -> TaskConfig {
+            // Job files only know the built-in tasks; this one saves as an empty contour
+            TaskConfig::ContourTrace {
+                num_rays: 0,
+                position: Point3::origin(),
+                normal: Vector3::z(),
+                mode: TraceMode::Polar,
+                height_sampling: HeightSampling::default(),
+                lead: Lead::default(),
+                tool_id: self.tool_id,
+            }
+        }
+        fn name(&self) -> &str {
+            "FixedKeypoints"
+        }
+        fn describe(&self, _units: Units) -> String {
+            format!("{} fixed keypoints", self.keypoints.len())
+        }
+        fn estimated_keypoint_count(&self) -> usize {
+            self.keypoints.len()
+        }
+        fn clone_box(&self) -> Box<dyn CAMTask> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn collisions_are_reported_per_keypoint() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(box_mesh(Point3::new(-20.0, -20.0, -10.0), Point3::new(20.0, 20.0, 0.0))).unwrap();
+        job.add_tool(Tool::new(3, "Endmill".to_string(), 20.0, 6.0)).unwrap();
+        let keypoints = vec![
+            // Upright on the top, then tilted 45° into it, then upright again
+            Keypoint::new(Point3::new(0.0, 0.0, 0.0), Vector3::z()),
+            Keypoint::new(Point3::new(5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 1.0)),
+            Keypoint::new(Point3::new(10.0, 0.0, 0.0), Vector3::z()),
+        ];
+        job.add_task(Box::new(FixedKeypoints { keypoints: keypoints.clone(), tool_id: 3 }));
+        // The same tilt with a tool missing from the library can't be checked
+        job.add_task(Box::new(FixedKeypoints { keypoints, tool_id: 9 }));
+
+        let reports = job.check_collisions();
+        assert_eq!(reports.len(), 1);
+        assert_eq!((reports[0].keypoint_index, reports[0].tool_id), (1, 3));
+        // The lower edge of the flat end dips half a diagonal of its radius in
+        assert!((reports[0].penetration_depth - 3.0 * 0.5f32.sqrt()).abs() < 0.05);
+    }
+
+    #[test]
+    fn gouges_are_judged_by_each_task_tolerances() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(box_mesh(Point3::new(-20.0, -20.0, -10.0), Point3::new(20.0, 20.0, 0.0))).unwrap();
+        job.add_tool(Tool::new(3, "Endmill".to_string(), 20.0, 6.0)).unwrap();
+        // Upright, its flat end 0.05 into the top: 1/120 of the diameter
+        let keypoints = vec![Keypoint::new(Point3::new(0.0, 0.0, -0.05), Vector3::z())];
+        for _ in 0..2 {
+            job.add_task(Box::new(FixedKeypoints { keypoints: keypoints.clone(), tool_id: 3 }));
+        }
+        job.set_tolerances(Tolerances { gouge_fraction: 0.05, ..Tolerances::default() });
+        job.set_task_tolerances(1, Tolerances::default()).unwrap();
+        assert_eq!(job.tolerances_for(0).gouge_fraction, 0.05);
+        assert_eq!(job.tolerances_for(1).gouge_fraction, 1e-3);
+
+        let reports = job.check_collisions();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].keypoint_index, 1);
+    }
+
+    #[test]
+    fn keypoints_outside_the_stock_are_reported() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(box_mesh(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 0.0))).unwrap();
+        job.set_stock_padding(Vector3::zeros()).unwrap();
+        let mut task = DrillHoles::new(Vec::new(), 1.0, 0);
+        task.set_keypoints(vec![
+            Keypoint::new(Point3::new(0.0, 0.0, -1.0), Vector3::z()),
+            // On the stock's top face
+            Keypoint::new(Point3::new(5.0, 5.0, 0.0), Vector3::z()),
+            Keypoint::new(Point3::new(0.0, 7.0, -1.0), Vector3::z()),
+            // Rapids are reached above the stock on purpose
+            Keypoint { rapid: true, ..Keypoint::new(Point3::new(0.0, 0.0, 3.0), Vector3::z()) },
+        ]);
+        job.add_task(Box::new(task));
+
+        let reports = job.check_stock_containment();
+        assert_eq!(reports.len(), 1);
+        assert_eq!((reports[0].keypoint_index, reports[0].task_index), (2, 0));
+        assert_eq!(reports[0].position, Point3::new(0.0, 7.0, -1.0));
+    }
+
+    /// How far a tool of `geometry` with its tip on a flat plate, tilted 45° off the
+    /// plate's normal, reaches into it.
+    fn tilted_depth(geometry: ToolGeometry) -> f32 {
+        let plate = trimesh(&box_mesh(Point3::new(-20.0, -20.0, -10.0), Point3::new(20.0, 20.0, 0.0)));
+        let tool = Tool::new(0, "Test".to_string(), 20.0, 6.0).with_geometry(geometry);
+        let axis = Vector3::new(1.0, 0.0, 1.0).normalize();
+        tool_contact_depth(&tool, &Point3::origin(), &axis, &plate).unwrap_or(0.0)
+    }
+
+    #[test]
+    fn contact_depth_follows_tool_geometry() {
+        let flat = tilted_depth(ToolGeometry::FlatEnd);
+        let ball = tilted_depth(ToolGeometry::BallNose);
+        // A 90° V-bit tilted 45° lies along the plate
+        let vbit = tilted_depth(ToolGeometry::Vbit { angle: 90.0 });
+        assert!((flat - 3.0 * 0.5f32.sqrt()).abs() < 0.05, "flat end reached {}", flat);
+        assert!((ball - 3.0 * (1.0 - 0.5f32.sqrt())).abs() < 0.05, "ball nose reached {}", ball);
+        assert!(vbit < 0.05, "V-bit reached {}", vbit);
+    }
+}
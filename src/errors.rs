@@ -1,18 +1,40 @@
-use std::fmt;
-
 #[derive(thiserror::Error, Debug)]
 pub enum CAMError {
+    #[error("Invalid mesh: {0}")]
     InvalidMesh(String),
+    #[error("Mesh not set for CAM job")]
     MeshNotSet,
+    #[error("Processing error: {0}")]
     ProcessingError(String),
+    #[error("Tool {0} not found in tool library")]
+    ToolNotFound(usize),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse STL: {0}")]
+    StlParse(String),
+    #[error("Failed to parse mesh: {0}")]
+    MeshParse(String),
+    #[error("Unsupported mesh format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
 }
 
-impl fmt::Display for CAMError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            CAMError::MeshNotSet => write!(f, "Mesh not set for CAM job"),
-            CAMError::InvalidMesh(msg) => write!(f, "Invalid mesh: {}", msg),
-            CAMError::ProcessingError(msg) => write!(f, "Processing error: {}", msg),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_display_their_details() {
+        assert_eq!(CAMError::ToolNotFound(4).to_string(), "Tool 4 not found in tool library");
+        assert_eq!(CAMError::MeshNotSet.to_string(), "Mesh not set for CAM job");
+        assert_eq!(CAMError::InvalidMesh("no faces".to_string()).to_string(), "Invalid mesh: no faces");
+        assert_eq!(CAMError::ProcessingError("empty ring".to_string()).to_string(), "Processing error: empty ring");
+
+        let io: CAMError = std::io::Error::new(std::io::ErrorKind::NotFound, "part.stl missing").into();
+        assert!(matches!(io, CAMError::Io(_)));
+        assert_eq!(io.to_string(), "I/O error: part.stl missing");
+        let json: CAMError = serde_json::from_str::<u32>("[").unwrap_err().into();
+        assert!(json.to_string().starts_with("Serialization error: "));
     }
 }
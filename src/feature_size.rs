@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use nalgebra::{Isometry3, Point3, Vector3};
+use ncollide3d::query::{Ray, RayCast};
+use ncollide3d::math::Point as NCPoint;
+use stl_io::IndexedMesh;
+use crate::errors::CAMError;
+use crate::stl_operations::{get_bounds, indexed_mesh_to_trimesh};
+use crate::tool::ToolLibrary;
+
+/// Concave folds at least this sharp, in radians, are taken as sharp internal corners
+/// (radius zero) rather than one step of a tessellated fillet.
+pub const SHARP_CORNER_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+/// Folds flatter than this, in radians, are treated as flat.
+const FLAT_ANGLE: f32 = 1e-3;
+
+/// A feature measurement and where on the part it was taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureSize {
+    pub size: f32,
+    pub location: Point3<f32>,
+}
+
+/// The smallest details of a part, from `min_feature_sizes`.
+#[derive(Debug, Clone, Default)]
+pub struct MinFeatureReport {
+    /// Smallest concave radius along the surface, e.g. of an internal fillet. Zero for
+    /// a sharp internal corner, which no round tool can cut. `None` for convex parts.
+    pub min_internal_radius: Option<FeatureSize>,
+    /// Thinnest material, measured straight in from a face to the surface behind it.
+    pub thinnest_wall: Option<FeatureSize>,
+}
+
+impl MinFeatureReport {
+    /// One warning per tool in `tools` whose radius is larger than the smallest
+    /// internal radius, so it would leave material in that corner.
+    pub fn warnings(&self, tools: &ToolLibrary) -> Vec<String> {
+        let radius = match self.min_internal_radius {
+            Some(radius) => radius,
+            None => return Vec::new(),
+        };
+        tools
+            .iter()
+            .filter(|tool| tool.diameter / 2.0 > radius.size)
+            .map(|tool| {
+                format!(
+                    "{} is too large for the internal radius {} at {:?}",
+                    tool.describe(),
+                    radius.size,
+                    radius.location.coords.as_slice()
+                )
+            })
+            .collect()
+    }
+}
+
+/// Estimates the smallest internal radius and thinnest wall of a closed, outward-facing
+/// mesh (see `repair_mesh`).
+///
+/// The radius is read off every concave edge: a fillet tessellated into facets of
+/// width `w` that turn by `a` at each edge has radius `w / (2 sin(a / 2))`, with `w`
+/// taken as the average height of the two faces over their shared edge. Folds of at
+/// least `SHARP_CORNER_ANGLE` count as sharp corners. Walls are measured by casting a
+/// ray inward from the centroid of every face.
+pub fn min_feature_sizes(mesh: &IndexedMesh) -> Result<MinFeatureReport, CAMError> {
+    let (min, max) = get_bounds(mesh)?;
+    let diagonal = (max - min).norm();
+    let point = |index: usize| {
+        let v = mesh.vertices[index];
+        Point3::new(v[0], v[1], v[2])
+    };
+    let normals: Vec<Option<Vector3<f32>>> = mesh
+        .faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices.map(point);
+            (b - a).cross(&(c - a)).try_normalize(f32::EPSILON)
+        })
+        .collect();
+
+    let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (face_index, face) in mesh.faces.iter().enumerate() {
+        for i in 0..3 {
+            let (a, b) = (face.vertices[i], face.vertices[(i + 1) % 3]);
+            edge_faces.entry((a.min(b), a.max(b))).or_default().push(face_index);
+        }
+    }
+
+    let mut min_internal_radius: Option<FeatureSize> = None;
+    for (&(a, b), faces) in &edge_faces {
+        let (first, second) = match faces.as_slice() {
+            &[first, second] => (first, second),
+            _ => continue,
+        };
+        let (normal_first, normal_second) = match (normals[first], normals[second]) {
+            (Some(normal_first), Some(normal_second)) => (normal_first, normal_second),
+            _ => continue,
+        };
+        let (start, end) = (point(a), point(b));
+        let edge = match (end - start).try_normalize(f32::EPSILON) {
+            Some(edge) => edge,
+            None => continue,
+        };
+        let opposite = |face: usize| point(mesh.faces[face].vertices.iter().copied().find(|&v| v != a && v != b).unwrap_or(a));
+        let (apex_first, apex_second) = (opposite(first), opposite(second));
+
+        let angle = normal_first.angle(&normal_second);
+        // Concave when the second face rises off the outside of the first
+        if angle < FLAT_ANGLE || (apex_second - start).dot(&normal_first) <= 0.0 {
+            continue;
+        }
+        let radius = if angle >= SHARP_CORNER_ANGLE {
+            0.0
+        } else {
+            let height = |apex: Point3<f32>| (apex - start).cross(&edge).norm();
+            let width = (height(apex_first) + height(apex_second)) / 2.0;
+            width / (2.0 * (angle / 2.0).sin())
+        };
+        if min_internal_radius.is_none_or(|current| radius < current.size) {
+            min_internal_radius = Some(FeatureSize { size: radius, location: start + (end - start) / 2.0 });
+        }
+    }
+
+    let tri_mesh = indexed_mesh_to_trimesh(mesh);
+    let margin = diagonal * 1e-4;
+    let mut thinnest_wall: Option<FeatureSize> = None;
+    for (face, normal) in mesh.faces.iter().zip(&normals) {
+        let normal = match normal {
+            Some(normal) => *normal,
+            None => continue,
+        };
+        let [a, b, c] = face.vertices.map(point);
+        let centroid = Point3::from((a.coords + b.coords + c.coords) / 3.0);
+        let origin = centroid - normal * margin;
+        let ray = Ray::new(NCPoint::from(origin.coords), -normal);
+        if let Some(toi) = tri_mesh.toi_with_ray(&Isometry3::identity(), &ray, diagonal, false) {
+            let thickness = toi + margin;
+            if thinnest_wall.is_none_or(|current| thickness < current.size) {
+                thinnest_wall = Some(FeatureSize { size: thickness, location: centroid - normal * (thickness / 2.0) });
+            }
+        }
+    }
+
+    Ok(MinFeatureReport { min_internal_radius, thinnest_wall })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MeshBuilder;
+    use crate::tool::Tool;
+
+    /// An L-shaped bar 20 long in Y: a 4 thick wall at X -4..0 standing on a 4 thick
+    /// floor at Z -4..0, with the inside corner rounded to `fillet` in `segments` facets,
+    /// or left sharp with no segments.
+    fn filleted_angle(fillet: f32, segments: usize) -> IndexedMesh {
+        let mut profile = vec![(0.0, 10.0)];
+        for i in 0..=segments {
+            let t = std::f32::consts::FRAC_PI_2 * i as f32 / segments.max(1) as f32;
+            profile.push((fillet * (1.0 - t.cos()), fillet * (1.0 - t.sin())));
+        }
+        profile.extend_from_slice(&[(10.0, 0.0), (10.0, -4.0), (-4.0, -4.0), (-4.0, 10.0)]);
+
+        let mut builder = MeshBuilder::default();
+        let mut ends = |y: f32| profile.iter().map(|&(x, z)| builder.vertex(Point3::new(x, y, z))).collect::<Vec<_>>();
+        let (front, back) = (ends(0.0), ends(20.0));
+        // The profile is star-shaped around the corner of the L, so the ends fan from there
+        let (front_center, back_center) = (builder.vertex(Point3::new(-2.0, 0.0, -2.0)), builder.vertex(Point3::new(-2.0, 20.0, -2.0)));
+        let n = profile.len();
+        for i in 0..n {
+            let j = (i + 1) % n;
+            let (x0, z0) = profile[i];
+            let (x1, z1) = profile[j];
+            // The profile runs clockwise seen from +Y, so the outside is on its left
+            let outward = Vector3::new(z0 - z1, 0.0, x1 - x0);
+            builder.quad(front[i], front[j], back[j], back[i], outward);
+            builder.face(front_center, front[i], front[j], -Vector3::y());
+            builder.face(back_center, back[i], back[j], Vector3::y());
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn internal_fillet_radius_and_wall_are_found() {
+        let report = min_feature_sizes(&filleted_angle(2.0, 8)).unwrap();
+        let radius = report.min_internal_radius.unwrap();
+        assert!((radius.size - 2.0).abs() < 0.05, "radius {}", radius.size);
+        // On the fillet, which curves around (2, 2) in X and Z
+        assert!(((radius.location.x - 2.0).hypot(radius.location.z - 2.0) - 2.0).abs() < 0.05);
+        let wall = report.thinnest_wall.unwrap();
+        assert!((wall.size - 4.0).abs() < 0.01, "wall {}", wall.size);
+
+        let mut tools = ToolLibrary::new();
+        tools.add_tool(Tool::new(0, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        tools.add_tool(Tool::new(1, "Endmill".to_string(), 20.0, 6.0)).unwrap();
+        let warnings = report.warnings(&tools);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("T1 "));
+    }
+
+    #[test]
+    fn unrounded_inside_corner_is_sharp() {
+        let report = min_feature_sizes(&filleted_angle(0.0, 0)).unwrap();
+        assert_eq!(report.min_internal_radius.map(|radius| radius.size), Some(0.0));
+    }
+}
@@ -0,0 +1,86 @@
+//! Rendering playback to numbered PNG files, e.g. to assemble a video of the toolpath.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use kiss3d::window::Window;
+use crate::errors::CAMError;
+
+/// Digits in every frame number, so the files sort in playback order.
+const MIN_FRAME_DIGITS: usize = 4;
+
+/// Captures `frame_count` frames into `output_dir`, spread evenly over the job's
+/// keypoints. Drive it from the render loop: seek playback to `keypoint_index` of the
+/// next frame, render, then `capture`.
+pub struct FrameExport {
+    pub output_dir: PathBuf,
+    pub frame_count: usize,
+    next_frame: usize,
+}
+
+impl FrameExport {
+    pub fn new(output_dir: PathBuf, frame_count: usize) -> Self {
+        FrameExport { output_dir, frame_count, next_frame: 0 }
+    }
+
+    /// Whether every frame has been written.
+    pub fn is_finished(&self) -> bool {
+        self.next_frame >= self.frame_count
+    }
+
+    /// Keypoint the next frame shows, out of `num_keypoints`. The first frame shows the
+    /// first keypoint and the last frame the last one.
+    pub fn keypoint_index(&self, num_keypoints: usize) -> usize {
+        if self.frame_count <= 1 || num_keypoints == 0 {
+            return 0;
+        }
+        self.next_frame * (num_keypoints - 1) / (self.frame_count - 1)
+    }
+
+    /// Saves what `window` last rendered as the next frame and returns its path.
+    pub fn capture(&mut self, window: &Window) -> Result<PathBuf, CAMError> {
+        if self.next_frame == 0 {
+            fs::create_dir_all(&self.output_dir)?;
+        }
+        let path = frame_path(&self.output_dir, self.next_frame, self.frame_count);
+        window
+            .snap_image()
+            .save(&path)
+            .map_err(|e| CAMError::ProcessingError(format!("Failed to save frame {}: {}", path.display(), e)))?;
+        self.next_frame += 1;
+        Ok(path)
+    }
+}
+
+/// `frame_0000.png`, `frame_0001.png`, ... in `output_dir`, zero-padded to at least
+/// four digits and to enough for `frame_count` frames.
+pub fn frame_path(output_dir: &Path, index: usize, frame_count: usize) -> PathBuf {
+    let digits = frame_count.saturating_sub(1).to_string().len().max(MIN_FRAME_DIGITS);
+    output_dir.join(format!("frame_{:0width$}.png", index, width = digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_names_are_zero_padded_and_sequential() {
+        let dir = Path::new("frames");
+        let names: Vec<PathBuf> = (0..3).map(|index| frame_path(dir, index, 3)).collect();
+        assert_eq!(names, ["frame_0000.png", "frame_0001.png", "frame_0002.png"].iter().map(|name| dir.join(name)).collect::<Vec<_>>());
+        // Wider when there are more frames than four digits can number
+        assert_eq!(frame_path(dir, 7, 10_000), dir.join("frame_0007.png"));
+        assert_eq!(frame_path(dir, 7, 10_001), dir.join("frame_00007.png"));
+    }
+
+    #[test]
+    fn frames_spread_over_the_keypoints() {
+        let mut export = FrameExport::new(PathBuf::from("frames"), 5);
+        let mut shown = Vec::new();
+        while !export.is_finished() {
+            shown.push(export.keypoint_index(101));
+            export.next_frame += 1;
+        }
+        assert_eq!(shown, vec![0, 25, 50, 75, 100]);
+        assert_eq!(FrameExport::new(PathBuf::from("frames"), 1).keypoint_index(101), 0);
+    }
+}
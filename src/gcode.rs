@@ -0,0 +1,860 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use nalgebra::{Isometry3, Point2, Point3, Vector2};
+use ncollide3d::query::{PointQuery, Ray, RayCast};
+use ncollide3d::shape::TriMesh;
+use crate::cam_job::{CAMJOB, Keypoint, Units};
+use crate::errors::CAMError;
+use crate::stl_operations::{get_bounds, indexed_mesh_to_trimesh, is_point_inside_model};
+
+/// How F words are interpreted by the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedMode {
+    /// G94: F is a feed rate in units per minute.
+    UnitsPerMinute,
+    /// G93: F is the reciprocal of each move's duration in minutes, given on every cut.
+    InverseTime,
+    /// G95: F is the distance travelled per spindle revolution.
+    UnitsPerRevolution,
+}
+
+impl FeedMode {
+    fn code(self) -> &'static str {
+        match self {
+            FeedMode::UnitsPerMinute => "G94",
+            FeedMode::InverseTime => "G93",
+            FeedMode::UnitsPerRevolution => "G95",
+        }
+    }
+}
+
+/// Frame the coordinates of a program are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinateFrame {
+    /// Part coordinates, as generated. The control applies the work offset.
+    PartRelative,
+    /// Machine coordinates: every position is mapped through `origin`, the part's
+    /// placement on the machine (e.g. the viewer's `job_origin`).
+    MachineAbsolute { origin: Isometry3<f32> },
+}
+
+impl CoordinateFrame {
+    fn apply(&self, position: &Point3<f32>) -> Point3<f32> {
+        match self {
+            CoordinateFrame::PartRelative => *position,
+            CoordinateFrame::MachineAbsolute { origin } => origin * position,
+        }
+    }
+}
+
+/// Settings for turning a built job into a 3-axis G-code program.
+#[derive(Debug, Clone)]
+pub struct GcodeOptions {
+    /// Distance above the top of the stock used for rapid moves, in millimetres.
+    pub clearance: f32,
+    /// Cutting feed rate in millimetres per minute, used when a task's tool isn't in the library.
+    pub feed_rate: f32,
+    /// Spindle speed in RPM.
+    pub spindle_speed: f32,
+    pub feed_mode: FeedMode,
+    /// Gap between the end of one task and the start of the next, with the same tool,
+    /// below which the tool stays at cutting depth instead of retracting, as long as
+    /// the straight move doesn't pass through the target. Zero always retracts.
+    pub keep_down_distance: f32,
+    /// Longest program the controller accepts, in lines. Longer jobs are split into
+    /// several programs by `export_gcode_programs`.
+    pub max_lines: Option<usize>,
+    /// Safe Z and the clearance are measured in this frame too. The park position is
+    /// always taken as given.
+    pub coordinate_frame: CoordinateFrame,
+    /// Runs of three or more cuts in a plane parallel to XY that lie on a
+    /// circle within this distance, in millimetres, are written as one G2/G3 arc.
+    /// `None` writes every cut as G1.
+    pub arc_tolerance: Option<f32>,
+    /// Feed rate in millimetres per minute for keypoints on finishing passes (see
+    /// `Keypoint::finishing`). `None` cuts them at the tool's feed rate.
+    pub finishing_feed_rate: Option<f32>,
+}
+
+impl Default for GcodeOptions {
+    fn default() -> Self {
+        GcodeOptions {
+            clearance: 5.0,
+            feed_rate: 500.0,
+            spindle_speed: 10000.0,
+            feed_mode: FeedMode::UnitsPerMinute,
+            keep_down_distance: 0.0,
+            max_lines: None,
+            coordinate_frame: CoordinateFrame::PartRelative,
+            arc_tolerance: None,
+            finishing_feed_rate: None,
+        }
+    }
+}
+
+/// Generates a program visiting every task's keypoints in order. Each task is entered
+/// with a rapid at safe Z and a plunge to its first keypoint, and left with a retract,
+/// unless the next task starts within `keep_down_distance` (see `GcodeOptions`).
+/// Before every tool change the tool retracts and rapids to the job's park position.
+/// `max_lines` is ignored here; see `export_gcode_programs`.
+pub fn export_gcode(job: &CAMJOB, options: &GcodeOptions) -> Result<String, CAMError> {
+    let program = Program::new(job, options)?;
+    let (header, footer) = (program.header(), program.footer());
+    let lines = header.into_iter()
+        .chain(program.body.into_iter().map(|line| line.text))
+        .chain(footer);
+    Ok(lines.map(|line| line + "\n").collect())
+}
+
+/// The job as one or more programs, split so none is longer than `max_lines`. Splits
+/// only happen after a cut; the tool retracts and the program ends, and the next one
+/// loads the tool again, starts the spindle and plunges back to where the cut stopped.
+pub fn export_gcode_programs(job: &CAMJOB, options: &GcodeOptions) -> Result<Vec<String>, CAMError> {
+    let max_lines = match options.max_lines {
+        Some(max_lines) => max_lines,
+        None => return Ok(vec![export_gcode(job, options)?]),
+    };
+    let program = Program::new(job, options)?;
+    let footer = program.footer();
+
+    let mut programs = Vec::new();
+    let mut preamble = program.header();
+    let mut start = 0;
+    loop {
+        let capacity = max_lines.saturating_sub(preamble.len() + footer.len());
+        if capacity == 0 {
+            return Err(CAMError::ProcessingError(format!("max_lines {} leaves no room for moves", max_lines)));
+        }
+        let end = if program.body.len() - start <= capacity {
+            program.body.len()
+        } else {
+            (start..start + capacity)
+                .rev()
+                .find(|&i| program.body[i].resume.is_some())
+                .map(|i| i + 1)
+                .ok_or_else(|| CAMError::ProcessingError(format!("no safe point to split within {} lines", max_lines)))?
+        };
+
+        let lines = preamble.iter().cloned()
+            .chain(program.body[start..end].iter().map(|line| line.text.clone()))
+            .chain(footer.iter().cloned());
+        programs.push(lines.map(|line| line + "\n").collect());
+
+        if end == program.body.len() {
+            return Ok(programs);
+        }
+        preamble = program.resume(program.body[end - 1].resume.as_ref().unwrap());
+        start = end;
+    }
+}
+
+/// Writes the program to `path`. With `max_lines` set, the programs from
+/// `export_gcode_programs` are written next to it instead, numbered from 1
+/// (`part.nc` becomes `part_1.nc`, `part_2.nc`, ...).
+pub fn write_gcode(job: &CAMJOB, options: &GcodeOptions, path: &Path) -> Result<(), CAMError> {
+    if options.max_lines.is_none() {
+        fs::write(path, export_gcode(job, options)?)?;
+        return Ok(());
+    }
+    for (i, program) in export_gcode_programs(job, options)?.iter().enumerate() {
+        fs::write(numbered_path(path, i + 1), program)?;
+    }
+    Ok(())
+}
+
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+/// Tool state right after a cut, enough to pick the cut up again in a new program.
+struct Resume {
+    tool_id: usize,
+    position: Point3<f32>,
+    feed_rate: f32,
+}
+
+struct ProgramLine {
+    text: String,
+    /// Set on cuts, after which the program may be split.
+    resume: Option<Resume>,
+}
+
+impl ProgramLine {
+    fn new(text: String) -> Self {
+        ProgramLine { text, resume: None }
+    }
+}
+
+/// The moves of a job with everything the header, footer and split points need.
+struct Program<'a> {
+    options: &'a GcodeOptions,
+    units: Units,
+    /// In millimetres.
+    safe_z: f32,
+    park: Point3<f32>,
+    body: Vec<ProgramLine>,
+}
+
+impl<'a> Program<'a> {
+    fn new(job: &CAMJOB, options: &'a GcodeOptions) -> Result<Self, CAMError> {
+        let safe_z = safe_z(job, options)?;
+        let park = job.tool_change_position;
+        let frame = options.coordinate_frame;
+        let mut out = Vec::new();
+        let mut current_tool = None;
+        // Where the previous task left the tool at cutting depth, if it hasn't retracted.
+        let mut last_cut: Option<Point3<f32>> = None;
+        let target = match &job.target_mesh {
+            Some(mesh) if options.keep_down_distance > 0.0 => Some(indexed_mesh_to_trimesh(mesh)),
+            _ => None,
+        };
+
+        if options.feed_mode == FeedMode::UnitsPerRevolution && options.spindle_speed <= 0.0 {
+            return Err(CAMError::ProcessingError("feed per revolution needs a positive spindle speed".to_string()));
+        }
+
+        // Everything below is computed in millimetres and part coordinates, and moved
+        // into the output frame and units as it is written.
+        let units = job.units;
+        let safe_z_out = units.from_mm(safe_z);
+        let mut program = Program { options, units, safe_z, park, body: Vec::new() };
+
+        for (task_index, task) in job.get_tasks().iter().enumerate() {
+            let keypoints = job.task_keypoints(task_index);
+            let first = match keypoints.first() {
+                Some(keypoint) => keypoint.position,
+                None => continue,
+            };
+
+            let tool_id = task.get_tool_id();
+            if current_tool != Some(tool_id) {
+                if current_tool.is_some() {
+                    out.push(ProgramLine::new("M5".to_string()));
+                }
+                out.extend(program.tool_change(tool_id).into_iter().map(ProgramLine::new));
+                current_tool = Some(tool_id);
+                last_cut = None;
+            }
+
+            let tool_feed_rate = job.get_tool(tool_id).map_or(options.feed_rate, |tool| tool.feed_rate);
+            let feed_rate_for = |keypoint: &Keypoint| match options.finishing_feed_rate {
+                Some(finishing_feed_rate) if keypoint.finishing => finishing_feed_rate,
+                _ => tool_feed_rate,
+            };
+            let cut = |text: String, position: Point3<f32>, feed_rate: f32| ProgramLine {
+                text,
+                resume: Some(Resume { tool_id, position: frame.apply(&position), feed_rate }),
+            };
+            // Feed rate last written in this task; modal modes restate it when it changes
+            let mut stated_feed: Option<f32> = None;
+            let entry_feed_rate = feed_rate_for(&keypoints[0]);
+            match last_cut {
+                Some(end) if !keypoints[0].rapid && can_stay_down(target.as_ref(), &end, &first, options.keep_down_distance) => {
+                    if let Some(feed) = feed_word(&end, &first, entry_feed_rate, options, units, true) {
+                        out.push(cut(format!("{}{}", linear_move(&frame.apply(&first), units), feed), first, entry_feed_rate));
+                        stated_feed = Some(entry_feed_rate);
+                    }
+                }
+                _ => {
+                    out.push(ProgramLine::new(format!("G0 Z{:.4}", safe_z_out)));
+                    let entry = frame.apply(&first);
+                    out.push(ProgramLine::new(format!("G0 X{:.4} Y{:.4}", units.from_mm(entry.x), units.from_mm(entry.y))));
+                    if let Some(plunge) = program.plunge(&entry, entry_feed_rate) {
+                        out.push(cut(plunge, first, entry_feed_rate));
+                        stated_feed = Some(entry_feed_rate);
+                    }
+                }
+            }
+            // Arcs are fitted in the output frame, where G17 puts them in the XY plane
+            let moves: Vec<Point3<f32>> = keypoints[1..].iter().map(|keypoint| frame.apply(&keypoint.position)).collect();
+            let mut previous = first;
+            let mut i = 0;
+            while i < moves.len() {
+                let keypoint = &keypoints[i + 1];
+                if keypoint.rapid {
+                    out.push(ProgramLine::new(format!("G0 Z{:.4}", safe_z_out)));
+                    out.push(ProgramLine::new(format!("G0 X{:.4} Y{:.4}", units.from_mm(moves[i].x), units.from_mm(moves[i].y))));
+                    out.push(ProgramLine::new(format!("G0 Z{:.4}", units.from_mm(moves[i].z))));
+                    previous = keypoint.position;
+                    i += 1;
+                    continue;
+                }
+                let feed_rate = feed_rate_for(keypoint);
+                let restate = stated_feed != Some(feed_rate);
+                let start = frame.apply(&previous);
+                // An arc is cut at one feed rate, so it can't span a roughing/finishing change
+                // or a rapid
+                let run = keypoints[i + 1..]
+                    .iter()
+                    .take_while(|next| next.finishing == keypoint.finishing && !next.rapid)
+                    .count();
+                if let Some(arc) = options.arc_tolerance.and_then(|tolerance| fit_arc(&start, &moves[i..i + run], tolerance)) {
+                    let end = keypoints[i + arc.count].position;
+                    let feed = feed_for_distance(arc.length, feed_rate, options, units, restate);
+                    out.push(cut(format!("{}{}", arc_move(&start, &moves[i + arc.count - 1], &arc, units), feed), end, feed_rate));
+                    stated_feed = Some(feed_rate);
+                    previous = end;
+                    i += arc.count;
+                    continue;
+                }
+                if let Some(feed) = feed_word(&previous, &keypoint.position, feed_rate, options, units, restate) {
+                    out.push(cut(format!("{}{}", linear_move(&moves[i], units), feed), keypoint.position, feed_rate));
+                    stated_feed = Some(feed_rate);
+                }
+                previous = keypoint.position;
+                i += 1;
+            }
+            last_cut = Some(previous);
+        }
+
+        program.body = out;
+        Ok(program)
+    }
+
+    fn header(&self) -> Vec<String> {
+        let unit_code = match self.units {
+            Units::Millimeters => "G21",
+            Units::Inches => "G20",
+        };
+        vec![
+            format!("{} G90 {}", unit_code, self.options.feed_mode.code()),
+            format!("G0 Z{:.4}", self.units.from_mm(self.safe_z)),
+        ]
+    }
+
+    fn footer(&self) -> Vec<String> {
+        vec![format!("G0 Z{:.4}", self.units.from_mm(self.safe_z)), "M5".to_string(), "M30".to_string()]
+    }
+
+    /// Retract, rapid to the park position, load `tool_id` and start the spindle.
+    fn tool_change(&self, tool_id: usize) -> Vec<String> {
+        let units = self.units;
+        let park_z = self.park.z.max(self.safe_z);
+        vec![
+            format!("G0 Z{:.4}", units.from_mm(self.safe_z)),
+            format!("G0 X{:.4} Y{:.4} Z{:.4}", units.from_mm(self.park.x), units.from_mm(self.park.y), units.from_mm(park_z)),
+            format!("M6 T{}", tool_id),
+            format!("M3 S{:.0}", self.options.spindle_speed),
+        ]
+    }
+
+    /// Feed from safe Z straight down to `to`, stating the feed rate.
+    fn plunge(&self, to: &Point3<f32>, feed_rate: f32) -> Option<String> {
+        let plunge_start = Point3::new(to.x, to.y, self.safe_z);
+        feed_word(&plunge_start, to, feed_rate, self.options, self.units, true)
+            .map(|feed| format!("G1 Z{:.4}{}", self.units.from_mm(to.z), feed))
+    }
+
+    /// Header of a program continuing after a split at `resume`.
+    fn resume(&self, resume: &Resume) -> Vec<String> {
+        let mut lines = self.header();
+        lines.extend(self.tool_change(resume.tool_id));
+        let position = resume.position.map(|value| self.units.from_mm(value));
+        lines.push(format!("G0 X{:.4} Y{:.4}", position.x, position.y));
+        lines.extend(self.plunge(&resume.position, resume.feed_rate));
+        lines
+    }
+}
+
+fn linear_move(position: &Point3<f32>, units: Units) -> String {
+    let position = position.map(|value| units.from_mm(value));
+    format!("G1 X{:.4} Y{:.4} Z{:.4}", position.x, position.y, position.z)
+}
+
+/// Fewest cuts worth replacing with an arc.
+const MIN_ARC_POINTS: usize = 3;
+
+/// A run of cuts written as a single G2/G3.
+struct Arc {
+    /// Number of cuts the arc replaces.
+    count: usize,
+    center: Point2<f32>,
+    clockwise: bool,
+    /// In millimetres.
+    length: f32,
+}
+
+/// The longest arc from `start` through the leading points of `moves`, if at least
+/// `MIN_ARC_POINTS` of them fit one within `tolerance`.
+fn fit_arc(start: &Point3<f32>, moves: &[Point3<f32>], tolerance: f32) -> Option<Arc> {
+    let mut best = None;
+    for count in MIN_ARC_POINTS..=moves.len() {
+        match arc_through(start, &moves[..count], tolerance) {
+            Some(arc) => best = Some(arc),
+            None => break,
+        }
+    }
+    best
+}
+
+/// The arc from `start` through every point of `points`, in order. The circle is taken
+/// through the start, middle and last point; every point must be at `start`'s height
+/// and on the circle within `tolerance`, the sweep must keep one direction and stay
+/// under a full turn, and no segment may bow out from its chord by more than
+/// `tolerance`, so a few far-apart corners of a polygon aren't rounded off. Points
+/// that all lie within `tolerance` of the line from start to end are left as cuts,
+/// since rounding error would otherwise fit them to a circle of enormous radius.
+fn arc_through(start: &Point3<f32>, points: &[Point3<f32>], tolerance: f32) -> Option<Arc> {
+    if points.iter().any(|p| (p.z - start.z).abs() > tolerance) {
+        return None;
+    }
+    let start = start.xy();
+    let middle = points[(points.len() - 1) / 2].xy();
+    let end = points[points.len() - 1].xy();
+    let chord = (end - start).try_normalize(f32::EPSILON)?;
+    if points.iter().all(|p| chord.perp(&(p.xy() - start)).abs() <= tolerance) {
+        return None;
+    }
+    let center = circle_center(&start, &middle, &end)?;
+    let radius = (start - center).norm();
+
+    let mut sweep: f32 = 0.0;
+    let mut previous = start;
+    for point in points.iter().map(|p| p.xy()) {
+        if ((point - center).norm() - radius).abs() > tolerance {
+            return None;
+        }
+        let (from, to) = (previous - center, point - center);
+        let angle = from.perp(&to).atan2(from.dot(&to));
+        if angle.abs() <= f32::EPSILON || (sweep != 0.0 && angle.signum() != sweep.signum()) {
+            return None;
+        }
+        if radius * (1.0 - (angle / 2.0).cos()) > tolerance {
+            return None;
+        }
+        sweep += angle;
+        previous = point;
+    }
+    if sweep.abs() >= 2.0 * std::f32::consts::PI {
+        return None;
+    }
+
+    Some(Arc { count: points.len(), center, clockwise: sweep < 0.0, length: radius * sweep.abs() })
+}
+
+/// Center of the circle through three points, or `None` if they are collinear.
+fn circle_center(a: &Point2<f32>, b: &Point2<f32>, c: &Point2<f32>) -> Option<Point2<f32>> {
+    let (ab, ac) = (b - a, c - a);
+    let d = 2.0 * ab.perp(&ac);
+    if d.abs() <= f32::EPSILON {
+        return None;
+    }
+    let x = (ac.y * ab.norm_squared() - ab.y * ac.norm_squared()) / d;
+    let y = (ab.x * ac.norm_squared() - ac.x * ab.norm_squared()) / d;
+    Some(a + Vector2::new(x, y))
+}
+
+/// G2/G3 from `start` to `end` about `arc`'s center, with I/J relative to `start`.
+fn arc_move(start: &Point3<f32>, end: &Point3<f32>, arc: &Arc, units: Units) -> String {
+    let code = if arc.clockwise { "G2" } else { "G3" };
+    let end = end.map(|value| units.from_mm(value));
+    let offset = (arc.center - start.xy()).map(|value| units.from_mm(value));
+    format!("{} X{:.4} Y{:.4} Z{:.4} I{:.4} J{:.4}", code, end.x, end.y, end.z, offset.x, offset.y)
+}
+
+/// Whether the tool can feed straight from `from` to `to` at depth: the gap is within
+/// `max_distance` and the segment neither crosses nor passes through the target. Both
+/// ends lie on the surface, so hits right at the ends are ignored. The tool's radius
+/// isn't considered, only its center line.
+fn can_stay_down(target: Option<&TriMesh<f32>>, from: &Point3<f32>, to: &Point3<f32>, max_distance: f32) -> bool {
+    let distance = (to - from).norm();
+    if distance > max_distance {
+        return false;
+    }
+    let target = match target {
+        Some(target) if distance > f32::EPSILON => target,
+        _ => return true,
+    };
+
+    let direction = (to - from) / distance;
+    let margin = distance * 1e-3;
+    let ray = Ray::new(from + direction * margin, direction);
+    if target.toi_with_ray(&Isometry3::identity(), &ray, distance - 2.0 * margin, false).is_some() {
+        return false;
+    }
+    // With no crossing in between, the segment is all outside, all inside, or runs
+    // along the surface, where the tool rides as it does while cutting
+    let midpoint = nalgebra::center(from, to);
+    target.distance_to_point(&Isometry3::identity(), &midpoint, false) <= margin || !is_point_inside_model(&midpoint, &direction, target)
+}
+
+/// F word for a cut from `from` to `to`, or `None` for a zero-length move, which
+/// has no inverse time. Modal modes only restate the feed when `first` is set: on
+/// the first cut of a task and whenever the feed rate changes.
+fn feed_word(from: &Point3<f32>, to: &Point3<f32>, feed_rate: f32, options: &GcodeOptions, units: Units, first: bool) -> Option<String> {
+    let distance = (to - from).norm();
+    if distance <= f32::EPSILON {
+        return None;
+    }
+    Some(feed_for_distance(distance, feed_rate, options, units, first))
+}
+
+/// F word for a cut `distance` millimetres long; see `feed_word`.
+fn feed_for_distance(distance: f32, feed_rate: f32, options: &GcodeOptions, units: Units, first: bool) -> String {
+    match options.feed_mode {
+        FeedMode::InverseTime => format!(" F{:.4}", feed_rate / distance),
+        FeedMode::UnitsPerMinute if first => format!(" F{:.1}", units.from_mm(feed_rate)),
+        FeedMode::UnitsPerRevolution if first => format!(" F{:.4}", units.from_mm(feed_rate) / options.spindle_speed),
+        _ => String::new(),
+    }
+}
+
+/// Rapid height: `clearance` above the stock, or above the target if there's no stock,
+/// in the output frame.
+fn safe_z(job: &CAMJOB, options: &GcodeOptions) -> Result<f32, CAMError> {
+    let mesh = job.get_stock_mesh().or(job.target_mesh.as_ref()).ok_or(CAMError::MeshNotSet)?;
+    let top = match options.coordinate_frame {
+        CoordinateFrame::PartRelative => get_bounds(mesh)?.1.z,
+        frame => mesh
+            .vertices
+            .iter()
+            .map(|v| frame.apply(&Point3::new(v[0], v[1], v[2])).z)
+            .fold(f32::NEG_INFINITY, f32::max),
+    };
+    Ok(top + options.clearance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cam_job::CAMTask;
+    use crate::tasks::{CircularClearing, ContourTrace, DrillHole, DrillHoles};
+    use crate::test_support::{box_mesh, cylinder_mesh, temp_path, trimesh};
+    use crate::tool::Tool;
+    use nalgebra::Vector3;
+
+    /// A job on a 10 thick plate whose top is at Z = 0, with tools 0 and 1 in the
+    /// library.
+    fn plate_job() -> CAMJOB {
+        let mut job = CAMJOB::new();
+        job.set_mesh(box_mesh(Point3::new(-20.0, -20.0, -10.0), Point3::new(20.0, 20.0, 0.0))).unwrap();
+        job.add_tool(Tool::new(0, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        job.add_tool(Tool::new(1, "Endmill".to_string(), 20.0, 6.0).with_feed_rate(800.0)).unwrap();
+        job
+    }
+
+    /// A contour around the plate at `z`, cut with `tool_id`.
+    fn contour(job: &CAMJOB, z: f32, tool_id: usize) -> Box<dyn CAMTask> {
+        Box::new(ContourTrace::new(12, Point3::new(0.0, 0.0, z), Vector3::z(), job.target_mesh.as_ref().unwrap(), tool_id))
+    }
+
+    #[test]
+    fn tool_changes_retract_and_park() {
+        let mut job = plate_job();
+        job.set_tool_change_position(Point3::new(-50.0, 40.0, 2.0));
+        job.add_task(contour(&job, -5.0, 0));
+        job.add_task(contour(&job, -4.0, 0));
+        job.add_task(contour(&job, -2.0, 1));
+        job.build().unwrap();
+
+        let options = GcodeOptions::default();
+        let gcode = export_gcode(&job, &options).unwrap();
+        let safe_z = safe_z(&job, &options).unwrap();
+        assert!(safe_z > 2.0);
+        let lines: Vec<&str> = gcode.lines().collect();
+        // The first two tasks share a tool, so it's only loaded once for both
+        let changes: Vec<usize> = lines.iter().enumerate().filter(|(_, line)| line.starts_with("M6")).map(|(i, _)| i).collect();
+        assert_eq!(changes.len(), 2);
+        for (&change, tool_id) in changes.iter().zip(0..) {
+            assert_eq!(lines[change], format!("M6 T{}", tool_id));
+            assert_eq!(lines[change - 2], format!("G0 Z{:.4}", safe_z));
+            // The park position is never below safe Z
+            assert_eq!(lines[change - 1], format!("G0 X-50.0000 Y40.0000 Z{:.4}", safe_z));
+            assert_eq!(lines[change + 1], "M3 S10000");
+        }
+        // The spindle stops before the second change
+        assert_eq!(lines[changes[1] - 3], "M5");
+    }
+
+    #[test]
+    fn feed_words_follow_the_feed_mode() {
+        let mut job = plate_job();
+        job.add_task(contour(&job, -5.0, 1));
+        job.build().unwrap();
+        let keypoints = job.gather_keypoints();
+        let (first, second) = (keypoints[0].position, keypoints[1].position);
+        let cuts = |feed_mode: FeedMode| {
+            let gcode = export_gcode(&job, &GcodeOptions { feed_mode, ..GcodeOptions::default() }).unwrap();
+            assert!(gcode.starts_with(&format!("G21 G90 {}", feed_mode.code())));
+            gcode.lines().filter(|line| line.starts_with("G1")).map(str::to_string).collect::<Vec<_>>()
+        };
+
+        // Plunging from safe Z to the first keypoint, then on to the second
+        let plunge = safe_z(&job, &GcodeOptions::default()).unwrap() - first.z;
+        let per_minute = cuts(FeedMode::UnitsPerMinute);
+        assert_eq!(per_minute[0], format!("G1 Z{:.4} F800.0", first.z));
+        assert_eq!(per_minute[1], linear_move(&second, Units::Millimeters));
+        let inverse_time = cuts(FeedMode::InverseTime);
+        assert_eq!(inverse_time[0], format!("G1 Z{:.4} F{:.4}", first.z, 800.0 / plunge));
+        assert_eq!(inverse_time[1], format!("{} F{:.4}", linear_move(&second, Units::Millimeters), 800.0 / (second - first).norm()));
+        assert!(inverse_time.iter().all(|line| line.contains(" F")));
+        let per_revolution = cuts(FeedMode::UnitsPerRevolution);
+        assert_eq!(per_revolution[0], format!("G1 Z{:.4} F0.0800", first.z));
+        assert_eq!(per_revolution[1], linear_move(&second, Units::Millimeters));
+
+        let stopped = GcodeOptions { feed_mode: FeedMode::UnitsPerRevolution, spindle_speed: 0.0, ..GcodeOptions::default() };
+        assert!(matches!(export_gcode(&job, &stopped), Err(CAMError::ProcessingError(_))));
+    }
+
+    #[test]
+    fn inch_jobs_write_g20_and_convert_coordinates() {
+        let mut job = plate_job();
+        job.add_task(contour(&job, -5.0, 1));
+        job.build().unwrap();
+        let first = job.gather_keypoints()[0].position;
+        let options = GcodeOptions::default();
+        let safe_z = safe_z(&job, &options).unwrap();
+
+        job.set_units(Units::Inches);
+        let gcode = export_gcode(&job, &options).unwrap();
+        assert!(gcode.starts_with("G20 G90 G94"));
+        assert!(gcode.contains(&format!("G0 Z{:.4}", safe_z / 25.4)));
+        assert!(gcode.contains(&format!("G1 Z{:.4} F{:.1}", first.z / 25.4, 800.0 / 25.4)));
+    }
+
+    #[test]
+    fn tasks_close_together_stay_down() {
+        let mut job = plate_job();
+        for z in [-2.0, -4.0].iter().copied() {
+            let mesh = job.target_mesh.clone().unwrap();
+            job.add_task(Box::new(ContourTrace::new(16, Point3::new(0.0, 0.0, z), Vector3::z(), &mesh, 1)));
+        }
+        job.build().unwrap();
+        let entries = |keep_down_distance: f32| {
+            let gcode = export_gcode(&job, &GcodeOptions { keep_down_distance, ..GcodeOptions::default() }).unwrap();
+            gcode.lines().filter(|line| line.starts_with("G0 X")).count()
+        };
+
+        // One entry for each task, or only the first when the second starts nearby
+        assert_eq!(entries(0.0), 3);
+        assert_eq!(entries(10.0), 2);
+    }
+
+    #[test]
+    fn staying_down_never_crosses_the_target() {
+        let target = trimesh(&box_mesh(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 5.0)));
+        let beside = (Point3::new(6.0, 0.0, 0.0), Point3::new(6.0, 2.0, 0.0));
+        assert!(can_stay_down(Some(&target), &beside.0, &beside.1, 5.0));
+        assert!(!can_stay_down(Some(&target), &beside.0, &beside.1, 1.0));
+        // Both ends on the surface, with the part in between
+        let across = (Point3::new(-5.0, 0.0, 0.0), Point3::new(5.0, 0.0, 0.0));
+        assert!(!can_stay_down(Some(&target), &across.0, &across.1, 20.0));
+        assert!(can_stay_down(None, &across.0, &across.1, 20.0));
+    }
+
+    #[test]
+    fn long_jobs_split_into_programs_that_restore_the_tool() {
+        let mut job = plate_job();
+        job.add_task(contour(&job, -5.0, 1));
+        job.add_task(contour(&job, -2.0, 1));
+        job.build().unwrap();
+        let options = GcodeOptions { max_lines: Some(20), ..GcodeOptions::default() };
+        let programs = export_gcode_programs(&job, &options).unwrap();
+        assert!(programs.len() > 1);
+
+        let cuts = |gcode: &str| gcode.lines().filter(|line| line.starts_with("G1 X")).map(str::to_string).collect::<Vec<_>>();
+        let whole = export_gcode(&job, &GcodeOptions::default()).unwrap();
+        assert_eq!(programs.iter().flat_map(|program| cuts(program)).collect::<Vec<_>>(), cuts(&whole));
+
+        let retract = format!("G0 Z{:.4}", safe_z(&job, &options).unwrap());
+        for (i, program) in programs.iter().enumerate() {
+            let lines: Vec<&str> = program.lines().collect();
+            assert!(lines.len() <= 20);
+            assert_eq!(lines[..2], ["G21 G90 G94", retract.as_str()]);
+            assert_eq!(lines[lines.len() - 3..], [retract.as_str(), "M5", "M30"]);
+            assert!(lines.contains(&"M6 T1") && lines.contains(&"M3 S10000"));
+            if i > 0 {
+                // Back down to where the previous program stopped cutting
+                let stop = programs[i - 1].lines().rev().find(|line| line.starts_with("G1 X")).unwrap();
+                let z_at = stop.rfind(" Z").unwrap();
+                let entry = lines.iter().position(|line| *line == "M3 S10000").unwrap() + 1;
+                assert_eq!(lines[entry], format!("G0 {}", &stop[3..z_at]));
+                assert_eq!(lines[entry + 1], format!("G1{} F800.0", &stop[z_at..]));
+            }
+        }
+
+        let path = temp_path("split.nc");
+        write_gcode(&job, &options, &path).unwrap();
+        for number in 1..=programs.len() {
+            let numbered = numbered_path(&path, number);
+            assert_eq!(fs::read_to_string(&numbered).unwrap(), programs[number - 1]);
+            fs::remove_file(numbered).unwrap();
+        }
+        assert!(!path.exists() && !numbered_path(&path, programs.len() + 1).exists());
+    }
+
+    #[test]
+    fn drill_holes_are_joined_by_rapids_at_safe_z() {
+        let mut job = plate_job();
+        let holes = vec![DrillHole::new(Point3::new(-10.0, 0.0, 0.0), 5.0), DrillHole::new(Point3::new(10.0, 0.0, 0.0), 5.0)];
+        job.add_task(Box::new(DrillHoles::new(holes, 2.0, 0).with_retract_height(1.0)));
+        job.build().unwrap();
+
+        let options = GcodeOptions { arc_tolerance: Some(0.1), ..GcodeOptions::default() };
+        let gcode = export_gcode(&job, &options).unwrap();
+        let safe_z = format!("G0 Z{:.4}", safe_z(&job, &options).unwrap());
+        let lines: Vec<&str> = gcode.lines().collect();
+        let transit = lines.iter().position(|line| *line == "G0 X10.0000 Y0.0000").expect("no rapid to the second hole");
+        assert_eq!(lines[transit - 1], safe_z);
+        assert_eq!(lines[transit + 1], "G0 Z1.0000");
+        // Nothing feeds sideways: every G1 stays on the axis of the hole it drills
+        let on_axis = |lines: &[&str], x: &str| lines.iter().filter(|line| line.starts_with("G1 X")).all(|line| line.starts_with(x));
+        assert!(on_axis(&lines[..transit], "G1 X-10.0000 Y0.0000"), "{}", gcode);
+        assert!(on_axis(&lines[transit..], "G1 X10.0000 Y0.0000"), "{}", gcode);
+        // An arc never spans a rapid
+        assert!(!lines.iter().any(|line| line.starts_with("G2 ") || line.starts_with("G3 ")), "{}", gcode);
+    }
+
+    #[test]
+    fn machine_frame_moves_every_coordinate_by_the_origin() {
+        let mut job = plate_job();
+        let holes = vec![DrillHole::new(Point3::new(3.0, 4.0, 0.0), 2.0), DrillHole::new(Point3::new(-3.0, 4.0, 0.0), 2.0)];
+        job.add_task(Box::new(DrillHoles::new(holes, 2.0, 1)));
+        job.build().unwrap();
+        let cut_positions = |frame: CoordinateFrame| -> Vec<Point3<f32>> {
+            let gcode = export_gcode(&job, &GcodeOptions { coordinate_frame: frame, ..GcodeOptions::default() }).unwrap();
+            gcode
+                .lines()
+                .filter(|line| line.starts_with("G1 X"))
+                .map(|line| {
+                    let value = |axis: char| line.split(' ').find(|word| word.starts_with(axis)).unwrap()[1..].parse::<f32>().unwrap();
+                    Point3::new(value('X'), value('Y'), value('Z'))
+                })
+                .collect()
+        };
+        // Every keypoint after the entry, which is plunged to stating only Z, but the
+        // rapid to the second hole
+        let cuts: Vec<Point3<f32>> = job.task_keypoints(0).iter().skip(1).filter(|keypoint| !keypoint.rapid).map(|keypoint| keypoint.position).collect();
+        let origin = Isometry3::translation(100.0, 50.0, -20.0);
+
+        let close = |a: &[Point3<f32>], b: &[Point3<f32>]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).norm() < 1e-3);
+        assert!(close(&cut_positions(CoordinateFrame::PartRelative), &cuts));
+        let shifted: Vec<Point3<f32>> = cuts.iter().map(|position| origin * position).collect();
+        assert!(close(&cut_positions(CoordinateFrame::MachineAbsolute { origin }), &shifted));
+
+        // Safe Z is measured in the machine frame too
+        let options = GcodeOptions { coordinate_frame: CoordinateFrame::MachineAbsolute { origin }, ..GcodeOptions::default() };
+        let gcode = export_gcode(&job, &options).unwrap();
+        assert_eq!(gcode.lines().nth(1), Some("G0 Z-14.0000"));
+        assert!(gcode.lines().any(|line| line == "G0 X97.0000 Y54.0000"), "{}", gcode);
+    }
+
+    /// Value of `axis`'s word in a G-code line.
+    fn word(line: &str, axis: char) -> f32 {
+        line.split(' ').find(|word| word.starts_with(axis)).unwrap()[1..].parse().unwrap()
+    }
+
+    #[test]
+    fn circle_center_of_three_points() {
+        let center = circle_center(&Point2::new(5.0, 2.0), &Point2::new(1.0, 6.0), &Point2::new(-3.0, 2.0)).unwrap();
+        assert!((center - Point2::new(1.0, 2.0)).norm() < 1e-5);
+        assert!(circle_center(&Point2::new(0.0, 0.0), &Point2::new(1.0, 1.0), &Point2::new(3.0, 3.0)).is_none());
+    }
+
+    #[test]
+    fn points_on_a_circle_fit_one_arc_in_their_direction() {
+        let on_circle = |degrees: f32| Point3::new(2.0 + 10.0 * degrees.to_radians().cos(), -1.0 + 10.0 * degrees.to_radians().sin(), -3.0);
+        let start = on_circle(0.0);
+        let counter_clockwise: Vec<Point3<f32>> = (1..=8).map(|i| on_circle(i as f32 * 10.0)).collect();
+        let arc = fit_arc(&start, &counter_clockwise, 0.05).unwrap();
+        assert_eq!(arc.count, 8);
+        assert!(!arc.clockwise);
+        assert!((arc.center - Point2::new(2.0, -1.0)).norm() < 1e-3);
+        assert!((arc.length - 10.0 * 80f32.to_radians()).abs() < 1e-3);
+        let line = arc_move(&start, &counter_clockwise[7], &arc, Units::Millimeters);
+        assert!(line.starts_with("G3 "), "{}", line);
+        assert!((word(&line, 'X') - counter_clockwise[7].x).abs() < 1e-3 && (word(&line, 'Y') - counter_clockwise[7].y).abs() < 1e-3);
+        // I/J lead from the start to the center
+        assert!((word(&line, 'I') + 10.0).abs() < 1e-3 && word(&line, 'J').abs() < 1e-3, "{}", line);
+
+        let clockwise: Vec<Point3<f32>> = (1..=8).map(|i| on_circle(i as f32 * -10.0)).collect();
+        let arc = fit_arc(&start, &clockwise, 0.05).unwrap();
+        assert!(arc.clockwise);
+        assert!(arc_move(&start, &clockwise[7], &arc, Units::Millimeters).starts_with("G2 "));
+    }
+
+    #[test]
+    fn points_off_the_circle_end_the_arc() {
+        let on_circle = |degrees: f32| Point3::new(10.0 * degrees.to_radians().cos(), 10.0 * degrees.to_radians().sin(), 0.0);
+        let start = on_circle(0.0);
+        let mut points: Vec<Point3<f32>> = (1..=8).map(|i| on_circle(i as f32 * 10.0)).collect();
+        // Pulled 0.5 toward the center, past the tolerance
+        points[5] *= 0.95;
+        assert_eq!(fit_arc(&start, &points, 0.05).unwrap().count, 5);
+        // Too few on the circle before it to be worth an arc
+        points[2] *= 0.95;
+        assert!(fit_arc(&start, &points, 0.05).is_none());
+        // Straight runs and changes of height never fit
+        let straight: Vec<Point3<f32>> = (1..=4).map(|i| Point3::new(i as f32, 0.0, 0.0)).collect();
+        assert!(fit_arc(&Point3::origin(), &straight, 0.05).is_none());
+        let climbing: Vec<Point3<f32>> = (1..=4).map(|i| on_circle(i as f32 * 10.0) + Vector3::z() * i as f32).collect();
+        assert!(fit_arc(&start, &climbing, 0.05).is_none());
+    }
+
+    #[test]
+    fn round_contours_are_cut_as_arcs_about_their_center() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(cylinder_mesh(10.0, -10.0, 0.0, 64)).unwrap();
+        job.add_tool(Tool::new(1, "Endmill".to_string(), 20.0, 6.0)).unwrap();
+        let mesh = job.target_mesh.clone().unwrap();
+        job.add_task(Box::new(ContourTrace::new(32, Point3::new(0.0, 0.0, -5.0), Vector3::z(), &mesh, 1)));
+        job.build().unwrap();
+        let keypoints = job.task_keypoints(0);
+        let (first, second) = (keypoints[0].position, keypoints[1].position);
+        let counter_clockwise = first.xy().coords.perp(&second.xy().coords) > 0.0;
+
+        let gcode = export_gcode(&job, &GcodeOptions { arc_tolerance: Some(0.1), ..GcodeOptions::default() }).unwrap();
+        let arcs: Vec<&str> = gcode.lines().filter(|line| line.starts_with("G2 ") || line.starts_with("G3 ")).collect();
+        assert!(!arcs.is_empty(), "{}", gcode);
+        assert!(arcs.iter().all(|line| line.starts_with(if counter_clockwise { "G3 " } else { "G2 " })), "{}", gcode);
+        // The first arc starts where the contour is entered; I/J lead from there to the axis
+        let center = first.xy() + Vector2::new(word(arcs[0], 'I'), word(arcs[0], 'J'));
+        assert!(center.coords.norm() < 0.1, "{}", arcs[0]);
+        let end = Point2::new(word(arcs[0], 'X'), word(arcs[0], 'Y'));
+        assert!(((end - center).norm() - (first.xy() - center).norm()).abs() < 0.1);
+
+        // The square plate's sides don't lie on a circle, so its contour stays G1
+        let mut plate = plate_job();
+        let mesh = plate.target_mesh.clone().unwrap();
+        plate.add_task(Box::new(ContourTrace::new(32, Point3::new(0.0, 0.0, -5.0), Vector3::z(), &mesh, 1)));
+        plate.build().unwrap();
+        let gcode = export_gcode(&plate, &GcodeOptions { arc_tolerance: Some(0.1), ..GcodeOptions::default() }).unwrap();
+        assert!(gcode.lines().any(|line| line.starts_with("G1 X")));
+        assert!(!gcode.lines().any(|line| line.starts_with("G2 ") || line.starts_with("G3 ")), "{}", gcode);
+    }
+
+    #[test]
+    fn finishing_passes_are_fed_at_the_finishing_rate() {
+        let mut job = plate_job();
+        job.add_task(Box::new(
+            CircularClearing::new(Point3::new(0.0, 0.0, -6.0), Point3::new(0.0, 0.0, -4.0), 2, 35.0, 32, 3.0, 0.1, 1).with_finishing(1.0, 0.5),
+        ));
+        job.build().unwrap();
+        let keypoints = job.task_keypoints(0);
+        let first_finishing = keypoints.iter().position(|keypoint| keypoint.finishing).expect("no finishing pass");
+        // The line cutting to a keypoint, matched on its coordinates
+        let line_to = |gcode: &str, index: usize| -> String {
+            let position = keypoints[index].position;
+            let target = format!("G1 X{:.4} Y{:.4} Z{:.4}", position.x, position.y, position.z);
+            gcode.lines().find(|line| line.starts_with(&target)).unwrap_or_else(|| panic!("no cut to {}", target)).to_string()
+        };
+
+        let options = GcodeOptions { finishing_feed_rate: Some(120.0), ..GcodeOptions::default() };
+        let gcode = export_gcode(&job, &options).unwrap();
+        assert!(line_to(&gcode, first_finishing).ends_with(" F120.0"), "{}", line_to(&gcode, first_finishing));
+        // Feed words are modal: roughing states the tool's rate, finishing never returns to it
+        let finishing_starts = gcode.lines().position(|line| line.ends_with(" F120.0")).unwrap();
+        assert!(gcode.lines().take(finishing_starts).any(|line| line.ends_with(" F800.0")));
+        assert!(!gcode.lines().skip(finishing_starts).any(|line| line.contains(" F800.0")));
+
+        // Without a finishing rate every cut is at the tool's
+        let gcode = export_gcode(&job, &GcodeOptions::default()).unwrap();
+        assert!(!gcode.contains(" F120.0"));
+        assert!(!line_to(&gcode, first_finishing).contains(" F"));
+    }
+}
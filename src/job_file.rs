@@ -0,0 +1,309 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+use stl_io::{IndexedMesh, IndexedTriangle, Vector};
+use crate::cam_job::{CAMJOB, Units};
+use crate::errors::CAMError;
+use crate::tasks::TaskConfig;
+use crate::tolerances::Tolerances;
+use crate::tool::{Tool, ToolConfig};
+
+/// Plain-data copy of an `IndexedMesh`, which doesn't implement serde itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeshData {
+    pub vertices: Vec<[f32; 3]>,
+    pub faces: Vec<[usize; 3]>,
+    pub normals: Vec<[f32; 3]>,
+}
+
+impl From<&IndexedMesh> for MeshData {
+    fn from(mesh: &IndexedMesh) -> Self {
+        MeshData {
+            vertices: mesh.vertices.iter().map(|v| [v[0], v[1], v[2]]).collect(),
+            faces: mesh.faces.iter().map(|f| f.vertices).collect(),
+            normals: mesh.faces.iter().map(|f| [f.normal[0], f.normal[1], f.normal[2]]).collect(),
+        }
+    }
+}
+
+/// Fails with `CAMError::InvalidMesh` when there isn't exactly one normal per face or
+/// a face refers to a vertex that doesn't exist, as in a hand-edited job file.
+impl TryFrom<MeshData> for IndexedMesh {
+    type Error = CAMError;
+
+    fn try_from(data: MeshData) -> Result<Self, CAMError> {
+        if data.faces.len() != data.normals.len() {
+            return Err(CAMError::InvalidMesh(format!(
+                "{} faces but {} face normals",
+                data.faces.len(),
+                data.normals.len()
+            )));
+        }
+        if let Some(index) = data.faces.iter().flatten().find(|&&index| index >= data.vertices.len()) {
+            return Err(CAMError::InvalidMesh(format!(
+                "face refers to vertex {} of {}",
+                index,
+                data.vertices.len()
+            )));
+        }
+        Ok(IndexedMesh {
+            vertices: data.vertices.into_iter().map(Vector::new).collect(),
+            faces: data.faces.into_iter()
+                .zip(data.normals)
+                .map(|(vertices, normal)| IndexedTriangle { normal: Vector::new(normal), vertices })
+                .collect(),
+        })
+    }
+}
+
+/// On-disk representation of a `CAMJOB`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobFile {
+    pub target_mesh: Option<MeshData>,
+    pub stock_mesh: Option<MeshData>,
+    pub tools: Vec<ToolConfig>,
+    pub tasks: Vec<TaskConfig>,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub tolerances: Tolerances,
+}
+
+impl CAMJOB {
+    /// Writes the job's meshes, tools and task parameters to a JSON file.
+    pub fn save_job(&self, path: &Path) -> Result<(), CAMError> {
+        let job_file = JobFile {
+            target_mesh: self.target_mesh.as_ref().map(MeshData::from),
+            stock_mesh: self.stock_mesh.as_ref().map(MeshData::from),
+            tools: self.tool_library.iter().map(Tool::config).collect(),
+            tasks: self.get_tasks().iter().map(|task| task.config()).collect(),
+            units: self.units,
+            tolerances: self.tolerances,
+        };
+
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &job_file)?;
+        Ok(())
+    }
+
+    /// Writes every keypoint to a CSV file with a header row and the columns
+    /// `task_index,tool_id,x,y,z,nx,ny,nz`, in task then generation order (the order of
+    /// `gather_keypoints`). Positions are in millimetres. Build the job first.
+    pub fn export_keypoints_csv(&self, path: &Path) -> Result<(), CAMError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "task_index,tool_id,x,y,z,nx,ny,nz")?;
+        for (task_index, keypoint) in self.gather_keypoints_with_task() {
+            let tool_id = keypoint.tool_id.map_or_else(String::new, |tool_id| tool_id.to_string());
+            let (p, n) = (keypoint.position, keypoint.normal);
+            writeln!(writer, "{},{},{},{},{},{},{},{}", task_index, tool_id, p.x, p.y, p.z, n.x, n.y, n.z)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes cutter location data for an external verifier: a CSV file with a header
+    /// row and, per keypoint, the tool, the cutter location (`Tool::cutter_location`),
+    /// the cutter contact point on the surface and the unit tool axis, as
+    /// `task_index,tool_id,cl_x,cl_y,cl_z,cc_x,cc_y,cc_z,i,j,k`. Keypoints are in the
+    /// order of `gather_keypoints` and positions in millimetres. Build the job first.
+    pub fn export_cl_data(&self, path: &Path) -> Result<(), CAMError> {
+        let tasks = self.get_tasks();
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "task_index,tool_id,cl_x,cl_y,cl_z,cc_x,cc_y,cc_z,i,j,k")?;
+        for (task_index, keypoint) in self.gather_keypoints_with_task() {
+            let tool_id = keypoint.tool_id.unwrap_or_else(|| tasks[task_index].get_tool_id());
+            let tool = self.get_tool(tool_id).ok_or_else(|| {
+                CAMError::ProcessingError(format!("Task {} uses tool {}, which is not in the library", task_index, tool_id))
+            })?;
+            let axis = keypoint.normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros);
+            let (cc, cl) = (keypoint.position, tool.cutter_location(&keypoint.position, &axis));
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                task_index, tool_id, cl.x, cl.y, cl.z, cc.x, cc.y, cc.z, axis.x, axis.y, axis.z
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a job written by `save_job`. Tools come back without render models (see
+    /// `ToolLibrary::attach_visuals`), and the job has to be built again to regenerate keypoints.
+    pub fn load_job(path: &Path) -> Result<CAMJOB, CAMError> {
+        let file = File::open(path)?;
+        let job_file: JobFile = serde_json::from_reader(BufReader::new(file))?;
+
+        let mut job = CAMJOB::new();
+        job.target_mesh = job_file.target_mesh.map(IndexedMesh::try_from).transpose()?;
+        job.stock_mesh = job_file.stock_mesh.map(IndexedMesh::try_from).transpose()?;
+        job.units = job_file.units;
+        job.tolerances = job_file.tolerances;
+
+        for tool in job_file.tools {
+            job.add_tool(Tool::from_config(tool))?;
+        }
+
+        if !job_file.tasks.is_empty() {
+            let mesh = job.target_mesh.clone().ok_or(CAMError::MeshNotSet)?;
+            for task in job_file.tasks {
+                job.add_task(task.into_task(&mesh));
+            }
+        }
+
+        Ok(job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tasks::{CircularClearing, ContourTrace, DrillHole, DrillHoles, Lead, MultiContourTrace, RasterSurface, TraceMode};
+    use crate::test_support::{box_mesh, cube_mesh, temp_path};
+    use crate::tool::ToolGeometry;
+    use nalgebra::{Point3, Vector3};
+
+    #[test]
+    fn mesh_data_round_trips() {
+        let mesh = cube_mesh(2.0);
+        let restored = IndexedMesh::try_from(MeshData::from(&mesh)).unwrap();
+        assert_eq!(restored.vertices, mesh.vertices);
+        assert_eq!(restored.faces.len(), mesh.faces.len());
+        assert!(restored.faces.iter().zip(&mesh.faces).all(|(a, b)| a.vertices == b.vertices && a.normal == b.normal));
+    }
+
+    #[test]
+    fn mismatched_mesh_data_is_rejected() {
+        let mut missing_normal = MeshData::from(&cube_mesh(2.0));
+        missing_normal.normals.pop();
+        assert!(matches!(IndexedMesh::try_from(missing_normal), Err(CAMError::InvalidMesh(_))));
+
+        let mut bad_index = MeshData::from(&cube_mesh(2.0));
+        bad_index.faces[0][1] = bad_index.vertices.len();
+        assert!(matches!(IndexedMesh::try_from(bad_index), Err(CAMError::InvalidMesh(_))));
+    }
+
+    #[test]
+    fn saved_job_loads_back() {
+        let mesh = cube_mesh(10.0);
+        let mut job = CAMJOB::new();
+        job.set_mesh(mesh.clone()).unwrap();
+        job.add_tool(Tool::new(3, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        job.add_task(Box::new(ContourTrace::new(12, Point3::new(0.0, 0.0, 1.0), Vector3::z(), &mesh, 3)));
+
+        let path = temp_path("job.json");
+        job.save_job(&path).unwrap();
+        let loaded = CAMJOB::load_job(&path);
+        std::fs::remove_file(&path).ok();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.target_mesh.as_ref().unwrap().faces.len(), 12);
+        assert!(loaded.get_tool(3).is_some());
+        assert_eq!(loaded.get_tasks().len(), 1);
+        assert_eq!(loaded.get_tasks()[0].name(), "ContourTrace");
+    }
+
+    #[test]
+    fn rebuilt_job_matches_the_saved_one() {
+        let mesh = cube_mesh(10.0);
+        let mut job = CAMJOB::new();
+        job.set_mesh(mesh.clone()).unwrap();
+        job.add_tool(Tool::new(0, "Endmill".to_string(), 20.0, 3.0)).unwrap();
+        job.add_task(Box::new(
+            MultiContourTrace::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, 4.0), 3, 16, 0)
+                .with_mode(TraceMode::Grid)
+                .with_lead(Lead::Radial { length: 2.0 }),
+        ));
+        job.add_task(Box::new(
+            CircularClearing::new(Point3::new(0.0, 0.0, -4.0), Point3::new(0.0, 0.0, 4.0), 2, 12.0, 32, 2.0, 0.1, 0)
+                .with_clearance(0.5)
+                .with_min_rings(2)
+                .with_finishing(0.5, 0.25),
+        ));
+        job.add_task(Box::new(
+            ContourTrace::new(12, Point3::new(0.0, 0.0, 1.0), Vector3::z(), &mesh, 0).with_lead(Lead::Tangential { radius: 1.5 }),
+        ));
+        job.build().unwrap();
+
+        let path = temp_path("rebuild.json");
+        job.save_job(&path).unwrap();
+        let loaded = CAMJOB::load_job(&path);
+        std::fs::remove_file(&path).ok();
+        let mut loaded = loaded.unwrap();
+        loaded.build().unwrap();
+
+        let (saved, rebuilt) = (job.gather_keypoints(), loaded.gather_keypoints());
+        assert!(saved.iter().any(|keypoint| keypoint.finishing), "the clearing's finishing pass didn't run");
+        assert_eq!(rebuilt.len(), saved.len());
+        for (a, b) in saved.iter().zip(&rebuilt) {
+            assert_eq!((a.position, a.normal, a.layer, a.tool_id), (b.position, b.normal, b.layer, b.tool_id));
+            assert_eq!((a.finishing, a.rapid), (b.finishing, b.rapid));
+        }
+    }
+
+    #[test]
+    fn keypoints_csv_has_a_row_per_keypoint() {
+        let mut job = CAMJOB::new();
+        job.set_mesh(cube_mesh(10.0)).unwrap();
+        job.add_tool(Tool::new(3, "Drill".to_string(), 20.0, 3.0)).unwrap();
+        job.add_tool(Tool::new(4, "Drill".to_string(), 20.0, 2.0)).unwrap();
+        let holes = |x: f32| vec![DrillHole::new(Point3::new(x, 0.0, 5.0), 2.0), DrillHole::new(Point3::new(x, 2.0, 5.0), 2.0)];
+        job.add_task(Box::new(DrillHoles::new(holes(-2.0), 1.0, 3)));
+        job.add_task(Box::new(DrillHoles::new(holes(2.0), 1.0, 4)));
+        job.build().unwrap();
+
+        let path = temp_path("keypoints.csv");
+        job.export_keypoints_csv(&path).unwrap();
+        let written = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).ok();
+        let written = written.unwrap();
+
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("task_index,tool_id,x,y,z,nx,ny,nz"));
+        let rows: Vec<Vec<f32>> = lines.map(|line| line.split(',').map(|field| field.parse().unwrap()).collect()).collect();
+        let keypoints = job.gather_keypoints();
+        assert_eq!(rows.len(), keypoints.len());
+        for (row, keypoint) in rows.iter().zip(&keypoints) {
+            assert_eq!(row.len(), 8);
+            let (p, n) = (keypoint.position, keypoint.normal);
+            assert_eq!(row[2..], [p.x, p.y, p.z, n.x, n.y, n.z]);
+        }
+        // Task then generation order, each row with its task's tool
+        let tasks: Vec<(f32, f32)> = rows.iter().map(|row| (row[0], row[1])).collect();
+        let half = rows.len() / 2;
+        assert!(tasks[..half].iter().all(|&task| task == (0.0, 3.0)));
+        assert!(tasks[half..].iter().all(|&task| task == (1.0, 4.0)));
+    }
+
+    #[test]
+    fn ball_cutter_location_is_a_radius_above_the_contact() {
+        let plate = box_mesh(Point3::new(-10.0, -10.0, -2.0), Point3::new(10.0, 10.0, 0.0));
+        let mut job = CAMJOB::new();
+        job.set_mesh(plate.clone()).unwrap();
+        job.add_tool(Tool::new(2, "Ball".to_string(), 20.0, 6.0).with_geometry(ToolGeometry::BallNose)).unwrap();
+        job.add_task(Box::new(RasterSurface::new(5.0, 10.0, 20.0, &plate, 2)));
+        job.build().unwrap();
+
+        let path = temp_path("cl.csv");
+        job.export_cl_data(&path).unwrap();
+        let written = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).ok();
+        let written = written.unwrap();
+
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("task_index,tool_id,cl_x,cl_y,cl_z,cc_x,cc_y,cc_z,i,j,k"));
+        let rows: Vec<Vec<f32>> = lines.map(|line| line.split(',').map(|field| field.parse().unwrap()).collect()).collect();
+        assert_eq!(rows.len(), job.gather_keypoints().len());
+        assert!(!rows.is_empty());
+        for row in &rows {
+            let (cl, cc, axis) = (Vector3::new(row[2], row[3], row[4]), Vector3::new(row[5], row[6], row[7]), Vector3::new(row[8], row[9], row[10]));
+            assert_eq!(row[1], 2.0);
+            // Straight up off the flat top, by the ball's radius
+            assert!((axis - Vector3::z()).norm() < 1e-5);
+            assert!(cc.z.abs() < 1e-4);
+            assert!((cl - cc - axis * 3.0).norm() < 1e-4);
+        }
+    }
+}
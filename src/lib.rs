@@ -0,0 +1,29 @@
+//! Toolpath generation for STL parts: load a mesh into a `CAMJOB`, add tasks and
+//! tools, `build` it, then export G-code or step through the material removal
+//! simulation. `prelude` re-exports what most users need.
+//!
+//! The kiss3d viewer (`app_state`, `frame_export`, tool models, simulation scene
+//! nodes) is behind the default `viewer` feature; build with `default-features = false`
+//! to use the library without a windowing stack.
+
+pub mod errors;
+pub mod prelude;
+pub mod tasks;
+pub mod cam_job;
+#[cfg(feature = "viewer")]
+pub mod app_state;
+#[cfg(feature = "viewer")]
+pub mod frame_export;
+pub mod tool;
+pub mod stl_operations;
+pub mod mesh_loaders;
+pub mod job_file;
+pub mod gcode;
+pub mod simulation;
+pub mod serde_helpers;
+pub mod collision;
+pub mod feature_size;
+pub mod report;
+pub mod tolerances;
+#[cfg(test)]
+pub(crate) mod test_support;
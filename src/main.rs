@@ -1,83 +1,339 @@
-mod errors;
-mod prelude;
-mod tasks;
-mod cam_job;
-mod app_state;
-mod tool;
-mod stl_operations;
-
-use app_state::{AppState, handle_ui};
-use stl_operations::{center_and_scale_mesh, load_stl, mesh_to_kiss3d};
-use cam_job::CAMJOB;
-use tool::Tool;
-use kiss3d::nalgebra::{Vector3, Point3};
-use kiss3d::window::Window;
+#[cfg(feature = "viewer")]
+use carver::app_state::{AppState, handle_ui, apply_mesh_opacity};
+#[cfg(feature = "viewer")]
+use carver::frame_export::FrameExport;
+use carver::stl_operations::{center_and_scale_mesh, get_bounds, load_mesh, repair_mesh, write_stl};
+#[cfg(feature = "viewer")]
+use carver::stl_operations::{add_mesh_chunks, KISS3D_MAX_VERTICES};
+use carver::cam_job::CAMJOB;
+use carver::errors::CAMError;
+use carver::feature_size::min_feature_sizes;
+use carver::gcode::{GcodeOptions, write_gcode};
+use carver::tool::{Tool, ToolGeometry, ToolLibrary};
+use stl_io::IndexedMesh;
+use nalgebra::Point3;
+#[cfg(feature = "viewer")]
+use kiss3d::window::{CanvasSetup, NumSamples, Window};
+#[cfg(feature = "viewer")]
 use kiss3d::light::Light;
-use tasks::*;
-use std::rc::Rc;
-use std::{cell::RefCell, path::Path};
-use std::env;
-use anyhow::Result;
-
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <stl_file>", args[0]);
-        std::process::exit(1);
-    }
-    let stl_file = &args[1];
-    let filename = Path::new(stl_file);
-    let mut mesh = load_stl(filename)?;
-    let (min_z, max_z) = center_and_scale_mesh(&mut mesh);
-
-    let mut window = Window::new("STL Viewer with Keypoints");
-    let mut c = window.add_mesh(Rc::new(RefCell::new(mesh_to_kiss3d(&mesh))), Vector3::new(1.0, 1.0, 1.0));
+use carver::tasks::*;
+#[cfg(feature = "viewer")]
+use kiss3d::scene::SceneNode;
+use std::path::{Path, PathBuf};
+use clap::Parser;
+
+/// Command line. Without `--headless` or `--frames` the interactive viewer opens.
+#[derive(Parser)]
+#[command(name = "watch-stl", about = "Generate and preview toolpaths for a mesh")]
+struct Args {
+    /// Mesh to machine.
+    stl_file: PathBuf,
+    /// Build the job and write G-code to `--out` without opening a window.
+    #[arg(long, requires = "out")]
+    headless: bool,
+    /// G-code file written by `--headless`.
+    #[arg(long, value_name = "GCODE_FILE")]
+    out: Option<PathBuf>,
+    /// Render playback to numbered PNGs in `--out-dir` and exit.
+    #[arg(long, requires = "out_dir", conflicts_with = "headless")]
+    frames: bool,
+    /// Directory `--frames` writes to.
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+    /// Number of frames `--frames` renders.
+    #[arg(long, default_value_t = 100)]
+    count: usize,
+    /// Write the stock generated around the part to this STL and exit, to compare
+    /// against the part in another viewer.
+    #[arg(long, value_name = "STL_FILE", conflicts_with_all = ["headless", "frames"])]
+    stock: Option<PathBuf>,
+    #[command(flatten)]
+    tasks: TaskOptions,
+    #[command(flatten)]
+    window: WindowOptions,
+}
+
+/// Which tasks the job gets and their parameters. With neither `--contour` nor
+/// `--clearing`, both are added.
+#[derive(clap::Args)]
+struct TaskOptions {
+    /// Add a multi-layer contour trace.
+    #[arg(long)]
+    contour: bool,
+    #[arg(long, default_value_t = 50)]
+    contour_layers: usize,
+    #[arg(long, default_value_t = 200)]
+    contour_rays: usize,
+    /// Add circular clearing.
+    #[arg(long)]
+    clearing: bool,
+    #[arg(long, default_value_t = 50)]
+    clearing_layers: usize,
+    /// Starting ring radius.
+    #[arg(long, default_value_t = 75.0)]
+    clearing_radius: f32,
+    #[arg(long, default_value_t = 50)]
+    clearing_points: usize,
+}
+
+/// Viewer window setup, for the interactive viewer and `--frames`.
+#[derive(clap::Args)]
+#[cfg_attr(not(feature = "viewer"), allow(dead_code))]
+struct WindowOptions {
+    /// Multisample anti-aliasing samples per pixel: 1 (off), 2, 4 or 8. Higher
+    /// counts smooth the wireframes at some GPU cost.
+    #[arg(long, default_value_t = 1, value_parser = parse_msaa_samples)]
+    msaa: u32,
+    #[arg(long, default_value_t = 800)]
+    width: u32,
+    #[arg(long, default_value_t = 600)]
+    height: u32,
+}
+
+/// Accepts the sample counts kiss3d can request.
+fn parse_msaa_samples(value: &str) -> Result<u32, String> {
+    let samples: u32 = value.parse().map_err(|_| format!("`{}` is not a sample count", value))?;
+    match samples {
+        1 | 2 | 4 | 8 => Ok(samples),
+        _ => Err(format!("MSAA sample count must be 1, 2, 4 or 8, not {}", samples)),
+    }
+}
+
+#[cfg(feature = "viewer")]
+impl WindowOptions {
+    fn open(&self, title: &str) -> Window {
+        let samples = match self.msaa {
+            2 => NumSamples::Two,
+            4 => NumSamples::Four,
+            8 => NumSamples::Eight,
+            _ => NumSamples::Zero,
+        };
+        Window::new_with_setup(title, self.width, self.height, CanvasSetup { vsync: true, samples })
+    }
+}
+
+/// What to run: the interactive viewer, a headless run that builds the job and
+/// writes G-code without opening a window, a viewer run that renders playback to
+/// numbered PNGs and exits, or a dump of the stock mesh.
+enum Command {
+    View { stl_file: PathBuf },
+    Headless { stl_file: PathBuf, output: PathBuf },
+    Frames { stl_file: PathBuf, output_dir: PathBuf, frame_count: usize },
+    Stock { stl_file: PathBuf, output: PathBuf },
+}
+
+impl Args {
+    /// Splits off the task and window options. clap has already checked that
+    /// `--headless` and `--frames` come with their output paths.
+    fn into_command(self) -> (Command, TaskOptions, WindowOptions) {
+        let stl_file = self.stl_file;
+        if let Some(output) = self.stock {
+            return (Command::Stock { stl_file, output }, self.tasks, self.window);
+        }
+        let command = match (self.headless, self.frames, self.out, self.out_dir) {
+            (true, _, Some(output), _) => Command::Headless { stl_file, output },
+            (_, true, _, Some(output_dir)) => Command::Frames { stl_file, output_dir, frame_count: self.count },
+            _ => Command::View { stl_file },
+        };
+        (command, self.tasks, self.window)
+    }
+}
+
+/// Builds the job shared by every mode: the tool library and the tasks chosen in
+/// `options`, spanning the height of the (already centered) part.
+fn create_job(mesh: &IndexedMesh, min_z: f32, max_z: f32, options: &TaskOptions) -> Result<CAMJOB, CAMError> {
+    let mut tool_library = ToolLibrary::new();
+    tool_library.add_tool(Tool::new(0, "End Mill 6mm".to_string(), 0.05, 0.006))?;
+    tool_library.add_tool(Tool::new(1, "Ball Mill 4mm".to_string(), 0.04, 0.004).with_geometry(ToolGeometry::BallNose))?;
+
+    let mut cam_job = CAMJOB::new().with_tool_library(tool_library);
+    cam_job.set_mesh(mesh.clone())?;
+
+    let all_tasks = !options.contour && !options.clearing;
+    if options.contour || all_tasks {
+        cam_job.add_task(Box::new(MultiContourTrace::new(
+            Point3::new(0.0, 0.0, min_z),
+            Point3::new(0.0, 0.0, max_z),
+            options.contour_layers,
+            options.contour_rays,
+            0, // tool_id for End Mill 6mm
+        )));
+    }
+
+    if options.clearing || all_tasks {
+        cam_job.add_task(Box::new(CircularClearing::new(
+            Point3::new(0.0, 0.0, min_z),
+            Point3::new(0.0, 0.0, max_z),
+            options.clearing_layers,
+            options.clearing_radius,
+            options.clearing_points,
+            5.,
+            0.001,
+            1, // tool_id for Ball Mill 4mm
+        )));
+    }
+
+    Ok(cam_job)
+}
+
+fn run_headless(stl_file: &Path, output: &Path, options: &TaskOptions) -> Result<(), CAMError> {
+    let mut mesh = load_mesh(stl_file)?;
+    repair_mesh(&mut mesh)?;
+    let (min_z, max_z) = center_and_scale_mesh(&mut mesh, None);
+    let mut cam_job = create_job(&mesh, min_z, max_z, options)?;
+    let features = min_feature_sizes(&mesh)?;
+    if let Some(radius) = features.min_internal_radius {
+        println!("Smallest internal radius: {} at {:?}", radius.size, radius.location.coords.as_slice());
+    }
+    if let Some(wall) = features.thinnest_wall {
+        println!("Thinnest wall: {} at {:?}", wall.size, wall.location.coords.as_slice());
+    }
+    for warning in features.warnings(&cam_job.tool_library) {
+        eprintln!("Warning: {}", warning);
+    }
+    cam_job.build()?;
+    println!("{}", cam_job.report());
+    for tool in cam_job.tool_library.iter() {
+        println!("{}", tool.describe());
+    }
+    write_gcode(&cam_job, &GcodeOptions::default(), output)?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Writes the stock `set_mesh` generates for the part, in the same centered and
+/// scaled coordinates the other modes work in.
+fn write_stock(stl_file: &Path, output: &Path) -> Result<(), CAMError> {
+    let mut mesh = load_mesh(stl_file)?;
+    repair_mesh(&mut mesh)?;
+    center_and_scale_mesh(&mut mesh, None);
+    let mut cam_job = CAMJOB::new();
+    cam_job.set_mesh(mesh.clone())?;
+    let stock = cam_job.get_stock_mesh().ok_or(CAMError::MeshNotSet)?;
+
+    let (part_min, part_max) = get_bounds(&mesh)?;
+    let (stock_min, stock_max) = get_bounds(stock)?;
+    println!("Part bounds:  {:?} to {:?}", part_min.coords.as_slice(), part_max.coords.as_slice());
+    println!("Stock bounds: {:?} to {:?}", stock_min.coords.as_slice(), stock_max.coords.as_slice());
+    write_stl(stock, output, true)?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+fn main() -> Result<(), CAMError> {
+    let (command, options, window) = Args::parse().into_command();
+    match command {
+        Command::View { stl_file } => run_viewer(&stl_file, &options, &window),
+        Command::Headless { stl_file, output } => run_headless(&stl_file, &output, &options),
+        Command::Frames { stl_file, output_dir, frame_count } => {
+            export_frames(&stl_file, output_dir, frame_count, &options, &window)
+        }
+        Command::Stock { stl_file, output } => write_stock(&stl_file, &output),
+    }
+}
+
+#[cfg(not(feature = "viewer"))]
+fn run_viewer(_stl_file: &Path, _options: &TaskOptions, _window: &WindowOptions) -> Result<(), CAMError> {
+    Err(CAMError::ProcessingError(
+        "built without the `viewer` feature; use <stl_file> --headless --out <gcode_file>".into(),
+    ))
+}
+
+#[cfg(not(feature = "viewer"))]
+fn export_frames(
+    stl_file: &Path,
+    _output_dir: PathBuf,
+    _frame_count: usize,
+    options: &TaskOptions,
+    window: &WindowOptions,
+) -> Result<(), CAMError> {
+    run_viewer(stl_file, options, window)
+}
+
+#[cfg(feature = "viewer")]
+fn run_viewer(stl_file: &Path, options: &TaskOptions, window: &WindowOptions) -> Result<(), CAMError> {
+    view(stl_file, options, window, None)
+}
+
+/// Adds `mesh` to the scene, in several parts if it has more vertices than one kiss3d
+/// mesh can index. `what` names the mesh in messages.
+#[cfg(feature = "viewer")]
+fn add_viewer_mesh(window: &mut Window, mesh: &IndexedMesh, what: &str) -> Result<SceneNode, CAMError> {
+    if mesh.faces.is_empty() {
+        return Err(CAMError::ProcessingError(format!("the {} mesh has no faces to draw", what)));
+    }
+    if mesh.vertices.len() > KISS3D_MAX_VERTICES {
+        println!("The {} mesh has {} vertices; drawing it in parts", what, mesh.vertices.len());
+    }
+    add_mesh_chunks(window.scene_mut(), mesh)
+        .map_err(|e| CAMError::ProcessingError(format!("can't draw the {} mesh: {}", what, e)))
+}
+
+/// Builds the job, then plays it back in the viewer window, saving each frame.
+#[cfg(feature = "viewer")]
+fn export_frames(
+    stl_file: &Path,
+    output_dir: PathBuf,
+    frame_count: usize,
+    options: &TaskOptions,
+    window: &WindowOptions,
+) -> Result<(), CAMError> {
+    view(stl_file, options, window, Some(FrameExport::new(output_dir, frame_count)))
+}
+
+/// With `frames`, the job is built up front and the window closes once every frame
+/// has been captured.
+#[cfg(feature = "viewer")]
+fn view(stl_file: &Path, options: &TaskOptions, window_options: &WindowOptions, mut frames: Option<FrameExport>) -> Result<(), CAMError> {
+    let mut mesh = load_mesh(stl_file)?;
+    repair_mesh(&mut mesh)?;
+    let original_bounds = get_bounds(&mesh)?;
+    let (min_z, max_z) = center_and_scale_mesh(&mut mesh, None);
+
+    let mut window = window_options.open("STL Viewer with Keypoints");
+    let mut c = add_viewer_mesh(&mut window, &mesh, "target")?;
     c.set_color(0.8, 0.8, 0.8);
     c.set_lines_width(1.0);
     c.set_surface_rendering_activation(false);
     window.set_light(Light::StickToCamera);
 
-    let mut cam_job = CAMJOB::new();
-    cam_job.set_mesh(mesh.clone())?;
-
-    // Initialize tools
-    cam_job.add_tool(Tool::new(0, "End Mill 6mm".to_string(), &mut window, 0.05, 0.006));
-    cam_job.add_tool(Tool::new(1, "Ball Mill 4mm".to_string(), &mut window, 0.04, 0.004));
+    let mut cam_job = create_job(&mesh, min_z, max_z, options)?;
+    cam_job.tool_library.attach_visuals(&mut window);
+    if frames.is_some() {
+        cam_job.build()?;
+    }
 
-    let mut stock_mesh = window.add_mesh(
-        Rc::new(RefCell::new(mesh_to_kiss3d(cam_job.get_stock_mesh().unwrap()))),
-        Vector3::new(1.0, 1.0, 1.0)
-    );
+    let mut stock_mesh = add_viewer_mesh(&mut window, cam_job.get_stock_mesh().ok_or(CAMError::MeshNotSet)?, "stock")?;
     stock_mesh.set_color(0.5, 0.5, 0.5);
     stock_mesh.set_lines_width(1.0);
     stock_mesh.set_surface_rendering_activation(false);
 
-    cam_job.add_task(Box::new(MultiContourTrace::new(
-        Point3::new(0.0, 0.0, min_z),
-        Point3::new(0.0, 0.0, max_z),
-        50,
-        200,
-        // 0, // tool_id for End Mill 6mm
-    )));
-
-    cam_job.add_task(Box::new(CircularClearing::new(
-        Point3::new(0.0, 0.0, min_z),
-        Point3::new(0.0, 0.0, max_z),
-        50,
-        75.0,
-        50,
-        5.,
-        0.001,
-        // 1, // tool_id for Ball Mill 4mm
-    )));
-
     // Initialize AppState
     let mut app_state = {
         let mut ui = window.conrod_ui_mut().set_widgets();
         AppState::new(mesh.clone(), cam_job, stock_mesh, &mut ui)
     };
+    app_state.original_bounds = Some(original_bounds);
+    let num_keypoints = app_state.cam_job.borrow().gather_keypoints().len();
+    if frames.is_some() {
+        app_state.is_playing = true;
+        app_state.rebuild_keypoint_spheres(&mut window);
+    }
+    // Whether the frame just rendered shows a keypoint sought for export
+    let mut frame_pending = false;
 
     while window.render() {
+        app_state.poll_build(&mut window);
+
+        if let Some(export) = &mut frames {
+            if frame_pending {
+                println!("Saved {}", export.capture(&window)?.display());
+            }
+            if export.is_finished() {
+                break;
+            }
+        }
+
         {
             let mut ui = window.conrod_ui_mut().set_widgets();
             handle_ui(&mut app_state, &mut ui);
@@ -86,25 +342,53 @@ fn main() -> Result<()> {
         if app_state.show_keypoint_lines {
             app_state.draw_keypoint_lines(&mut window);
         }
+        app_state.draw_bounds(&mut window);
+        app_state.draw_face_normals(&mut window);
 
-        if app_state.is_playing {
-            app_state.animate();
+        match &frames {
+            Some(export) => {
+                app_state.seek_keypoint(export.keypoint_index(num_keypoints));
+                frame_pending = true;
+            }
+            None if app_state.is_playing => app_state.animate(),
+            None => {}
         }
 
+        app_state.generate_simulation_mesh(&mut window);
+
         // Update mesh visibility
         c.set_visible(app_state.show_mesh);
+        apply_mesh_opacity(&mut c, app_state.mesh_opacity);
 
         // Update stock mesh visibility
         app_state.stock_mesh.set_visible(app_state.show_stock_mesh);
 
-        // Update tool visibility
-        let cam_job = app_state.cam_job.lock().unwrap();
-        for tool_id in 0..2 {  // Assuming we have 2 tools
-            if let Some(tool) = cam_job.get_tool(tool_id) {
-                tool.set_visible(app_state.is_playing);
-            }
+        // Show only the tool cutting at the current keypoint
+        let cam_job = app_state.cam_job.borrow();
+        for tool in cam_job.tool_library.iter() {
+            tool.set_visible(app_state.is_playing && app_state.active_tool_id() == Some(tool.id));
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msaa_takes_only_the_sample_counts_kiss3d_supports() {
+        for samples in [1, 2, 4, 8].iter() {
+            assert_eq!(parse_msaa_samples(&samples.to_string()), Ok(*samples));
+        }
+        for value in ["0", "3", "16"].iter() {
+            assert!(parse_msaa_samples(value).unwrap_err().contains("must be 1, 2, 4 or 8"));
+        }
+        assert!(parse_msaa_samples("four").unwrap_err().contains("not a sample count"));
+
+        let error = Args::try_parse_from(["watch-stl", "part.stl", "--msaa", "3"]).err().unwrap();
+        assert!(error.to_string().contains("MSAA sample count must be 1, 2, 4 or 8, not 3"));
+        let args = Args::try_parse_from(["watch-stl", "part.stl", "--msaa", "4", "--width", "1024"]).unwrap();
+        assert_eq!((args.window.msaa, args.window.width, args.window.height), (4, 1024, 600));
+    }
+}
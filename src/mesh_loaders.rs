@@ -0,0 +1,211 @@
+//! Readers for the mesh file formats `stl_operations::load_mesh` accepts. STL is
+//! always available; OBJ and PLY need the `obj` and `ply` features.
+
+use std::path::Path;
+#[cfg(any(feature = "obj", feature = "ply"))]
+use nalgebra::Point3;
+use stl_io::IndexedMesh;
+#[cfg(any(feature = "obj", feature = "ply"))]
+use stl_io::{IndexedTriangle, Vertex};
+use crate::errors::CAMError;
+use crate::stl_operations::load_stl;
+#[cfg(any(feature = "obj", feature = "ply"))]
+use crate::stl_operations::face_normal;
+
+/// Reads one mesh file format into an `IndexedMesh`.
+pub trait MeshLoader {
+    /// Lowercase file extensions this loader handles, without the dot.
+    fn extensions(&self) -> &'static [&'static str];
+    fn load(&self, path: &Path) -> Result<IndexedMesh, CAMError>;
+}
+
+/// Every loader enabled in this build.
+pub fn loaders() -> Vec<Box<dyn MeshLoader>> {
+    vec![
+        Box::new(StlLoader),
+        #[cfg(feature = "obj")]
+        Box::new(ObjLoader),
+        #[cfg(feature = "ply")]
+        Box::new(PlyLoader),
+    ]
+}
+
+pub struct StlLoader;
+
+impl MeshLoader for StlLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["stl"]
+    }
+    fn load(&self, path: &Path) -> Result<IndexedMesh, CAMError> {
+        load_stl(path)
+    }
+}
+
+/// Wavefront OBJ. Every object in the file is merged into one mesh, and polygons
+/// are triangulated as fans.
+#[cfg(feature = "obj")]
+pub struct ObjLoader;
+
+#[cfg(feature = "obj")]
+impl MeshLoader for ObjLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["obj"]
+    }
+    fn load(&self, path: &Path) -> Result<IndexedMesh, CAMError> {
+        let options = tobj::LoadOptions { triangulate: true, ..Default::default() };
+        let (models, _materials) = tobj::load_obj(path, &options)
+            .map_err(|e| CAMError::MeshParse(format!("{}: {}", path.display(), e)))?;
+
+        let mut positions = Vec::new();
+        let mut triangles = Vec::new();
+        for model in &models {
+            let base = positions.len();
+            positions.extend(model.mesh.positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]));
+            triangles.extend(model.mesh.indices.chunks_exact(3).map(|t| {
+                [base + t[0] as usize, base + t[1] as usize, base + t[2] as usize]
+            }));
+        }
+        mesh_from_triangles(path, positions, triangles)
+    }
+}
+
+/// Stanford PLY, ASCII or binary. Reads the `vertex` element's `x`/`y`/`z` and the
+/// `face` element's index list, triangulating polygons as fans.
+#[cfg(feature = "ply")]
+pub struct PlyLoader;
+
+#[cfg(feature = "ply")]
+impl MeshLoader for PlyLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ply"]
+    }
+    fn load(&self, path: &Path) -> Result<IndexedMesh, CAMError> {
+        use ply_rs::parser::Parser;
+        use ply_rs::ply::{DefaultElement, Property};
+
+        let parse_error = |message: String| CAMError::MeshParse(format!("{}: {}", path.display(), message));
+        let scalar = |property: Option<&Property>| match property {
+            Some(Property::Float(value)) => Some(*value),
+            Some(Property::Double(value)) => Some(*value as f32),
+            _ => None,
+        };
+        let indices = |property: Option<&Property>| -> Option<Vec<usize>> {
+            match property {
+                Some(Property::ListInt(list)) => Some(list.iter().map(|&i| i as usize).collect()),
+                Some(Property::ListUInt(list)) => Some(list.iter().map(|&i| i as usize).collect()),
+                Some(Property::ListShort(list)) => Some(list.iter().map(|&i| i as usize).collect()),
+                Some(Property::ListUShort(list)) => Some(list.iter().map(|&i| i as usize).collect()),
+                Some(Property::ListUChar(list)) => Some(list.iter().map(|&i| i as usize).collect()),
+                _ => None,
+            }
+        };
+
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let ply = Parser::<DefaultElement>::new()
+            .read_ply(&mut reader)
+            .map_err(|e| parse_error(e.to_string()))?;
+
+        let mut positions = Vec::new();
+        for vertex in ply.payload.get("vertex").map_or(&[][..], |v| v.as_slice()) {
+            match (scalar(vertex.get("x")), scalar(vertex.get("y")), scalar(vertex.get("z"))) {
+                (Some(x), Some(y), Some(z)) => positions.push([x, y, z]),
+                _ => return Err(parse_error("vertex without float x, y and z".into())),
+            }
+        }
+
+        let mut triangles = Vec::new();
+        for face in ply.payload.get("face").map_or(&[][..], |f| f.as_slice()) {
+            let polygon = indices(face.get("vertex_indices").or_else(|| face.get("vertex_index")))
+                .ok_or_else(|| parse_error("face without a vertex index list".into()))?;
+            for i in 1..polygon.len().saturating_sub(1) {
+                triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+            }
+        }
+        mesh_from_triangles(path, positions, triangles)
+    }
+}
+
+/// Builds an `IndexedMesh` from loaded geometry. Face normals are always computed
+/// from the winding, since not every format stores them per face.
+#[cfg(any(feature = "obj", feature = "ply"))]
+fn mesh_from_triangles(path: &Path, positions: Vec<[f32; 3]>, triangles: Vec<[usize; 3]>) -> Result<IndexedMesh, CAMError> {
+    if let Some(triangle) = triangles.iter().find(|t| t.iter().any(|&v| v >= positions.len())) {
+        return Err(CAMError::MeshParse(format!("{}: face {:?} references a missing vertex", path.display(), triangle)));
+    }
+
+    let points: Vec<Point3<f32>> = positions.iter().map(|p| Point3::new(p[0], p[1], p[2])).collect();
+    let faces = triangles
+        .into_iter()
+        .map(|[a, b, c]| IndexedTriangle {
+            normal: face_normal(&points[a], &points[b], &points[c]),
+            vertices: [a, b, c],
+        })
+        .collect();
+
+    Ok(IndexedMesh {
+        vertices: positions.into_iter().map(Vertex::new).collect(),
+        faces,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stl_operations::{load_mesh, write_stl};
+    use crate::test_support::{cube_mesh, temp_path};
+
+    /// Loads `contents` written to a scratch file named `name`.
+    fn load_written(name: &str, contents: &str) -> Result<IndexedMesh, CAMError> {
+        let path = temp_path(name);
+        std::fs::write(&path, contents).unwrap();
+        let mesh = load_mesh(&path);
+        std::fs::remove_file(&path).ok();
+        mesh
+    }
+
+    /// A unit square in the XY plane, counter-clockwise seen from +Z, as two
+    /// triangles: every face faces +Z.
+    #[cfg(any(feature = "obj", feature = "ply"))]
+    fn assert_square_facing_up(mesh: &IndexedMesh) {
+        assert_eq!((mesh.vertices.len(), mesh.faces.len()), (4, 2));
+        assert_eq!(mesh.faces[0].vertices, [0, 1, 2]);
+        assert_eq!(mesh.faces[1].vertices, [0, 2, 3]);
+        for face in &mesh.faces {
+            assert!((face.normal[0], face.normal[1]) == (0.0, 0.0) && (face.normal[2] - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn loaders_are_picked_by_extension() {
+        let path = temp_path("Picked.STL");
+        write_stl(&cube_mesh(2.0), &path, true).unwrap();
+        let stl = load_mesh(&path);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(stl.unwrap().faces.len(), 12);
+
+        assert!(matches!(load_written("part.step", "ISO-10303-21;"), Err(CAMError::UnsupportedFormat(_))));
+        assert!(matches!(load_written("no-extension", ""), Err(CAMError::UnsupportedFormat(_))));
+        #[cfg(not(feature = "obj"))]
+        assert!(matches!(load_written("part.obj", ""), Err(CAMError::UnsupportedFormat(_))));
+    }
+
+    #[cfg(feature = "obj")]
+    #[test]
+    fn obj_quads_are_triangulated_with_normals_from_winding() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+        assert_square_facing_up(&load_written("square.OBJ", obj).unwrap());
+        // A face past the vertex list is a parse error, not a panic
+        assert!(load_written("broken.obj", "v 0 0 0\nv 1 0 0\nf 1 2 5\n").is_err());
+    }
+
+    #[cfg(feature = "ply")]
+    #[test]
+    fn ply_quads_are_triangulated_with_normals_from_winding() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                   element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                   0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        assert_square_facing_up(&load_written("square.ply", ply).unwrap());
+        let missing = ply.replace("4 0 1 2 3", "3 0 1 7");
+        assert!(matches!(load_written("broken.ply", &missing), Err(CAMError::MeshParse(_))));
+    }
+}
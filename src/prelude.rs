@@ -1,3 +1,27 @@
-use crate::cam_job::CAMJOB;
-use crate::errors::CAMError;
-use stl_io::{IndexedMesh, IndexedTriangle, Triangle, Vector, Vertex};
\ No newline at end of file
+//! The types and functions needed to set up, build and export a job, for
+//! `use carver::prelude::*;`.
+//!
+//! ```
+//! use carver::prelude::*;
+//!
+//! let mut job = CAMJOB::new();
+//! job.add_tool(Tool::new(0, "Endmill".to_string(), 30.0, 6.0))?;
+//! assert_eq!(job.get_tool(0).map(|tool| tool.diameter), Some(6.0));
+//! // Nothing to build without a part
+//! assert!(matches!(job.build(), Err(CAMError::MeshNotSet)));
+//! # Ok::<(), CAMError>(())
+//! ```
+
+pub use crate::cam_job::{BuildProgress, CAMJOB, CAMTask, Keypoint, StockShape, Units};
+pub use crate::errors::CAMError;
+pub use crate::gcode::{export_gcode, export_gcode_programs, write_gcode, CoordinateFrame, FeedMode, GcodeOptions};
+pub use crate::stl_operations::{
+    center_and_scale_mesh, fill_small_holes, get_bounds, indexed_mesh_to_trimesh, load_mesh, load_stl, repair_mesh, rotate_mesh_deg, transform_mesh, validate_mesh, write_stl,
+    MeshReport,
+};
+pub use crate::tasks::{
+    CircularClearing, ContourTrace, DrillHole, DrillHoles, HeightSampling, Lead, MultiContourTrace, RasterSurface, SpiralClearing, TaskConfig, TraceMode,
+};
+pub use crate::tolerances::Tolerances;
+pub use crate::tool::{Tool, ToolConfig, ToolGeometry, ToolLibrary};
+pub use stl_io::{IndexedMesh, IndexedTriangle, Triangle, Vector, Vertex};
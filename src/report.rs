@@ -0,0 +1,78 @@
+use std::fmt;
+use nalgebra::Point3;
+use crate::cam_job::{CAMJOB, Units};
+
+/// Summary of a job's toolpath, from `CAMJOB::report`.
+#[derive(Debug, Clone)]
+pub struct JobReport {
+    /// Whether `build` has run since the job's mesh or tasks last changed.
+    pub built: bool,
+    pub total_keypoints: usize,
+    /// Name and keypoint count of every task, in job order.
+    pub task_keypoints: Vec<(String, usize)>,
+    /// Bounding box of all keypoints, or `None` when there are none.
+    pub bounds: Option<(Point3<f32>, Point3<f32>)>,
+}
+
+impl CAMJOB {
+    pub fn report(&self) -> JobReport {
+        let task_keypoints: Vec<(String, usize)> = self
+            .get_tasks()
+            .iter()
+            .enumerate()
+            .map(|(task_index, task)| (task.name().to_string(), self.task_keypoints(task_index).len()))
+            .collect();
+
+        let bounds = self.gather_keypoints().iter().fold(None, |bounds: Option<(Point3<f32>, Point3<f32>)>, keypoint| {
+            let p = keypoint.position;
+            Some(match bounds {
+                None => (p, p),
+                Some((min, max)) => (min.inf(&p), max.sup(&p)),
+            })
+        });
+
+        JobReport {
+            built: self.is_built(),
+            total_keypoints: task_keypoints.iter().map(|(_, count)| count).sum(),
+            task_keypoints,
+            bounds,
+        }
+    }
+}
+
+impl fmt::Display for JobReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Job {}: {} keypoints", if self.built { "built" } else { "not built" }, self.total_keypoints)?;
+        for (index, (name, count)) in self.task_keypoints.iter().enumerate() {
+            writeln!(f, "  {}. {}: {} keypoints", index + 1, name, count)?;
+        }
+        match self.bounds {
+            Some((min, max)) => write!(
+                f,
+                "  bounds: ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3})",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            ),
+            None => write!(f, "  bounds: none"),
+        }
+    }
+}
+
+/// The X, Y and Z extents of the box from `min` to `max`, labeled and in `units`,
+/// e.g. `"X: 12.500 mm"`.
+pub fn dimension_labels(min: &Point3<f32>, max: &Point3<f32>, units: Units) -> [String; 3] {
+    let size = max - min;
+    let label = |axis: &str, extent: f32| format!("{}: {:.3} {}", axis, units.from_mm(extent), units.suffix());
+    [label("X", size.x), label("Y", size.y), label("Z", size.z)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimension_labels_give_each_extent() {
+        let (min, max) = (Point3::new(-6.25, 0.0, -10.0), Point3::new(6.25, 25.4, 2.5));
+        assert_eq!(dimension_labels(&min, &max, Units::Millimeters), ["X: 12.500 mm", "Y: 25.400 mm", "Z: 12.500 mm"]);
+        assert_eq!(dimension_labels(&min, &max, Units::Inches), ["X: 0.492 in", "Y: 1.000 in", "Z: 0.492 in"]);
+    }
+}
@@ -0,0 +1,51 @@
+//! `#[serde(with = ...)]` adapters so nalgebra points and vectors are written to
+//! config and job files as plain `[x, y, z]` arrays.
+
+pub mod point3 {
+    use nalgebra::Point3;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(point: &Point3<f32>, serializer: S) -> Result<S::Ok, S::Error> {
+        [point.x, point.y, point.z].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Point3<f32>, D::Error> {
+        <[f32; 3]>::deserialize(deserializer).map(Point3::from)
+    }
+}
+
+pub mod vector3 {
+    use nalgebra::Vector3;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(vector: &Vector3<f32>, serializer: S) -> Result<S::Ok, S::Error> {
+        [vector.x, vector.y, vector.z].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vector3<f32>, D::Error> {
+        <[f32; 3]>::deserialize(deserializer).map(Vector3::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector3};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Placement {
+        #[serde(with = "super::point3")]
+        position: Point3<f32>,
+        #[serde(with = "super::vector3")]
+        normal: Vector3<f32>,
+    }
+
+    #[test]
+    fn points_and_vectors_round_trip_as_arrays() {
+        let placement = Placement { position: Point3::new(1.5, -2.0, 3.25), normal: Vector3::new(0.0, 0.0, 1.0) };
+        let json = serde_json::to_string(&placement).unwrap();
+        assert_eq!(json, r#"{"position":[1.5,-2.0,3.25],"normal":[0.0,0.0,1.0]}"#);
+        assert_eq!(serde_json::from_str::<Placement>(&json).unwrap(), placement);
+        assert!(serde_json::from_str::<Placement>(r#"{"position":[1.0,2.0],"normal":[0.0,0.0,1.0]}"#).is_err());
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use nalgebra::{Point3, Vector3};
+use marching_cubes::tables::TRI_TABLE;
+use ncollide3d::shape::TriMesh;
+use stl_io::{IndexedMesh, IndexedTriangle, Vertex};
+use crate::errors::CAMError;
+use crate::stl_operations::{face_normal, get_bounds, indexed_mesh_to_trimesh, ray_crossings};
+use crate::tool::ToolGeometry;
+
+/// Voxels along the longest side of the stock when no resolution is configured.
+pub const DEFAULT_VOXELS_PER_AXIS: usize = 64;
+
+/// Grids with more voxels than this get a warning: the occupancy alone takes a byte
+/// per voxel, and the surface mesh and undo history grow with it.
+pub const VOXEL_BUDGET: usize = 100_000_000;
+
+/// How finely the material removal simulation divides the stock's bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimulationResolution {
+    /// Voxels along the longest side of the stock.
+    VoxelsPerAxis(usize),
+    /// Edge length of a voxel, in millimetres.
+    VoxelSize(f32),
+    /// Roughly this many voxels in total; the grid's dimensions follow the stock's
+    /// proportions.
+    TotalVoxels(usize),
+}
+
+impl Default for SimulationResolution {
+    fn default() -> Self {
+        SimulationResolution::VoxelsPerAxis(DEFAULT_VOXELS_PER_AXIS)
+    }
+}
+
+impl SimulationResolution {
+    /// Voxel edge length for a grid over the box from `min` to `max`.
+    pub fn voxel_size(self, min: &Point3<f32>, max: &Point3<f32>) -> f32 {
+        let size = max - min;
+        match self {
+            SimulationResolution::VoxelsPerAxis(count) => size.max() / count.max(1) as f32,
+            SimulationResolution::VoxelSize(voxel_size) => voxel_size,
+            SimulationResolution::TotalVoxels(count) => {
+                // Flat axes get a single voxel, so only the others share the count
+                let extents: Vec<f32> = size.iter().copied().filter(|&extent| extent > f32::EPSILON).collect();
+                if extents.is_empty() {
+                    return size.max().max(f32::EPSILON);
+                }
+                let volume: f32 = extents.iter().product();
+                (volume / count.max(1) as f32).powf(1.0 / extents.len() as f32)
+            }
+        }
+    }
+}
+
+/// Number of voxels along each axis of a grid over the box from `min` to `max`.
+pub fn grid_dims(min: &Point3<f32>, max: &Point3<f32>, voxel_size: f32) -> [usize; 3] {
+    let size = max - min;
+    [
+        ((size.x / voxel_size).ceil() as usize).max(1),
+        ((size.y / voxel_size).ceil() as usize).max(1),
+        ((size.z / voxel_size).ceil() as usize).max(1),
+    ]
+}
+
+/// Occupancy grid of the material left in the stock. Voxel `(i, j, k)` covers the cube
+/// starting at `origin + (i, j, k) * voxel_size`.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    pub origin: Point3<f32>,
+    pub voxel_size: f32,
+    pub dims: [usize; 3],
+    occupied: Vec<bool>,
+}
+
+impl VoxelGrid {
+    /// A fully occupied grid covering the box from `min` to `max`.
+    pub fn filled(min: Point3<f32>, max: Point3<f32>, voxel_size: f32) -> Self {
+        let dims = grid_dims(&min, &max, voxel_size);
+        VoxelGrid {
+            origin: min,
+            voxel_size,
+            dims,
+            occupied: vec![true; dims[0] * dims[1] * dims[2]],
+        }
+    }
+
+    /// A grid over the bounds of `mesh` with only the voxels whose centers lie inside
+    /// the (closed) mesh occupied. Each row along X is filled between pairs of
+    /// crossings of a ray cast through the mesh. The ray runs slightly off the row's
+    /// centers so it doesn't pass exactly through the edges and vertices of meshes
+    /// lined up with the grid, where a crossing can be counted twice or a graze once.
+    pub fn from_mesh(mesh: &IndexedMesh, voxel_size: f32) -> Result<Self, CAMError> {
+        let (min, max) = get_bounds(mesh)?;
+        let mut grid = VoxelGrid::filled(min, max, voxel_size);
+        grid.occupied.iter_mut().for_each(|occupied| *occupied = false);
+
+        let tri_mesh = indexed_mesh_to_trimesh(mesh);
+        let start_x = min.x - voxel_size;
+        let end_x = max.x + voxel_size;
+        for k in 0..grid.dims[2] {
+            for j in 0..grid.dims[1] {
+                let row = grid.center(0, j, k) + Vector3::new(0.0, ROW_JITTER[0], ROW_JITTER[1]) * voxel_size;
+                let crossings = row_crossings(&tri_mesh, Point3::new(start_x, row.y, row.z), end_x, voxel_size * CROSSING_TOLERANCE);
+                for span in crossings.chunks_exact(2) {
+                    for i in 0..grid.dims[0] {
+                        let x = grid.center(i, j, k).x;
+                        if x >= span[0] && x <= span[1] {
+                            let index = grid.index(i, j, k);
+                            grid.occupied[index] = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(grid)
+    }
+
+    pub fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        (k * self.dims[1] + j) * self.dims[0] + i
+    }
+
+    pub fn center(&self, i: usize, j: usize, k: usize) -> Point3<f32> {
+        self.origin + Point3::new(i as f32 + 0.5, j as f32 + 0.5, k as f32 + 0.5).coords * self.voxel_size
+    }
+
+    pub fn is_occupied(&self, i: usize, j: usize, k: usize) -> bool {
+        self.occupied[self.index(i, j, k)]
+    }
+
+    pub fn occupied_count(&self) -> usize {
+        self.occupied.iter().filter(|&&occupied| occupied).count()
+    }
+
+    /// Whether the voxel at signed indices `cell` is occupied. Anything outside the
+    /// grid counts as empty.
+    fn is_occupied_at(&self, cell: [isize; 3]) -> bool {
+        if (0..3).any(|axis| cell[axis] < 0 || cell[axis] >= self.dims[axis] as isize) {
+            return false;
+        }
+        self.is_occupied(cell[0] as usize, cell[1] as usize, cell[2] as usize)
+    }
+
+    /// Surface of the remaining material, marching cubes over the occupancy sampled at
+    /// voxel centers. The cubes reach one voxel past the grid, where everything is
+    /// empty, so the surface is closed, and vertices are shared between cubes. Normals
+    /// face out of the material.
+    pub fn surface_mesh(&self) -> IndexedMesh {
+        let mut vertices: Vec<Point3<f32>> = Vec::new();
+        let mut faces = Vec::new();
+        // Vertices on the lattice edge from a voxel center along an axis
+        let mut edge_vertices: HashMap<([isize; 3], usize), usize> = HashMap::new();
+
+        for k in -1..self.dims[2] as isize {
+            for j in -1..self.dims[1] as isize {
+                for i in -1..self.dims[0] as isize {
+                    let corner = |c: usize| {
+                        let [x, y, z] = CUBE_CORNERS[c];
+                        [i + x, j + y, k + z]
+                    };
+                    let cube_index = (0..8)
+                        .filter(|&c| !self.is_occupied_at(corner(c)))
+                        .fold(0, |index, c| index | 1 << c);
+
+                    let mut vertex = |edge: usize| {
+                        let (a, b) = CUBE_EDGES[edge];
+                        let (a, b) = (corner(a), corner(b));
+                        let axis = (0..3).find(|&axis| a[axis] != b[axis]).unwrap_or(0);
+                        let start = if a[axis] < b[axis] { a } else { b };
+                        *edge_vertices.entry((start, axis)).or_insert_with(|| {
+                            // Occupancy is 0 or 1, so the surface crosses halfway
+                            let mut midpoint = Vector3::new(start[0] as f32, start[1] as f32, start[2] as f32).add_scalar(0.5);
+                            midpoint[axis] += 0.5;
+                            vertices.push(self.origin + midpoint * self.voxel_size);
+                            vertices.len() - 1
+                        })
+                    };
+                    for triangle in TRI_TABLE[cube_index].chunks_exact(3).take_while(|triangle| triangle[0] >= 0) {
+                        faces.push([vertex(triangle[0] as usize), vertex(triangle[1] as usize), vertex(triangle[2] as usize)]);
+                    }
+                }
+            }
+        }
+
+        IndexedMesh {
+            faces: faces
+                .into_iter()
+                .map(|[a, b, c]| IndexedTriangle { normal: face_normal(&vertices[a], &vertices[b], &vertices[c]), vertices: [a, b, c] })
+                .collect(),
+            vertices: vertices.iter().map(|v| Vertex::new([v.x, v.y, v.z])).collect(),
+        }
+    }
+
+    /// Clears every occupied voxel whose center lies within `radius` of `center`,
+    /// returning the indices it cleared so the cut can be undone.
+    pub fn remove_sphere(&mut self, center: &Point3<f32>, radius: f32) -> Vec<usize> {
+        self.remove_within(center, radius, |point| (point - center).norm() <= radius)
+    }
+
+    /// Clears every occupied voxel whose center lies inside `cut`, returning the
+    /// indices it cleared so the cut can be undone.
+    pub fn remove_cut(&mut self, cut: &ToolCut) -> Vec<usize> {
+        let (center, radius) = cut.bounding_sphere();
+        self.remove_within(&center, radius, |point| cut.contains(point))
+    }
+
+    /// Clears the occupied voxels around the sphere (`center`, `radius`) whose centers
+    /// pass `inside`.
+    fn remove_within(&mut self, center: &Point3<f32>, radius: f32, inside: impl Fn(&Point3<f32>) -> bool) -> Vec<usize> {
+        let mut removed = Vec::new();
+        let (lo, hi) = self.voxel_range(center, radius);
+
+        for k in lo[2]..hi[2] {
+            for j in lo[1]..hi[1] {
+                for i in lo[0]..hi[0] {
+                    let index = self.index(i, j, k);
+                    if self.occupied[index] && inside(&self.center(i, j, k)) {
+                        self.occupied[index] = false;
+                        removed.push(index);
+                    }
+                }
+            }
+        }
+
+        removed
+    }
+
+    pub fn restore(&mut self, indices: &[usize]) {
+        for &index in indices {
+            self.occupied[index] = true;
+        }
+    }
+
+    /// Half-open voxel index range overlapping the axis-aligned box around a sphere.
+    fn voxel_range(&self, center: &Point3<f32>, radius: f32) -> ([usize; 3], [usize; 3]) {
+        let mut lo = [0; 3];
+        let mut hi = [0; 3];
+        for axis in 0..3 {
+            let to_index = |value: f32| ((value - self.origin[axis]) / self.voxel_size).floor();
+            lo[axis] = to_index(center[axis] - radius).max(0.0) as usize;
+            hi[axis] = ((to_index(center[axis] + radius) + 1.0).max(0.0) as usize).min(self.dims[axis]);
+        }
+        (lo, hi)
+    }
+}
+
+/// The solid a tool clears at one keypoint: the profile of its cutting end (see
+/// `ToolGeometry::radius_at`) swept from `tip` up `axis` for the tool's `length`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToolCut {
+    pub tip: Point3<f32>,
+    /// Unit direction from the tip up the tool.
+    pub axis: Vector3<f32>,
+    pub radius: f32,
+    pub length: f32,
+    pub geometry: ToolGeometry,
+}
+
+impl ToolCut {
+    pub fn contains(&self, point: &Point3<f32>) -> bool {
+        let offset = point - self.tip;
+        let height = offset.dot(&self.axis);
+        (0.0..=self.length).contains(&height) && (offset - self.axis * height).norm() <= self.geometry.radius_at(self.radius, height)
+    }
+
+    /// Center and radius of a sphere enclosing the cut.
+    fn bounding_sphere(&self) -> (Point3<f32>, f32) {
+        (self.tip + self.axis * (self.length / 2.0), self.radius.hypot(self.length / 2.0))
+    }
+}
+
+/// Offset of the ray filling a row from the row's voxel centers along Y and Z, as a
+/// fraction of the voxel size. Small enough not to change which voxels are filled.
+const ROW_JITTER: [f32; 2] = [0.0123, 0.0371];
+
+/// Crossings of a row's ray closer than this fraction of the voxel size are the same
+/// crossing found twice.
+const CROSSING_TOLERANCE: f32 = 1e-4;
+
+/// Corners of a marching cube as offsets from its first, in the order the marching
+/// cubes tables number them.
+const CUBE_CORNERS: [[isize; 3]; 8] = [
+    [0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0],
+    [0, 0, 1], [1, 0, 1], [1, 1, 1], [0, 1, 1],
+];
+
+/// The corners at either end of each edge the marching cubes tables number.
+const CUBE_EDGES: [(usize, usize); 12] = [
+    (0, 1), (1, 2), (2, 3), (3, 0),
+    (4, 5), (5, 6), (6, 7), (7, 4),
+    (0, 4), (1, 5), (2, 6), (3, 7),
+];
+
+/// X coordinates, in order, where a ray from `origin` along +X crosses the mesh
+/// surface before reaching `end_x`, with crossings within `tolerance` of the previous
+/// one dropped.
+fn row_crossings(tri_mesh: &TriMesh<f32>, origin: Point3<f32>, end_x: f32, tolerance: f32) -> Vec<f32> {
+    let mut crossings: Vec<f32> = ray_crossings(tri_mesh, &origin, &Vector3::x(), end_x - origin.x)
+        .into_iter()
+        .map(|toi| origin.x + toi)
+        .collect();
+    crossings.dedup_by(|next, previous| *next - *previous < tolerance);
+    crossings
+}
+
+/// Incremental material removal: cuts are applied one keypoint at a time and the
+/// voxels each cut removed are remembered so the simulation can also step backwards.
+#[derive(Debug, Clone)]
+pub struct MaterialSimulation {
+    grid: VoxelGrid,
+    removed: Vec<Vec<usize>>,
+}
+
+impl MaterialSimulation {
+    pub fn new(grid: VoxelGrid) -> Self {
+        MaterialSimulation {
+            grid,
+            removed: Vec::new(),
+        }
+    }
+
+    pub fn grid(&self) -> &VoxelGrid {
+        &self.grid
+    }
+
+    /// Number of cuts currently applied.
+    pub fn current_step(&self) -> usize {
+        self.removed.len()
+    }
+
+    pub fn apply_cut(&mut self, cut: &ToolCut) {
+        let removed = self.grid.remove_cut(cut);
+        self.removed.push(removed);
+    }
+
+    /// Restores the material removed by the most recent cut.
+    pub fn undo_cut(&mut self) {
+        if let Some(removed) = self.removed.pop() {
+            self.grid.restore(&removed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block() -> MaterialSimulation {
+        MaterialSimulation::new(VoxelGrid::filled(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 0.0), 0.5))
+    }
+
+    /// An upright flat end of `radius` with its tip at `tip`.
+    fn flat_cut(tip: &Point3<f32>, radius: f32) -> ToolCut {
+        ToolCut { tip: *tip, axis: Vector3::z(), radius, length: 20.0, geometry: ToolGeometry::FlatEnd }
+    }
+
+    #[test]
+    fn cuts_are_undone_in_reverse() {
+        let mut simulation = block();
+        let full = simulation.grid().occupied_count();
+        simulation.apply_cut(&flat_cut(&Point3::new(0.0, 0.0, -2.0), 2.0));
+        let cut = simulation.grid().occupied_count();
+        assert!(cut < full);
+        // Cutting where the material is already gone removes nothing
+        simulation.apply_cut(&flat_cut(&Point3::new(0.0, 0.0, -1.5), 1.0));
+        assert_eq!(simulation.grid().occupied_count(), cut);
+        assert_eq!(simulation.current_step(), 2);
+
+        simulation.undo_cut();
+        assert_eq!(simulation.grid().occupied_count(), cut);
+        simulation.undo_cut();
+        assert_eq!(simulation.grid().occupied_count(), full);
+        simulation.undo_cut();
+        assert_eq!(simulation.current_step(), 0);
+    }
+
+    #[test]
+    fn rewinding_to_a_step_matches_advancing_straight_to_it() {
+        // Overlapping cuts along the top, so undoing has to restore only what each removed
+        let cuts: Vec<Point3<f32>> = (0..10).map(|i| Point3::new(-4.0 + i as f32 * 0.8, 0.0, -1.5)).collect();
+        let mut rewound = block();
+        for cut in &cuts {
+            rewound.apply_cut(&flat_cut(cut, 1.5));
+        }
+        let at_ten = rewound.grid().occupied_count();
+        while rewound.current_step() > 3 {
+            rewound.undo_cut();
+        }
+        let mut fresh = block();
+        for cut in &cuts[..3] {
+            fresh.apply_cut(&flat_cut(cut, 1.5));
+        }
+
+        assert_eq!(rewound.grid().occupied, fresh.grid().occupied);
+        assert!(at_ten < fresh.grid().occupied_count());
+    }
+
+    use crate::test_support::{box_mesh, MeshBuilder};
+
+    /// Every face of `mesh` faces away from `center`, as on a convex surface around it.
+    fn faces_away_from(mesh: &IndexedMesh, center: &Point3<f32>) -> bool {
+        mesh.faces.iter().all(|face| {
+            let corner = |i: usize| {
+                let v = mesh.vertices[face.vertices[i]];
+                Point3::new(v[0], v[1], v[2])
+            };
+            let centroid = Point3::from((corner(0).coords + corner(1).coords + corner(2).coords) / 3.0);
+            let normal = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+            normal.dot(&(centroid - center)) > 0.0
+        })
+    }
+
+    /// Edges of `mesh` not walked the other way by exactly one neighbouring face. Zero
+    /// for a closed surface with consistent winding.
+    fn unmatched_edges(mesh: &IndexedMesh) -> usize {
+        let mut edges: HashMap<(usize, usize), isize> = HashMap::new();
+        for face in &mesh.faces {
+            for i in 0..3 {
+                let (a, b) = (face.vertices[i], face.vertices[(i + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += if a < b { 1 } else { -1 };
+            }
+        }
+        edges.values().filter(|&&balance| balance != 0).count()
+    }
+
+    #[test]
+    fn total_voxel_target_sets_the_grid_size() {
+        let (min, max) = (Point3::new(-50.0, -20.0, 0.0), Point3::new(50.0, 20.0, 25.0));
+        let voxel_size = SimulationResolution::TotalVoxels(1_000_000).voxel_size(&min, &max);
+        let dims = grid_dims(&min, &max, voxel_size);
+        let total = dims.iter().product::<usize>() as f32;
+        // Rounding each axis up only adds a few layers of voxels
+        assert!((total / 1e6 - 1.0).abs() < 0.05, "{:?} is {} voxels", dims, total);
+        // Cubic voxels, so the grid keeps the stock's proportions
+        assert!((dims[0] as f32 / dims[2] as f32 - 4.0).abs() < 0.1, "{:?}", dims);
+
+        // A flat stock spends the whole count on its other axes
+        let flat = Point3::new(50.0, 20.0, 0.0);
+        let dims = grid_dims(&min, &flat, SimulationResolution::TotalVoxels(1_000_000).voxel_size(&min, &flat));
+        assert_eq!(dims[2], 1);
+        assert!((dims[0] * dims[1]) as f32 / 1e6 > 0.95);
+
+        assert_eq!(grid_dims(&min, &max, SimulationResolution::VoxelsPerAxis(10).voxel_size(&min, &max)), [10, 4, 3]);
+        assert_eq!(grid_dims(&min, &max, SimulationResolution::VoxelSize(5.0).voxel_size(&min, &max)), [20, 8, 5]);
+    }
+
+    #[test]
+    fn surface_of_single_voxel_is_closed_and_faces_out() {
+        let grid = VoxelGrid::filled(Point3::origin(), Point3::new(1.0, 1.0, 1.0), 1.0);
+        let surface = grid.surface_mesh();
+        assert!(!surface.faces.is_empty());
+        assert_eq!(unmatched_edges(&surface), 0);
+        assert!(faces_away_from(&surface, &Point3::new(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn surface_of_block_is_closed_and_smooths_corners() {
+        let grid = VoxelGrid::filled(Point3::origin(), Point3::new(4.0, 3.0, 2.0), 1.0);
+        let surface = grid.surface_mesh();
+        assert_eq!(unmatched_edges(&surface), 0);
+        assert!(faces_away_from(&surface, &Point3::new(2.0, 1.5, 1.0)));
+        // Marching cubes cuts the corners off rather than stepping around them
+        let (min, max) = get_bounds(&surface).unwrap();
+        assert_eq!(min, Point3::origin());
+        assert_eq!(max, Point3::new(4.0, 3.0, 2.0));
+        assert!(surface.vertices.iter().all(|v| v != &Vertex::new([0.0, 0.0, 0.0])));
+    }
+
+    #[test]
+    fn surface_of_empty_grid_is_empty() {
+        let mut grid = VoxelGrid::filled(Point3::origin(), Point3::new(2.0, 2.0, 2.0), 1.0);
+        grid.remove_sphere(&Point3::new(1.0, 1.0, 1.0), 10.0);
+        assert!(grid.surface_mesh().faces.is_empty());
+    }
+
+    #[test]
+    fn tool_geometry_decides_which_voxels_a_cut_clears() {
+        // Plunging 1 into the top of a block along -Z, with the tool standing up +Z
+        let cleared_by = |geometry: ToolGeometry| {
+            let mut grid = VoxelGrid::filled(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 0.0), 0.5);
+            let cut = ToolCut { tip: Point3::new(0.0, 0.0, -1.0), axis: Vector3::z(), radius: 3.0, length: 20.0, geometry };
+            let mut removed = grid.remove_cut(&cut);
+            removed.sort_unstable();
+            removed
+        };
+        let flat = cleared_by(ToolGeometry::FlatEnd);
+        let ball = cleared_by(ToolGeometry::BallNose);
+        let vbit = cleared_by(ToolGeometry::Vbit { angle: 90.0 });
+
+        assert_ne!(flat, ball);
+        // The ball and the V-bit's point fit inside the flat end's cylinder
+        assert!(ball.iter().all(|index| flat.binary_search(index).is_ok()));
+        assert!(vbit.iter().all(|index| ball.binary_search(index).is_ok()));
+        assert!(vbit.len() < ball.len() && ball.len() < flat.len());
+        // Just above the tip, 2.5 off the axis: under the flat end, outside the ball
+        let grid = VoxelGrid::filled(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 0.0), 0.5);
+        let beside_the_tip = grid.index(15, 10, 8);
+        assert_eq!(grid.center(15, 10, 8), Point3::new(2.75, 0.25, -0.75));
+        assert!(flat.contains(&beside_the_tip) && !ball.contains(&beside_the_tip));
+    }
+
+    #[test]
+    fn voxelized_box_fills_exactly() {
+        let grid = VoxelGrid::from_mesh(&box_mesh(Point3::origin(), Point3::new(4.0, 4.0, 4.0)), 1.0).unwrap();
+        assert_eq!(grid.occupied_count(), 64);
+    }
+
+    /// A square pyramid on `[0, 4]²` whose apex sits exactly on a row of voxel centers,
+    /// and a box further along that row.
+    fn grazed_apex_mesh() -> IndexedMesh {
+        let mut builder = MeshBuilder::default();
+        let base: Vec<usize> = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]
+            .iter()
+            .map(|&(x, y)| builder.vertex(Point3::new(x, y, 0.0)))
+            .collect();
+        let apex = builder.vertex(Point3::new(2.0, 2.5, 2.5));
+        builder.quad(base[0], base[1], base[2], base[3], -Vector3::z());
+        let inside = Point3::new(2.0, 2.0, 0.5);
+        for i in 0..4 {
+            let (a, b) = (base[i], base[(i + 1) % 4]);
+            let corners = [Point3::new(0.0, 0.0, 0.0), Point3::new(4.0, 0.0, 0.0), Point3::new(4.0, 4.0, 0.0), Point3::new(0.0, 4.0, 0.0)];
+            let middle = Point3::from((corners[i].coords + corners[(i + 1) % 4].coords) / 2.0);
+            builder.face(a, b, apex, middle - inside);
+        }
+        let pyramid = builder.build();
+
+        let block = box_mesh(Point3::new(6.0, 0.0, 0.0), Point3::new(10.0, 4.0, 4.0));
+        let offset = pyramid.vertices.len();
+        IndexedMesh {
+            vertices: pyramid.vertices.iter().chain(block.vertices.iter()).copied().collect(),
+            faces: pyramid.faces.iter().cloned()
+                .chain(block.faces.iter().map(|face| IndexedTriangle {
+                    normal: face.normal,
+                    vertices: [face.vertices[0] + offset, face.vertices[1] + offset, face.vertices[2] + offset],
+                }))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn row_grazing_a_vertex_keeps_its_parity() {
+        let grid = VoxelGrid::from_mesh(&grazed_apex_mesh(), 1.0).unwrap();
+        assert_eq!(grid.dims, [10, 4, 4]);
+        // The row through the apex is empty over the pyramid and full in the box
+        assert!((0..6).all(|i| !grid.is_occupied(i, 2, 2)));
+        assert!((6..10).all(|i| grid.is_occupied(i, 2, 2)));
+    }
+
+    #[test]
+    fn coincident_crossings_count_once() {
+        let tri_mesh = indexed_mesh_to_trimesh(&box_mesh(Point3::origin(), Point3::new(4.0, 4.0, 4.0)));
+        // Through the diagonal edges splitting the box's X faces into triangles
+        let crossings = row_crossings(&tri_mesh, Point3::new(-1.0, 2.0, 2.0), 5.0, 1e-4);
+        assert_eq!(crossings.len(), 2);
+        assert!((crossings[0] - 0.0).abs() < 1e-4 && (crossings[1] - 4.0).abs() < 1e-4);
+    }
+}
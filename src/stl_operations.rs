@@ -1,21 +1,110 @@
-use crate::prelude::*;
+#[cfg(feature = "viewer")]
+use std::convert::TryFrom;
+#[cfg(feature = "viewer")]
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+#[cfg(feature = "viewer")]
+use std::rc::Rc;
 use std::fs::File;
-use anyhow::Result;
-use stl_io::{self, IndexedMesh, Vertex};
-use kiss3d::nalgebra::Point3;
+use std::io::{BufWriter, Write};
+use stl_io::{self, IndexedMesh, IndexedTriangle, Triangle, Vector, Vertex};
+#[cfg(feature = "viewer")]
+use kiss3d::scene::SceneNode;
+use nalgebra::Point3;
 use crate::errors::CAMError;
-use ncollide3d::query::{Ray, RayCast};
+use crate::mesh_loaders::loaders;
+use ncollide3d::query::{PointQuery, Ray, RayCast};
 use ncollide3d::shape::TriMesh;
 use ncollide3d::math::Point as NCPoint;
-use kiss3d::nalgebra::{ Vector3, Isometry3};
+use nalgebra::{ Vector3, Isometry3, Translation3, Unit, UnitQuaternion};
 
 
 
-pub fn load_stl(filename: &Path) -> Result<IndexedMesh> {
+/// Files at least this large are memory-mapped by `load_stl` when the `mmap` feature
+/// is enabled.
+#[cfg(feature = "mmap")]
+pub const MMAP_THRESHOLD_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Reads an ASCII or binary STL. Failing to open the file is an `Io` error; a file
+/// that opens but isn't valid STL is a `StlParse` error. With the `mmap` feature,
+/// files of `MMAP_THRESHOLD_BYTES` or more are read through `load_stl_mmap`.
+pub fn load_stl(filename: &Path) -> Result<IndexedMesh, CAMError> {
     let mut file = File::open(filename)?;
-    Ok(stl_io::read_stl(&mut file)?)
+    #[cfg(feature = "mmap")]
+    if file.metadata()?.len() >= MMAP_THRESHOLD_BYTES {
+        return read_stl_mapped(&file, filename);
+    }
+    stl_io::read_stl(&mut file).map_err(|e| CAMError::StlParse(format!("{}: {}", filename.display(), e)))
+}
+
+/// Reads an STL through a memory map instead of buffered reads, so the raw file is
+/// parsed straight from the mapped pages rather than copied through a read buffer.
+/// This does not stream: `stl_io` still builds the whole indexed mesh in memory, so
+/// the mesh itself costs as much as with `load_stl`. Gives the same mesh as the
+/// buffered path.
+#[cfg(feature = "mmap")]
+pub fn load_stl_mmap(filename: &Path) -> Result<IndexedMesh, CAMError> {
+    read_stl_mapped(&File::open(filename)?, filename)
+}
+
+#[cfg(feature = "mmap")]
+fn read_stl_mapped(file: &File, filename: &Path) -> Result<IndexedMesh, CAMError> {
+    // Safety: the file must not be truncated or rewritten while it is mapped. The map
+    // only lives for this call.
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    stl_io::read_stl(&mut std::io::Cursor::new(&map[..]))
+        .map_err(|e| CAMError::StlParse(format!("{}: {}", filename.display(), e)))
 }
+
+/// Loads a mesh, picking the reader by file extension (case-insensitive). STL is
+/// always supported; OBJ and PLY need the `obj` and `ply` features.
+pub fn load_mesh(path: &Path) -> Result<IndexedMesh, CAMError> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let loader = loaders()
+        .into_iter()
+        .find(|loader| loader.extensions().contains(&extension.as_str()))
+        .ok_or_else(|| CAMError::UnsupportedFormat(format!("{} (extension {:?})", path.display(), extension)))?;
+    loader.load(path)
+}
+
+/// Writes `mesh` to `filename` as a binary STL, or as ASCII STL when `binary` is
+/// false (handy for diffing and debugging). Face normals are written as stored.
+pub fn write_stl(mesh: &IndexedMesh, filename: &Path, binary: bool) -> Result<(), CAMError> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    let triangles = mesh.faces.iter().map(|face| Triangle {
+        normal: face.normal,
+        vertices: [
+            mesh.vertices[face.vertices[0]],
+            mesh.vertices[face.vertices[1]],
+            mesh.vertices[face.vertices[2]],
+        ],
+    });
+
+    if binary {
+        stl_io::write_stl(&mut writer, triangles)?;
+    } else {
+        writeln!(writer, "solid carver")?;
+        for triangle in triangles {
+            let n = triangle.normal;
+            writeln!(writer, "  facet normal {:e} {:e} {:e}", n[0], n[1], n[2])?;
+            writeln!(writer, "    outer loop")?;
+            for v in triangle.vertices.iter() {
+                writeln!(writer, "      vertex {:e} {:e} {:e}", v[0], v[1], v[2])?;
+            }
+            writeln!(writer, "    endloop")?;
+            writeln!(writer, "  endfacet")?;
+        }
+        writeln!(writer, "endsolid carver")?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
    /// Converts IndexedMesh to ncollide3d::shape::TriMesh
 pub fn indexed_mesh_to_trimesh(mesh: &IndexedMesh) -> TriMesh<f32> {
     let vertices: Vec<NCPoint<f32>> = mesh.vertices.iter()
@@ -23,52 +112,110 @@ pub fn indexed_mesh_to_trimesh(mesh: &IndexedMesh) -> TriMesh<f32> {
         .collect();
     
     let indices: Vec<Point3<usize>> = mesh.faces.iter()
-        .map(|f| Point3::new(f.vertices[0] as usize, f.vertices[1] as usize, f.vertices[2] as usize))
+        .map(|f| Point3::new(f.vertices[0], f.vertices[1], f.vertices[2]))
         .collect();
 
     TriMesh::new(vertices, indices, None)
 }
 
-    /// Checks if a point is inside the 3D model.
-pub fn is_point_inside_model( point: &Point3<f32>, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>) -> bool {
-        let epsilon = 1e-6;
-        let ray_start = point + normal * epsilon;
-        let ray = Ray::new(ncollide3d::math::Point::from(ray_start.coords), *normal);
+/// Checks if a point is inside the 3D model by crossing parity: a ray cast from the
+/// point along `normal` crosses the surface an odd number of times only from inside.
+/// Points on the surface count as inside. The model has to be closed. The ray only
+/// reaches as far as the far side of the mesh's bounding box, past which it can't
+/// cross the surface again.
+pub fn is_point_inside_model(point: &Point3<f32>, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>) -> bool {
+    // A ray leaving the surface outward doesn't hit it at its origin, so points on the
+    // surface are found by distance instead
+    if tri_mesh.distance_to_point(&Isometry3::identity(), &NCPoint::from(point.coords), false) <= CROSSING_EPSILON {
+        return true;
+    }
+    let direction = normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z);
+    let max_toi = mesh_ray_reach(point, tri_mesh);
+    ray_crossings(tri_mesh, point, &direction, max_toi).len() % 2 == 1
+}
 
-        let forward_hit = tri_mesh.toi_and_normal_with_ray(&Isometry3::identity(), &ray, std::f32::MAX, true);
-        let backward_ray = Ray::new(ncollide3d::math::Point::from(ray_start.coords), -normal);
-        let backward_hit = tri_mesh.toi_and_normal_with_ray(&Isometry3::identity(), &backward_ray, std::f32::MAX, true);
+/// Distance a ray is nudged past each hit so the next cast doesn't find it again.
+/// Far along a ray the nudge grows with the distance travelled, or it would round away.
+const CROSSING_EPSILON: f32 = 1e-5;
 
-        forward_hit.is_some() != backward_hit.is_some()
+/// Distances, in order, at which a ray from `origin` along the unit `direction`
+/// crosses the surface of `tri_mesh` within `max_toi`.
+pub fn ray_crossings(tri_mesh: &TriMesh<f32>, origin: &Point3<f32>, direction: &Vector3<f32>, max_toi: f32) -> Vec<f32> {
+    let mut crossings = Vec::new();
+    let mut travelled = 0.0;
+    while travelled < max_toi {
+        let ray = Ray::new(NCPoint::from((origin + direction * travelled).coords), *direction);
+        match tri_mesh.toi_with_ray(&Isometry3::identity(), &ray, max_toi - travelled, false) {
+            Some(toi) => {
+                let hit = travelled + toi;
+                crossings.push(hit);
+                travelled = hit + CROSSING_EPSILON.max(hit * 4.0 * f32::EPSILON);
+            }
+            None => break,
+        }
     }
+    crossings
+}
+
+/// Max time of impact for a ray cast from `origin` with a unit direction: just far
+/// enough to reach the far side of the bounding sphere (`center`, `radius`). All
+/// tasks tracing a surface from outside the mesh should bound their rays with this
+/// so they agree on which hits are reachable.
+pub fn ray_reach(origin: &Point3<f32>, center: &Point3<f32>, radius: f32) -> f32 {
+    (origin - center).norm() + radius
+}
+
+/// `ray_reach` for the sphere around the bounding box of `tri_mesh`.
+pub fn mesh_ray_reach(origin: &Point3<f32>, tri_mesh: &TriMesh<f32>) -> f32 {
+    let aabb = tri_mesh.aabb();
+    ray_reach(origin, &Point3::from(aabb.center().coords), aabb.half_extents().norm())
+}
 
-pub fn center_and_scale_mesh(mesh: &mut IndexedMesh) -> (f32, f32) {
+/// Centers `mesh` on the Z axis and rebases it to sit on Z = 0. With `target_size`,
+/// it is also scaled uniformly so its largest dimension equals that size; `None`
+/// keeps its size. Returns the new Z range, `(0, height)`.
+pub fn center_and_scale_mesh(mesh: &mut IndexedMesh, target_size: Option<f32>) -> (f32, f32) {
     let (min, max) = get_bounds(mesh).expect("Failed to get mesh bounds");
-    let center = [
-        (min.x + max.x) / 2.0,
-        (min.y + max.y) / 2.0,
-        0.0, // We don't center vertically
-    ];
-    let size = [
-        max.x - min.x,
-        max.y - min.y,
-        max.z - min.z,
-    ];
-    let scale = 1.0;// / size.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
-
-    let min_z = min.z * scale;
-    let max_z = max.z * scale;
+    let size = max - min;
+    let largest = size.x.max(size.y).max(size.z);
+    let scale = match target_size {
+        Some(target_size) if largest > f32::EPSILON => target_size / largest,
+        _ => 1.0,
+    };
+    // Centered in X and Y, resting on Z = 0
+    let anchor = Point3::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, min.z);
 
     for vertex in &mut mesh.vertices {
-        let scaled_vertex = [
-            (vertex[0] - center[0]) * scale,
-            (vertex[1] - center[1]) * scale,
-            (vertex[2] - min.z) * scale + min_z, // Adjust z to start from min_z
-        ];
-        *vertex = Vertex::new(scaled_vertex);
+        let p = (Point3::new(vertex[0], vertex[1], vertex[2]) - anchor) * scale;
+        *vertex = Vertex::new([p.x, p.y, p.z]);
     }
 
-    (min_z, max_z)
+    (0.0, size.z * scale)
+}
+
+/// Moves `mesh` by `iso`, e.g. to orient a part for another setup. Vertices get the
+/// full transform; face normals only the rotation, so they stay unit length and
+/// ray casts against the result see the same facing.
+pub fn transform_mesh(mesh: &mut IndexedMesh, iso: &Isometry3<f32>) {
+    for vertex in &mut mesh.vertices {
+        let p = iso * Point3::new(vertex[0], vertex[1], vertex[2]);
+        *vertex = Vertex::new([p.x, p.y, p.z]);
+    }
+    for face in &mut mesh.faces {
+        let n = iso.rotation * Vector3::new(face.normal[0], face.normal[1], face.normal[2]);
+        face.normal = Vector::new([n.x, n.y, n.z]);
+    }
+}
+
+/// Rotates `mesh` by `degrees` about `axis` through the origin, right-handed. A zero
+/// `axis` leaves the mesh as it is.
+pub fn rotate_mesh_deg(mesh: &mut IndexedMesh, axis: &Vector3<f32>, degrees: f32) {
+    let axis = match Unit::try_new(*axis, f32::EPSILON) {
+        Some(axis) => axis,
+        None => return,
+    };
+    let rotation = UnitQuaternion::from_axis_angle(&axis, degrees.to_radians());
+    transform_mesh(mesh, &Isometry3::from_parts(Translation3::identity(), rotation));
 }
 
 pub fn get_bounds(mesh: &IndexedMesh) -> Result<(Point3<f32>, Point3<f32>), CAMError> {
@@ -85,14 +232,819 @@ pub fn get_bounds(mesh: &IndexedMesh) -> Result<(Point3<f32>, Point3<f32>), CAME
         })
 }
 
-pub fn mesh_to_kiss3d(mesh: &IndexedMesh) -> kiss3d::resource::Mesh {
+/// Converts `mesh` for drawing with kiss3d. Fails if it has more vertices than a
+/// kiss3d mesh can index; see `add_mesh_chunks`.
+#[cfg(feature = "viewer")]
+pub fn mesh_to_kiss3d(mesh: &IndexedMesh) -> Result<kiss3d::resource::Mesh, CAMError> {
     let vertices: Vec<Point3<f32>> = mesh.vertices.iter()
         .map(|v| Point3::new(v[0], v[1], v[2]))
         .collect();
     
-    let indices: Vec<Point3<u16>> = mesh.faces.iter()
-        .map(|f| Point3::new(f.vertices[0] as u16, f.vertices[1] as u16, f.vertices[2] as u16))
+    Ok(kiss3d::resource::Mesh::new(vertices, kiss3d_faces(mesh)?, None, None, false))
+}
+
+/// The 12 edges of the axis-aligned box from `min` to `max`, as line segments.
+pub fn box_wireframe_edges(min: &Point3<f32>, max: &Point3<f32>) -> Vec<(Point3<f32>, Point3<f32>)> {
+    let corner = |i: usize| Point3::new(
+        if i & 1 == 0 { min.x } else { max.x },
+        if i & 2 == 0 { min.y } else { max.y },
+        if i & 4 == 0 { min.z } else { max.z },
+    );
+
+    // Corners are numbered by which coordinates are at the max, one bit per axis, so
+    // each edge joins a corner to the one differing in a single bit.
+    let mut edges = Vec::with_capacity(12);
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                edges.push((corner(i), corner(i | bit)));
+            }
+        }
+    }
+    edges
+}
+
+/// One segment per face from its centroid along its stored normal, scaled to
+/// `length`, for drawing the mesh's own normals. Faces with a zero normal get a
+/// zero-length segment.
+pub fn face_normal_lines(mesh: &IndexedMesh, length: f32) -> Vec<(Point3<f32>, Point3<f32>)> {
+    mesh.faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices.map(|i| Point3::new(mesh.vertices[i][0], mesh.vertices[i][1], mesh.vertices[i][2]));
+            let centroid = Point3::from((a.coords + b.coords + c.coords) / 3.0);
+            let normal = Vector3::new(face.normal[0], face.normal[1], face.normal[2]);
+            (centroid, centroid + normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros) * length)
+        })
+        .collect()
+}
+
+/// Most vertices a single kiss3d mesh can address with its `u16` face indices.
+pub const KISS3D_MAX_VERTICES: usize = u16::MAX as usize + 1;
+
+/// Splits `mesh` into parts of at most `KISS3D_MAX_VERTICES` vertices each, keeping
+/// every face whole. Fails if a face refers to a vertex the mesh doesn't have.
+pub fn split_for_kiss3d(mesh: &IndexedMesh) -> Result<Vec<IndexedMesh>, CAMError> {
+    let mut chunks = Vec::new();
+    let mut chunk = IndexedMesh { vertices: Vec::new(), faces: Vec::new() };
+    let mut local_indices: HashMap<usize, usize> = HashMap::new();
+
+    for (face_index, face) in mesh.faces.iter().enumerate() {
+        if let Some(&global) = face.vertices.iter().find(|&&global| global >= mesh.vertices.len()) {
+            return Err(CAMError::ProcessingError(format!(
+                "face {} refers to vertex {} but the mesh has {} vertices", face_index, global, mesh.vertices.len()
+            )));
+        }
+        if chunk.vertices.len() + 3 > KISS3D_MAX_VERTICES {
+            chunks.push(std::mem::replace(&mut chunk, IndexedMesh { vertices: Vec::new(), faces: Vec::new() }));
+            local_indices.clear();
+        }
+        let mut vertices = [0; 3];
+        for (local, &global) in vertices.iter_mut().zip(face.vertices.iter()) {
+            *local = *local_indices.entry(global).or_insert_with(|| {
+                chunk.vertices.push(mesh.vertices[global]);
+                chunk.vertices.len() - 1
+            });
+        }
+        chunk.faces.push(IndexedTriangle { normal: face.normal, vertices });
+    }
+    if !chunk.faces.is_empty() {
+        chunks.push(chunk);
+    }
+    Ok(chunks)
+}
+
+/// Adds `mesh` to `parent` as a group of kiss3d meshes, split by `split_for_kiss3d`.
+/// Color, visibility and the like set on the returned group apply to every part.
+#[cfg(feature = "viewer")]
+pub fn add_mesh_chunks(parent: &mut SceneNode, mesh: &IndexedMesh) -> Result<SceneNode, CAMError> {
+    let chunks = split_for_kiss3d(mesh)?;
+    let mut group = parent.add_group();
+    for chunk in &chunks {
+        group.add_mesh(Rc::new(RefCell::new(mesh_to_kiss3d(chunk)?)), Vector3::new(1.0, 1.0, 1.0));
+    }
+    Ok(group)
+}
+
+/// Face indices for a kiss3d mesh, which indexes vertices with `u16`. Fails on a face
+/// using a vertex past that range rather than drawing it with wrapped indices; split
+/// larger meshes with `split_for_kiss3d` first.
+#[cfg(feature = "viewer")]
+pub(crate) fn kiss3d_faces(mesh: &IndexedMesh) -> Result<Vec<Point3<u16>>, CAMError> {
+    mesh.faces.iter()
+        .enumerate()
+        .map(|(face_index, f)| {
+            let index = |i: usize| u16::try_from(f.vertices[i]).map_err(|_| CAMError::InvalidMesh(format!(
+                "face {} uses vertex {}, past the {} a kiss3d mesh can index; split the mesh with split_for_kiss3d",
+                face_index, f.vertices[i], KISS3D_MAX_VERTICES
+            )));
+            Ok(Point3::new(index(0)?, index(1)?, index(2)?))
+        })
+        .collect()
+}
+
+/// Reads the geometry of a rendered node (e.g. the simulation mesh) back into an
+/// IndexedMesh. Face normals are recomputed from the triangle winding, and the
+/// node's own transform is ignored, so the result stays in job coordinates.
+#[cfg(feature = "viewer")]
+pub fn scene_node_to_indexed_mesh(node: &SceneNode) -> Result<IndexedMesh, CAMError> {
+    let mut vertex_sets: Vec<Vec<Point3<f32>>> = Vec::new();
+    let mut face_sets: Vec<Vec<Point3<u16>>> = Vec::new();
+    node.read_vertices(&mut |coords: &[Point3<f32>]| vertex_sets.push(coords.to_vec()));
+    node.read_faces(&mut |faces: &[Point3<u16>]| face_sets.push(faces.to_vec()));
+    chunks_to_indexed_mesh(&vertex_sets, &face_sets)
+}
+
+/// Joins the vertex and face buffers of kiss3d meshes, paired by position, into one
+/// IndexedMesh with normals recomputed from the winding.
+#[cfg(feature = "viewer")]
+pub(crate) fn chunks_to_indexed_mesh(vertex_sets: &[Vec<Point3<f32>>], face_sets: &[Vec<Point3<u16>>]) -> Result<IndexedMesh, CAMError> {
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    for (coords, indices) in vertex_sets.iter().zip(face_sets.iter()) {
+        let base = vertices.len();
+        vertices.extend(coords.iter().map(|p| Vertex::new([p.x, p.y, p.z])));
+
+        for face in indices {
+            let (a, b, c) = (face.x as usize, face.y as usize, face.z as usize);
+            faces.push(IndexedTriangle {
+                normal: face_normal(&coords[a], &coords[b], &coords[c]),
+                vertices: [base + a, base + b, base + c],
+            });
+        }
+    }
+
+    if faces.is_empty() {
+        return Err(CAMError::InvalidMesh("Scene node has no faces".into()));
+    }
+    Ok(IndexedMesh { vertices, faces })
+}
+
+/// Convex hull of the mesh's vertices as a closed, outward-facing mesh, e.g. for
+/// workholding and fixturing analysis.
+pub fn convex_hull(mesh: &IndexedMesh) -> Result<IndexedMesh, CAMError> {
+    if mesh.vertices.len() < 4 {
+        return Err(CAMError::InvalidMesh("Convex hull needs at least 4 vertices".into()));
+    }
+    let points: Vec<NCPoint<f32>> = mesh.vertices.iter()
+        .map(|v| NCPoint::new(v[0], v[1], v[2]))
         .collect();
 
-    kiss3d::resource::Mesh::new(vertices, indices, None, None, false)
-}
\ No newline at end of file
+    let mut hull = ncollide3d::transformation::convex_hull(&points);
+    hull.unify_index_buffer();
+    let indices = hull.flat_indices();
+
+    let vertices = hull.coords.iter().map(|p| Vertex::new([p.x, p.y, p.z])).collect();
+    let faces = indices
+        .chunks(3)
+        .map(|tri| {
+            let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+            IndexedTriangle {
+                normal: face_normal(&hull.coords[a], &hull.coords[b], &hull.coords[c]),
+                vertices: [a, b, c],
+            }
+        })
+        .collect();
+
+    Ok(IndexedMesh { vertices, faces })
+}
+
+/// Volume enclosed by a closed, outward-facing mesh: the sum of the signed volumes of
+/// the tetrahedra its faces make with the origin.
+pub fn mesh_volume(mesh: &IndexedMesh) -> f32 {
+    let vertex = |i: usize| Vector3::new(mesh.vertices[i][0], mesh.vertices[i][1], mesh.vertices[i][2]);
+    mesh.faces.iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices.map(vertex);
+            a.dot(&b.cross(&c)) / 6.0
+        })
+        .sum()
+}
+
+/// Unit normal of a counter-clockwise triangle, or zero for a degenerate one.
+pub(crate) fn face_normal(a: &Point3<f32>, b: &Point3<f32>, c: &Point3<f32>) -> Vector<f32> {
+    let normal = (b - a).cross(&(c - a)).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros);
+    Vector::new([normal.x, normal.y, normal.z])
+}
+
+/// Topology summary of a mesh, from `validate_mesh`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshReport {
+    pub vertex_count: usize,
+    pub face_count: usize,
+    /// Edges used by only one face, i.e. the borders of holes.
+    pub boundary_edges: usize,
+    /// Edges shared by more than two faces.
+    pub non_manifold_edges: usize,
+    /// Faces wound opposite to the majority of their connected surface. These usually
+    /// show up as flipped normals.
+    pub inconsistent_winding_faces: usize,
+    /// Faces with (near) zero area, whose ray hits come back with garbage normals.
+    pub degenerate_faces: usize,
+    /// Vertices lying within `WELD_EPSILON` of an earlier vertex.
+    pub duplicate_vertices: usize,
+    /// Vertices no face uses.
+    pub unreferenced_vertices: usize,
+}
+
+impl MeshReport {
+    /// Human-readable descriptions of every problem found.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.boundary_edges > 0 {
+            warnings.push(format!("mesh has {} boundary edges and is not closed", self.boundary_edges));
+        }
+        if self.non_manifold_edges > 0 {
+            warnings.push(format!("mesh has {} edges shared by more than two faces", self.non_manifold_edges));
+        }
+        if self.inconsistent_winding_faces > 0 {
+            warnings.push(format!("mesh has {} faces wound opposite to their neighbors", self.inconsistent_winding_faces));
+        }
+        if self.degenerate_faces > 0 {
+            warnings.push(format!("mesh has {} zero-area faces", self.degenerate_faces));
+        }
+        if self.duplicate_vertices > 0 {
+            warnings.push(format!("mesh has {} duplicate vertices", self.duplicate_vertices));
+        }
+        if self.unreferenced_vertices > 0 {
+            warnings.push(format!("mesh has {} vertices not used by any face", self.unreferenced_vertices));
+        }
+        warnings
+    }
+}
+
+/// Checks edge sharing, winding consistency, degenerate faces and duplicate or unused
+/// vertices. Fails only for meshes that can't be used at all (no faces, or faces
+/// referencing missing vertices); everything else is reported in the `MeshReport`.
+pub fn validate_mesh(mesh: &IndexedMesh) -> Result<MeshReport, CAMError> {
+    if mesh.faces.is_empty() {
+        return Err(CAMError::InvalidMesh("Mesh has no faces".into()));
+    }
+    if let Some(face) = mesh.faces.iter().position(|face| face.vertices.iter().any(|&v| v >= mesh.vertices.len())) {
+        return Err(CAMError::InvalidMesh(format!("Face {} references a missing vertex", face)));
+    }
+
+    // Faces using each undirected edge, with whether the face runs along it from the
+    // lower to the higher vertex index
+    let mut edge_faces: HashMap<(usize, usize), Vec<(usize, bool)>> = HashMap::new();
+    for (face_index, face) in mesh.faces.iter().enumerate() {
+        for i in 0..3 {
+            let (a, b) = (face.vertices[i], face.vertices[(i + 1) % 3]);
+            edge_faces.entry((a.min(b), a.max(b))).or_default().push((face_index, a < b));
+        }
+    }
+
+    let mut report = MeshReport {
+        vertex_count: mesh.vertices.len(),
+        face_count: mesh.faces.len(),
+        degenerate_faces: mesh.faces.iter().filter(|face| is_degenerate(mesh, face)).count(),
+        duplicate_vertices: mesh.vertices.len() - welded_vertices(mesh).1.len(),
+        ..MeshReport::default()
+    };
+    let mut referenced = vec![false; mesh.vertices.len()];
+    for face in &mesh.faces {
+        for &v in &face.vertices {
+            referenced[v] = true;
+        }
+    }
+    report.unreferenced_vertices = referenced.iter().filter(|&&used| !used).count();
+
+    // Neighbors across manifold edges, flagged when the pair is wound inconsistently
+    // (both faces run along the shared edge in the same direction)
+    let mut neighbors: Vec<Vec<(usize, bool)>> = vec![Vec::new(); mesh.faces.len()];
+    for faces in edge_faces.values() {
+        match faces.as_slice() {
+            [_] => report.boundary_edges += 1,
+            [(f, f_forward), (g, g_forward)] => {
+                let flipped = f_forward == g_forward;
+                neighbors[*f].push((*g, flipped));
+                neighbors[*g].push((*f, flipped));
+            }
+            _ => report.non_manifold_edges += 1,
+        }
+    }
+
+    // Propagate an orientation over each connected surface; the faces in the
+    // minority orientation are the inconsistent ones
+    let mut orientation: Vec<Option<bool>> = vec![None; mesh.faces.len()];
+    for start in 0..mesh.faces.len() {
+        if orientation[start].is_some() {
+            continue;
+        }
+        orientation[start] = Some(false);
+        let mut stack = vec![start];
+        let (mut total, mut flipped_count) = (0, 0);
+        while let Some(face) = stack.pop() {
+            let flipped = orientation[face].unwrap();
+            total += 1;
+            if flipped {
+                flipped_count += 1;
+            }
+            for &(neighbor, relative) in &neighbors[face] {
+                if orientation[neighbor].is_none() {
+                    orientation[neighbor] = Some(flipped != relative);
+                    stack.push(neighbor);
+                }
+            }
+        }
+        report.inconsistent_winding_faces += flipped_count.min(total - flipped_count);
+    }
+
+    Ok(report)
+}
+
+/// Vertices closer than this are considered the same point.
+pub const WELD_EPSILON: f32 = 1e-5;
+
+fn is_degenerate(mesh: &IndexedMesh, face: &IndexedTriangle) -> bool {
+    let [a, b, c] = face.vertices.map(|v| {
+        let v = mesh.vertices[v];
+        Point3::new(v[0], v[1], v[2])
+    });
+    (b - a).cross(&(c - a)).norm() <= WELD_EPSILON * WELD_EPSILON
+}
+
+/// Maps every vertex to the index of its welded copy, and returns the welded vertices.
+/// Positions are snapped to a `WELD_EPSILON` grid, so the first vertex in each cell
+/// stands for all of them.
+fn welded_vertices(mesh: &IndexedMesh) -> (Vec<usize>, Vec<Vertex>) {
+    let mut cells: HashMap<[i64; 3], usize> = HashMap::new();
+    let mut welded = Vec::new();
+    let remap = mesh
+        .vertices
+        .iter()
+        .map(|v| {
+            let cell = [0, 1, 2].map(|axis| (v[axis] / WELD_EPSILON).round() as i64);
+            *cells.entry(cell).or_insert_with(|| {
+                welded.push(*v);
+                welded.len() - 1
+            })
+        })
+        .collect();
+    (remap, welded)
+}
+
+/// Welds duplicate vertices, then drops degenerate faces and any vertices left
+/// unused. Returns the report of the repaired mesh.
+pub fn repair_mesh(mesh: &mut IndexedMesh) -> Result<MeshReport, CAMError> {
+    let (remap, welded) = welded_vertices(mesh);
+    let welded = IndexedMesh {
+        vertices: welded,
+        faces: mesh
+            .faces
+            .iter()
+            .map(|face| IndexedTriangle { normal: face.normal, vertices: face.vertices.map(|v| remap[v]) })
+            .collect(),
+    };
+
+    // Drop degenerate faces and compact away the vertices only they were using
+    let mut compacted: Vec<Option<usize>> = vec![None; welded.vertices.len()];
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    for face in welded.faces.iter().filter(|face| !is_degenerate(&welded, face)) {
+        let indices = face.vertices.map(|v| {
+            *compacted[v].get_or_insert_with(|| {
+                vertices.push(welded.vertices[v]);
+                vertices.len() - 1
+            })
+        });
+        faces.push(IndexedTriangle { normal: face.normal, vertices: indices });
+    }
+    let repaired = IndexedMesh { vertices, faces };
+
+    let report = validate_mesh(&repaired)?;
+    *mesh = repaired;
+    Ok(report)
+}
+
+/// Closes every hole bounded by at most `max_boundary_edges` edges and returns how
+/// many were filled. Triangles close with the winding of the faces around them, and
+/// holes with more than three edges are fanned from a new vertex at their centroid.
+/// Holes whose border passes through a vertex shared with another border are left
+/// open, since their outline is ambiguous.
+pub fn fill_small_holes(mesh: &mut IndexedMesh, max_boundary_edges: usize) -> Result<usize, CAMError> {
+    validate_mesh(mesh)?;
+
+    let mut edge_uses: HashMap<(usize, usize), usize> = HashMap::new();
+    for face in &mesh.faces {
+        for i in 0..3 {
+            let (a, b) = (face.vertices[i], face.vertices[(i + 1) % 3]);
+            *edge_uses.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+
+    // Walk each boundary edge backwards, which is the direction a filling face runs
+    // along it
+    let mut next: HashMap<usize, usize> = HashMap::new();
+    let mut ambiguous: HashSet<usize> = HashSet::new();
+    for face in &mesh.faces {
+        for i in 0..3 {
+            let (a, b) = (face.vertices[i], face.vertices[(i + 1) % 3]);
+            if edge_uses[&(a.min(b), a.max(b))] == 1 && next.insert(b, a).is_some() {
+                ambiguous.insert(b);
+            }
+        }
+    }
+
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut filled = 0;
+    let mut starts: Vec<usize> = next.keys().copied().collect();
+    starts.sort_unstable();
+    for start in starts {
+        if visited.contains(&start) {
+            continue;
+        }
+        let mut hole = vec![start];
+        let mut closed = false;
+        while let Some(&vertex) = next.get(hole.last().unwrap()) {
+            if vertex == start {
+                closed = true;
+                break;
+            }
+            if hole.contains(&vertex) || hole.len() > max_boundary_edges {
+                break;
+            }
+            hole.push(vertex);
+        }
+        visited.extend(hole.iter().copied());
+        if !closed || hole.len() < 3 || hole.len() > max_boundary_edges || hole.iter().any(|v| ambiguous.contains(v)) {
+            continue;
+        }
+
+        let point = |v: usize| Point3::new(mesh.vertices[v][0], mesh.vertices[v][1], mesh.vertices[v][2]);
+        let points: Vec<Point3<f32>> = hole.iter().map(|&v| point(v)).collect();
+        if let [a, b, c] = hole[..] {
+            mesh.faces.push(IndexedTriangle { normal: face_normal(&points[0], &points[1], &points[2]), vertices: [a, b, c] });
+        } else {
+            let centroid = Point3::from(points.iter().map(|p| p.coords).sum::<Vector3<f32>>() / points.len() as f32);
+            let center = mesh.vertices.len();
+            mesh.vertices.push(Vertex::new([centroid.x, centroid.y, centroid.z]));
+            for i in 0..hole.len() {
+                let j = (i + 1) % hole.len();
+                mesh.faces.push(IndexedTriangle {
+                    normal: face_normal(&points[i], &points[j], &centroid),
+                    vertices: [hole[i], hole[j], center],
+                });
+            }
+        }
+        filled += 1;
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{box_mesh, cube_mesh, temp_path, trimesh, tube_mesh};
+
+    #[test]
+    fn stl_round_trips_in_binary_and_ascii() {
+        let mesh = cube_mesh(2.0);
+        for binary in [true, false].iter().copied() {
+            let path = temp_path(if binary { "binary.stl" } else { "ascii.stl" });
+            write_stl(&mesh, &path, binary).unwrap();
+            let size = std::fs::metadata(&path).unwrap().len();
+            let loaded = load_stl(&path);
+            std::fs::remove_file(&path).ok();
+
+            if binary {
+                // 80-byte header, triangle count, 50 bytes per triangle
+                assert_eq!(size, 84 + 50 * 12);
+            }
+            let loaded = loaded.unwrap();
+            assert_eq!(loaded.faces.len(), mesh.faces.len());
+            assert_eq!(loaded.vertices.len(), mesh.vertices.len());
+            assert_eq!(get_bounds(&loaded).unwrap(), get_bounds(&mesh).unwrap());
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_stl_matches_the_buffered_read() {
+        let mesh = crate::test_support::cylinder_mesh(10.0, 0.0, 5.0, 2000);
+        for binary in [true, false].iter().copied() {
+            let path = temp_path(if binary { "mapped.stl" } else { "mapped-ascii.stl" });
+            write_stl(&mesh, &path, binary).unwrap();
+            let buffered = stl_io::read_stl(&mut File::open(&path).unwrap());
+            let mapped = load_stl_mmap(&path);
+            std::fs::remove_file(&path).ok();
+            let (buffered, mapped) = (buffered.unwrap(), mapped.unwrap());
+
+            assert_eq!(mapped.faces.len(), mesh.faces.len());
+            assert_eq!(mapped.vertices, buffered.vertices);
+            assert!(mapped.faces.iter().zip(&buffered.faces).all(|(a, b)| a.vertices == b.vertices && a.normal == b.normal));
+        }
+        assert!(matches!(load_stl_mmap(&temp_path("missing.stl")), Err(CAMError::Io(_))));
+    }
+
+    #[test]
+    fn box_wireframe_has_one_edge_per_side_of_each_face() {
+        let (min, max) = (Point3::new(-1.0, -2.0, 0.0), Point3::new(1.0, 2.0, 5.0));
+        let edges = box_wireframe_edges(&min, &max);
+        assert_eq!(edges.len(), 12);
+        // Four edges along each axis, each as long as the box on that axis
+        for (axis, length) in [2.0, 4.0, 5.0].iter().copied().enumerate() {
+            let along: Vec<_> = edges
+                .iter()
+                .filter(|(start, end)| (end - start).iter().filter(|d| **d != 0.0).count() == 1 && end[axis] != start[axis])
+                .collect();
+            assert_eq!(along.len(), 4);
+            assert!(along.iter().all(|(start, end)| end[axis] - start[axis] == length));
+        }
+        let mut distinct = edges.iter().map(|(start, end)| format!("{} {}", start, end)).collect::<Vec<_>>();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 12);
+    }
+
+    #[test]
+    fn face_normals_are_drawn_from_each_centroid() {
+        let mut mesh = cube_mesh(2.0);
+        let lines = face_normal_lines(&mesh, 0.5);
+        assert_eq!(lines.len(), mesh.faces.len());
+        for ((start, end), face) in lines.iter().zip(&mesh.faces) {
+            let centroid = face.vertices.iter().map(|&i| Vector3::new(mesh.vertices[i][0], mesh.vertices[i][1], mesh.vertices[i][2])).sum::<Vector3<f32>>() / 3.0;
+            assert!((start.coords - centroid).norm() < 1e-6);
+            let normal = Vector3::new(face.normal[0], face.normal[1], face.normal[2]);
+            assert!((end - start - normal * 0.5).norm() < 1e-6);
+        }
+
+        // Stored normals that aren't unit length still give `length`, and zero gives nothing
+        mesh.faces[0].normal = Vector::new([0.0, 0.0, -3.0]);
+        mesh.faces[1].normal = Vector::new([0.0, 0.0, 0.0]);
+        let lines = face_normal_lines(&mesh, 0.5);
+        assert!(((lines[0].1 - lines[0].0) - Vector3::new(0.0, 0.0, -0.5)).norm() < 1e-6);
+        assert_eq!(lines[1].0, lines[1].1);
+    }
+
+    #[test]
+    fn reversed_faces_are_reported_as_inconsistent_winding() {
+        let mut mesh = cube_mesh(2.0);
+        let report = validate_mesh(&mesh).unwrap();
+        assert_eq!((report.boundary_edges, report.non_manifold_edges, report.inconsistent_winding_faces), (0, 0, 0));
+        assert!(report.warnings().is_empty());
+
+        // Both triangles of one side turned inside out
+        for face in &mut mesh.faces[..2] {
+            face.vertices.swap(1, 2);
+        }
+        let report = validate_mesh(&mesh).unwrap();
+        assert_eq!((report.boundary_edges, report.non_manifold_edges, report.inconsistent_winding_faces), (0, 0, 2));
+        assert_eq!(report.warnings(), vec!["mesh has 2 faces wound opposite to their neighbors".to_string()]);
+    }
+
+    #[test]
+    fn repair_welds_duplicates_and_drops_degenerate_faces() {
+        let mut mesh = cube_mesh(2.0);
+        // A copy of vertex 0 used by the first face, a stray vertex and a zero-area face
+        mesh.vertices.push(mesh.vertices[0]);
+        mesh.vertices.push(Vertex::new([10.0, 10.0, 10.0]));
+        let first = &mut mesh.faces[0].vertices;
+        let corner = first.iter().position(|&v| v == 0).unwrap();
+        first[corner] = 8;
+        mesh.faces.push(IndexedTriangle { normal: Vector::new([0.0, 0.0, 1.0]), vertices: [0, 1, 0] });
+
+        let report = validate_mesh(&mesh).unwrap();
+        assert_eq!((report.degenerate_faces, report.duplicate_vertices, report.unreferenced_vertices), (1, 1, 1));
+
+        let repaired = repair_mesh(&mut mesh).unwrap();
+        assert_eq!((mesh.vertices.len(), mesh.faces.len()), (8, 12));
+        assert!(repaired.warnings().is_empty(), "{:?}", repaired.warnings());
+    }
+
+    #[test]
+    fn points_inside_a_cube_are_found_by_crossing_parity() {
+        let cube = trimesh(&cube_mesh(10.0));
+        let directions = [Vector3::x(), -Vector3::z(), Vector3::new(1.0, 2.0, 3.0)];
+        for direction in directions.iter() {
+            // Rays from inside hit the cube in both directions; only one is counted
+            assert!(is_point_inside_model(&Point3::new(1.0, -2.0, 0.5), direction, &cube));
+            assert!(!is_point_inside_model(&Point3::new(8.0, 0.0, 0.0), direction, &cube));
+            assert!(!is_point_inside_model(&Point3::new(-8.0, 0.0, 0.0), direction, &cube));
+            assert!(!is_point_inside_model(&Point3::new(0.0, 0.0, 50.0), direction, &cube));
+        }
+        // On a face counts as inside
+        assert!(is_point_inside_model(&Point3::new(5.0, 1.0, 1.0), &Vector3::x(), &cube));
+        assert!(is_point_inside_model(&Point3::new(1.0, 1.0, -5.0), &Vector3::z(), &cube));
+    }
+
+    #[test]
+    fn rays_on_a_unit_cube_reach_just_past_it() {
+        let cube = trimesh(&cube_mesh(1.0));
+        let center = Point3::origin();
+        let reach = mesh_ray_reach(&center, &cube);
+        assert!((reach - 0.75f32.sqrt()).abs() < 1e-6);
+
+        // Toward a corner the hit is in reach, and lands on the face rather than past it
+        let near_corner = Vector3::new(1.0, 0.9, 0.9).normalize();
+        let crossings = ray_crossings(&cube, &center, &near_corner, reach);
+        assert_eq!(crossings.len(), 1);
+        assert!((crossings[0] - 0.5 / near_corner.x).abs() < 1e-6);
+        let crossings = ray_crossings(&cube, &Point3::new(0.1, -0.2, 0.3), &Vector3::x(), mesh_ray_reach(&Point3::new(0.1, -0.2, 0.3), &cube));
+        assert_eq!(crossings.len(), 1);
+        assert!((crossings[0] - 0.4).abs() < 1e-6);
+
+        // Just inside and just outside a face, seen along a slanted ray
+        let slanted = Vector3::new(1.0, 2.0, 3.0);
+        assert!(is_point_inside_model(&Point3::new(0.499, 0.2, -0.3), &slanted, &cube));
+        assert!(!is_point_inside_model(&Point3::new(0.501, 0.2, -0.3), &slanted, &cube));
+        assert!(!is_point_inside_model(&Point3::new(0.501, 0.2, -0.3), &-slanted, &cube));
+    }
+
+    #[test]
+    fn crossings_far_along_a_ray_are_each_found_once() {
+        // Ten thousand units out, a fixed nudge past a hit rounds away and the same
+        // face is hit again and again
+        let cube = trimesh(&cube_mesh(1.0));
+        let origin = Point3::new(-10_000.0, 0.0, 0.0);
+        let crossings = ray_crossings(&cube, &origin, &Vector3::x(), mesh_ray_reach(&origin, &cube));
+        assert_eq!(crossings.len(), 2);
+        assert!((crossings[0] - 9_999.5).abs() < 1e-2 && (crossings[1] - 10_000.5).abs() < 1e-2);
+    }
+
+    #[test]
+    fn meshes_are_centered_on_z_and_optionally_scaled() {
+        let part = || box_mesh(Point3::new(2.0, -4.0, 3.0), Point3::new(6.0, 4.0, 13.0));
+
+        // Without a target size, only moved: centered in X and Y, resting on Z = 0
+        let mut kept = part();
+        assert_eq!(center_and_scale_mesh(&mut kept, None), (0.0, 10.0));
+        assert_eq!(get_bounds(&kept).unwrap(), (Point3::new(-2.0, -4.0, 0.0), Point3::new(2.0, 4.0, 10.0)));
+
+        // Halving the largest side halves every side
+        let mut halved = part();
+        assert_eq!(center_and_scale_mesh(&mut halved, Some(5.0)), (0.0, 5.0));
+        assert_eq!(get_bounds(&halved).unwrap(), (Point3::new(-1.0, -2.0, 0.0), Point3::new(1.0, 2.0, 5.0)));
+    }
+
+    #[test]
+    fn transforms_move_vertices_and_turn_normals() {
+        let original = box_mesh(Point3::new(1.0, 2.0, 3.0), Point3::new(4.0, 6.0, 8.0));
+        let close = |a: &IndexedMesh, b: &IndexedMesh| {
+            let near = |u: &Vector<f32>, v: &Vector<f32>| (0..3).all(|axis| (u[axis] - v[axis]).abs() < 1e-4);
+            a.vertices.iter().zip(&b.vertices).all(|(u, v)| near(u, v)) && a.faces.iter().zip(&b.faces).all(|(f, g)| near(&f.normal, &g.normal))
+        };
+
+        // A full turn, in steps, comes back to where it started
+        let mut turned = original.clone();
+        for _ in 0..3 {
+            rotate_mesh_deg(&mut turned, &Vector3::new(1.0, -2.0, 0.5), 120.0);
+        }
+        assert!(close(&turned, &original));
+
+        // Flipping the part over turns its top face down; moving it leaves normals be
+        let mut flipped = original.clone();
+        rotate_mesh_deg(&mut flipped, &Vector3::x(), 180.0);
+        let (min, max) = get_bounds(&flipped).unwrap();
+        assert!((min - Point3::new(1.0, -6.0, -8.0)).norm() < 1e-4 && (max - Point3::new(4.0, -2.0, -3.0)).norm() < 1e-4);
+        for (face, flipped_face) in original.faces.iter().zip(&flipped.faces) {
+            assert!((flipped_face.normal[2] + face.normal[2]).abs() < 1e-5);
+        }
+        let mut moved = original.clone();
+        transform_mesh(&mut moved, &Isometry3::translation(10.0, 0.0, -5.0));
+        assert!(moved.faces.iter().zip(&original.faces).all(|(f, g)| f.normal == g.normal));
+        assert_eq!(moved.vertices[0][0], original.vertices[0][0] + 10.0);
+
+        // A zero axis is no rotation at all
+        let mut unchanged = original.clone();
+        rotate_mesh_deg(&mut unchanged, &Vector3::zeros(), 90.0);
+        assert_eq!(unchanged.vertices, original.vertices);
+    }
+
+    #[test]
+    fn small_holes_are_filled_watertight() {
+        let closed = |mesh: &IndexedMesh| {
+            let report = validate_mesh(mesh).unwrap();
+            (report.boundary_edges, report.inconsistent_winding_faces) == (0, 0)
+        };
+
+        let mut mesh = cube_mesh(2.0);
+        let removed = mesh.faces.remove(5);
+        assert_eq!(validate_mesh(&mesh).unwrap().boundary_edges, 3);
+        assert_eq!(fill_small_holes(&mut mesh, 3).unwrap(), 1);
+        assert!(closed(&mesh));
+        let patch = mesh.faces.last().unwrap();
+        assert!((0..3).all(|axis| (patch.normal[axis] - removed.normal[axis]).abs() < 1e-5));
+
+        // A whole side missing is a four-edge hole, fanned from its centroid
+        let mut mesh = cube_mesh(2.0);
+        mesh.faces.drain(..2);
+        assert_eq!(fill_small_holes(&mut mesh, 3).unwrap(), 0);
+        assert_eq!(fill_small_holes(&mut mesh, 4).unwrap(), 1);
+        assert!(closed(&mesh));
+        assert_eq!((mesh.vertices.len(), mesh.faces.len()), (9, 14));
+    }
+
+    #[test]
+    fn convex_hull_closes_over_holes() {
+        let hull = convex_hull(&tube_mesh(2.0, 5.0, 0.0, 4.0, 16)).unwrap();
+        // Only the outer wall's vertices are on the hull
+        assert_eq!(hull.vertices.len(), 32);
+        assert!(hull.vertices.iter().all(|v| (v[0].hypot(v[1]) - 5.0).abs() < 1e-4));
+        let center = Vector3::new(0.0, 0.0, 2.0);
+        let vertex = |i: usize| Vector3::new(hull.vertices[i][0], hull.vertices[i][1], hull.vertices[i][2]);
+        for face in &hull.faces {
+            let centroid = face.vertices.iter().map(|&i| vertex(i)).sum::<Vector3<f32>>() / 3.0;
+            let normal = Vector3::new(face.normal[0], face.normal[1], face.normal[2]);
+            assert!(normal.dot(&(centroid - center)) > 0.0, "face facing inward");
+        }
+        // Closed over the hole: the whole 16-sided prism, hole included
+        let prism = 0.5 * 16.0 * 25.0 * (2.0 * std::f32::consts::PI / 16.0).sin() * 4.0;
+        assert!((mesh_volume(&hull) - prism).abs() < 1e-2, "{} vs {}", mesh_volume(&hull), prism);
+
+        let too_few = IndexedMesh { vertices: cube_mesh(1.0).vertices[..3].to_vec(), faces: Vec::new() };
+        assert!(matches!(convex_hull(&too_few), Err(CAMError::InvalidMesh(_))));
+    }
+
+    #[test]
+    fn convex_hull_drops_interior_points() {
+        let mut cube = cube_mesh(10.0);
+        let interior = [1.0, 2.0, -1.0];
+        cube.vertices.push(Vertex::new(interior));
+        let hull = convex_hull(&cube).unwrap();
+
+        assert_eq!(hull.vertices.len(), 8);
+        assert!(hull.vertices.iter().all(|v| (0..3).all(|axis| (v[axis].abs() - 5.0).abs() < 1e-4)));
+        assert!(!hull.vertices.iter().any(|v| (0..3).all(|axis| (v[axis] - interior[axis]).abs() < 1e-4)));
+        assert!((mesh_volume(&hull) - 1000.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn large_meshes_split_into_kiss3d_sized_parts() {
+        // Separate triangles share no vertices, so each part holds a third as many faces
+        let faces = 50_000;
+        let normal = Vector::new([0.0, 0.0, 1.0]);
+        let mesh = IndexedMesh {
+            vertices: (0..3 * faces).map(|i| Vertex::new([i as f32, (i % 3) as f32, 0.0])).collect(),
+            faces: (0..faces).map(|i| IndexedTriangle { normal, vertices: [3 * i, 3 * i + 1, 3 * i + 2] }).collect(),
+        };
+        let chunks = split_for_kiss3d(&mesh).unwrap();
+        let per_chunk = KISS3D_MAX_VERTICES / 3;
+        assert_eq!(chunks.iter().map(|chunk| chunk.faces.len()).collect::<Vec<_>>(), vec![per_chunk, per_chunk, faces - 2 * per_chunk]);
+        assert!(chunks.iter().all(|chunk| chunk.vertices.len() <= KISS3D_MAX_VERTICES));
+        let last = chunks.last().unwrap();
+        let first_face = &last.faces[0];
+        assert_eq!(first_face.vertices.map(|i| last.vertices[i]), mesh.faces[2 * per_chunk].vertices.map(|i| mesh.vertices[i]));
+
+        // Small meshes stay whole, and faces past the vertex list are an error
+        let cube = cube_mesh(1.0);
+        assert_eq!(split_for_kiss3d(&cube).unwrap().len(), 1);
+        let mut broken = cube;
+        broken.faces[3].vertices[1] = 99;
+        assert!(matches!(split_for_kiss3d(&broken), Err(CAMError::ProcessingError(_))));
+    }
+
+    #[cfg(feature = "viewer")]
+    #[test]
+    fn rendered_chunks_join_back_into_one_mesh() {
+        let mesh = cube_mesh(2.0);
+        // Two kiss3d meshes, as a node split by `add_mesh_chunks` reads back
+        let halves = [&mesh.faces[..6], &mesh.faces[6..]];
+        let vertices: Vec<Point3<f32>> = mesh.vertices.iter().map(|v| Point3::new(v[0], v[1], v[2])).collect();
+        let vertex_sets = vec![vertices.clone(), vertices];
+        let face_sets: Vec<Vec<Point3<u16>>> = halves
+            .iter()
+            .map(|faces| faces.iter().map(|f| f.vertices.map(|i| i as u16)).map(|[a, b, c]| Point3::new(a, b, c)).collect())
+            .collect();
+
+        let joined = chunks_to_indexed_mesh(&vertex_sets, &face_sets).unwrap();
+        assert_eq!(joined.vertices.len(), 2 * mesh.vertices.len());
+        assert_eq!(joined.faces.len(), mesh.faces.len());
+        for (joined_face, face) in joined.faces.iter().zip(&mesh.faces) {
+            let position = |i: usize| joined.vertices[i];
+            assert_eq!(joined_face.vertices.map(position), face.vertices.map(|i| mesh.vertices[i]));
+            let (a, b) = (joined_face.normal, face.normal);
+            assert!((0..3).all(|axis| (a[axis] - b[axis]).abs() < 1e-5));
+        }
+
+        assert!(matches!(chunks_to_indexed_mesh(&[], &[]), Err(CAMError::InvalidMesh(_))));
+    }
+
+    #[test]
+    fn volume_of_closed_meshes() {
+        assert!((mesh_volume(&cube_mesh(2.0)) - 8.0).abs() < 1e-4);
+        let offset = box_mesh(Point3::new(10.0, 20.0, 30.0), Point3::new(13.0, 22.0, 31.0));
+        assert!((mesh_volume(&offset) - 6.0).abs() < 1e-3);
+    }
+
+    #[cfg(feature = "viewer")]
+    #[test]
+    fn meshes_past_u16_indices_are_rejected() {
+        let vertices: Vec<Vertex> = (0..70_000).map(|i| Vertex::new([(i % 300) as f32, (i / 300) as f32, 0.0])).collect();
+        let normal = Vector::new([0.0, 0.0, 1.0]);
+        let faces = (0..vertices.len() - 2).step_by(3)
+            .map(|i| IndexedTriangle { normal, vertices: [i, i + 1, i + 2] })
+            .collect::<Vec<_>>();
+        let mut mesh = IndexedMesh { vertices, faces };
+
+        assert!(matches!(kiss3d_faces(&mesh), Err(CAMError::InvalidMesh(_))));
+        assert!(mesh_to_kiss3d(&mesh).is_err());
+
+        // Up to the last vertex u16 can index, every face is kept as it is
+        mesh.faces.retain(|face| face.vertices.iter().all(|&i| i <= u16::MAX as usize));
+        let drawn = kiss3d_faces(&mesh).unwrap();
+        assert_eq!(drawn.len(), mesh.faces.len());
+        assert_eq!(drawn.last(), Some(&Point3::new(65532, 65533, 65534)));
+    }
+}
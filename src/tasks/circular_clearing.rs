@@ -1,12 +1,14 @@
-use crate::prelude::*;
-use crate::cam_job::{CAMTask, Keypoint};
+use crate::cam_job::{CAMTask, Keypoint, Units};
 use crate::errors::CAMError;
-use crate::stl_operations::{indexed_mesh_to_trimesh, is_point_inside_model};
-use kiss3d::nalgebra::{Point3, Vector3, Isometry3};
+use crate::tasks::TaskConfig;
+use crate::stl_operations::{is_point_inside_model, mesh_ray_reach};
+use crate::tolerances::Tolerances;
+use nalgebra::{Point3, Vector3, Isometry3};
 use ncollide3d::query::{Ray, RayCast};
 use ncollide3d::shape::TriMesh;
 use stl_io::IndexedMesh;
 
+#[derive(Clone)]
 pub struct CircularClearing {
     start_position: Point3<f32>,
     end_position: Point3<f32>,
@@ -15,11 +17,25 @@ pub struct CircularClearing {
     num_points_per_ring: usize,
     max_shrink_amount: f32,
     min_shrink_amount: f32,
+    /// Minimum distance kept between a ring and the mesh, measured inward.
+    clearance: f32,
+    /// Rings a layer must cut before a blocked ring completes it. Until then a blocked
+    /// ring moves the layer inward by `max_shrink_amount` to try again. Zero, the
+    /// default, completes a layer at its first blocked ring.
+    min_rings: usize,
+    /// Material the roughing rings leave on the wall, measured inward. When positive,
+    /// a finishing pass then cuts it away at `finish_stepover`.
+    finish_allowance: f32,
+    finish_stepover: f32,
+    tool_id: usize,
     keypoints: Vec<Keypoint>,
     layer_completed: Vec<bool>,
+    rings_cut: Vec<usize>,
 }
 
 impl CircularClearing {
+    // Every argument shapes the rings, bar the tool that cuts them
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         start_position: Point3<f32>,
         end_position: Point3<f32>,
@@ -28,6 +44,7 @@ impl CircularClearing {
         num_points_per_ring: usize,
         max_shrink_amount: f32,
         min_shrink_amount: f32,
+        tool_id: usize,
     ) -> Self {
         CircularClearing {
             start_position,
@@ -37,72 +54,114 @@ impl CircularClearing {
             num_points_per_ring,
             max_shrink_amount,
             min_shrink_amount,
+            clearance: 0.0,
+            min_rings: 0,
+            finish_allowance: 0.0,
+            finish_stepover: 0.0,
+            tool_id,
             keypoints: Vec::new(),
             layer_completed: vec![false; num_layers],
+            rings_cut: vec![0; num_layers],
         }
     }
 
+    /// See `clearance`. Keeps every ring this far inside the mesh's walls; zero, the
+    /// default, lets rings run right up to them.
+    pub fn with_clearance(mut self, clearance: f32) -> Self {
+        self.clearance = clearance;
+        self
+    }
+
+    /// See `min_rings`. Lets clearing reach material below a narrow opening the outer
+    /// ring can't pass. Retried rings are only cut where they lie outside the model,
+    /// so a part wider than `initial_radius` isn't cut into.
+    pub fn with_min_rings(mut self, min_rings: usize) -> Self {
+        self.min_rings = min_rings;
+        self
+    }
+
+    /// Leaves `allowance` on the wall during roughing, then removes it with rings
+    /// `stepover` apart, the last one right at the wall. Those keypoints are marked
+    /// `finishing`. An allowance of zero skips the finishing pass.
+    pub fn with_finishing(mut self, allowance: f32, stepover: f32) -> Self {
+        self.finish_allowance = allowance;
+        self.finish_stepover = stepover;
+        self
+    }
+
     fn generate_ring_points(&self, center: &Point3<f32>, radius: f32, normal: &Vector3<f32>) -> Vec<(Point3<f32>, Vector3<f32>)> {
-        let mut points = Vec::new();
-        
-        let v1 = if normal.x.abs() < normal.y.abs() && normal.x.abs() < normal.z.abs() {
-            Vector3::new(1.0, 0.0, 0.0).cross(normal).normalize()
+        ring_points(center, radius, normal, self.num_points_per_ring)
+    }
+
+    fn is_ring_valid(&self, center: &Point3<f32>, radius: f32, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances) -> bool {
+        let clearance = self.clearance + self.finish_allowance;
+        is_ring_clear(center, radius, normal, self.num_points_per_ring, clearance, tolerances.min_ring_radius, tri_mesh)
+    }
+
+    /// Rings from just inside `rough_radius` down to the smallest clear one, at most
+    /// `finish_stepover` apart. The wall is looked for no deeper than the allowance
+    /// plus one `max_shrink_amount`, the most roughing can have stopped short by.
+    fn finishing_radii(&self, center: &Point3<f32>, rough_radius: f32, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances) -> Vec<f32> {
+        let is_clear = |radius: f32| {
+            is_ring_clear(center, radius, normal, self.num_points_per_ring, self.clearance, tolerances.min_ring_radius, tri_mesh)
+        };
+
+        let mut low = rough_radius - self.finish_allowance - self.max_shrink_amount;
+        let mut high = rough_radius;
+        let wall = if is_clear(low) {
+            low
         } else {
-            Vector3::new(0.0, 1.0, 0.0).cross(normal).normalize()
+            while high - low > tolerances.search_precision {
+                let mid = (low + high) / 2.0;
+                if is_clear(mid) {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            high
         };
-        let v2 = normal.cross(&v1);
 
-        for i in 0..self.num_points_per_ring {
-            let angle = i as f32 * 2.0 * std::f32::consts::PI / self.num_points_per_ring as f32;
-            let direction = (v1 * angle.cos() + v2 * angle.sin()).normalize();
-            let point = center + direction * radius;
-            points.push((point, direction));
+        let stepover = self.finish_stepover.max(tolerances.search_precision);
+        let mut radii = Vec::new();
+        let mut radius = rough_radius - stepover;
+        while radius > wall {
+            radii.push(radius);
+            radius -= stepover;
         }
-
-        points
+        if wall < rough_radius {
+            radii.push(wall);
+        }
+        radii
     }
 
-    fn is_ring_valid(&self, center: &Point3<f32>, radius: f32, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>) -> bool {
-        let points = self.generate_ring_points(&center, radius, &normal);
-        let num_points = points.len();
-        if (radius < 0.001){
-            return false;
-        }
-    
-        for i in 0..num_points {
-            let (current_point, _) = points[i];
-            let (next_point, _) = points[(i + 1) % num_points];
-    
-            let direction = next_point - current_point;
-            let ray = Ray::new(ncollide3d::math::Point::from(current_point.coords), direction);
-    
-            if let Some(toi) = tri_mesh.toi_with_ray(&Isometry3::identity(), &ray, std::f32::MAX, false) {
-                // If the intersection point is before the next point, the ring intersects with the model
-                if toi < direction.norm() || toi < 10. {
-                    return false;
+    fn finish_pass(&mut self, tri_mesh: &TriMesh<f32>, layer_positions: &[Point3<f32>], rough_radii: &[f32], normal: &Vector3<f32>, tolerances: &Tolerances) {
+        for layer in 0..self.num_layers {
+            let center = &layer_positions[layer];
+            for radius in self.finishing_radii(center, rough_radii[layer], normal, tri_mesh, tolerances) {
+                for (point, direction) in self.generate_ring_points(center, radius, normal) {
+                    let normal = surface_normal(tri_mesh, &point, &direction, radius);
+                    self.keypoints.push(Keypoint { layer, finishing: true, ..Keypoint::new(point, normal) });
                 }
             }
         }
-    
-        true
     }
-    
 
-    fn find_max_valid_shrink(&self, center: &Point3<f32>, current_radius: f32, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>) -> Option<f32> {
-        if self.is_ring_valid(center, current_radius - self.max_shrink_amount, normal, tri_mesh) {
+    fn find_max_valid_shrink(&self, center: &Point3<f32>, current_radius: f32, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances) -> Option<f32> {
+        if self.is_ring_valid(center, current_radius - self.max_shrink_amount, normal, tri_mesh, tolerances) {
             return Some(self.max_shrink_amount);
         }
 
-        if !self.is_ring_valid(center, current_radius - self.min_shrink_amount, normal, tri_mesh) {
+        if !self.is_ring_valid(center, current_radius - self.min_shrink_amount, normal, tri_mesh, tolerances) {
             return None;
         }
 
         let mut low = self.min_shrink_amount;
         let mut high = self.max_shrink_amount;
 
-        while high - low > 0.001 {  // Precision threshold
+        while high - low > tolerances.search_precision {
             let mid = (low + high) / 2.0;
-            if self.is_ring_valid(center, current_radius - mid, normal, tri_mesh) {
+            if self.is_ring_valid(center, current_radius - mid, normal, tri_mesh, tolerances) {
                 low = mid;
             } else {
                 high = mid;
@@ -112,7 +171,7 @@ impl CircularClearing {
         Some(low)
     }
 
-    fn process_phase(&mut self, tri_mesh: &TriMesh<f32>, layer_positions: &[Point3<f32>], current_radii: &mut [f32], normal: &Vector3<f32>) -> bool {
+    fn process_phase(&mut self, tri_mesh: &TriMesh<f32>, layer_positions: &[Point3<f32>], current_radii: &mut [f32], normal: &Vector3<f32>, tolerances: &Tolerances) -> bool {
         let mut any_valid_ring = false;
 
         for layer in 0..self.num_layers {
@@ -123,23 +182,29 @@ impl CircularClearing {
             let center = &layer_positions[layer];
             let radius = &mut current_radii[layer];
 
-            let proposed_shrink_amount = self.find_max_valid_shrink(center, *radius, normal, tri_mesh);
+            let proposed_shrink_amount = self.find_max_valid_shrink(center, *radius, normal, tri_mesh, tolerances);
             println!("Layer {}: Center {:?}, Current radius {}, Proposed shrink amount {:?}", layer, center, radius, proposed_shrink_amount);
             
             if let Some(shrink_amount) = proposed_shrink_amount {
-                let new_radius = (*radius - shrink_amount);//.max(self.min_shrink_amount);
+                let new_radius = *radius - shrink_amount;//.max(self.min_shrink_amount);
                 println!("Layer {}: Shrinking from {} to {}", layer, *radius, new_radius);
                 
                 let ring_points = self.generate_ring_points(center, new_radius, normal);
                 for (point, direction) in ring_points {
-                    self.keypoints.push(Keypoint {
-                        position: point,
-                        normal: direction,
-                    });
+                    let normal = surface_normal(tri_mesh, &point, &direction, new_radius);
+                    self.keypoints.push(Keypoint { layer, ..Keypoint::new(point, normal) });
                 }
                 
                 *radius = new_radius;
+                self.rings_cut[layer] += 1;
                 any_valid_ring = true;
+            } else if self.rings_cut[layer] < self.min_rings
+                && *radius - self.max_shrink_amount - self.clearance - self.finish_allowance >= tolerances.min_ring_radius
+            {
+                // Blocked before cutting enough, e.g. by a narrow opening above wider
+                // material: step inward and look for a clear ring in the next phase
+                *radius -= self.max_shrink_amount;
+                println!("Layer {}: ring blocked, retrying from radius {}", layer, *radius);
             } else {
                 self.layer_completed[layer] = true;
                 println!("Layer {} completed: No valid shrink amount found", layer);
@@ -152,17 +217,57 @@ impl CircularClearing {
 
 impl CAMTask for CircularClearing {
     fn get_tool_id(&self) -> usize {
-        1 as usize
+        self.tool_id
+    }
+    fn set_tool_id(&mut self, tool_id: usize) {
+        self.tool_id = tool_id;
+    }
+    fn name(&self) -> &str {
+        "CircularClearing"
+    }
+    fn describe(&self, units: Units) -> String {
+        format!("{}: {} layers, r={:.1}{}", self.name(), self.num_layers, units.from_mm(self.initial_radius), units.suffix())
+    }
+    fn estimated_keypoint_count(&self) -> usize {
+        // Rings shrink by at most max_shrink_amount, so this many fit at most
+        let rings = (self.initial_radius / self.max_shrink_amount.max(f32::EPSILON)).ceil().max(1.0) as usize;
+        self.num_layers * rings * self.num_points_per_ring
     }
-    fn process(&mut self, mesh: &IndexedMesh) -> Result<(), CAMError> {
+    fn clone_box(&self) -> Box<dyn CAMTask> {
+        Box::new(self.clone())
+    }
+    fn config(&self) -> TaskConfig {
+        TaskConfig::CircularClearing {
+            start_position: self.start_position,
+            end_position: self.end_position,
+            num_layers: self.num_layers,
+            initial_radius: self.initial_radius,
+            num_points_per_ring: self.num_points_per_ring,
+            max_shrink_amount: self.max_shrink_amount,
+            min_shrink_amount: self.min_shrink_amount,
+            clearance: self.clearance,
+            min_rings: self.min_rings,
+            finish_allowance: self.finish_allowance,
+            finish_stepover: self.finish_stepover,
+            tool_id: self.tool_id,
+        }
+    }
+    fn process(&mut self, _mesh: &IndexedMesh, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances, progress: &mut dyn FnMut(f32)) -> Result<(), CAMError> {
         println!("Processing circular clearing from {:?} to {:?}", self.start_position, self.end_position);
-        let tri_mesh = indexed_mesh_to_trimesh(mesh);
-
         self.keypoints.clear();
+        if self.num_layers == 0 {
+            return Err(CAMError::ProcessingError(format!("{} needs at least one layer", self.name())));
+        }
         self.layer_completed = vec![false; self.num_layers];
+        self.rings_cut = vec![0; self.num_layers];
 
-        let layer_height = (self.end_position - self.start_position).norm() / (self.num_layers - 1) as f32;
-        let normal = (self.end_position - self.start_position).normalize();
+        // A single layer sits at the start position
+        let layer_height = match self.num_layers {
+            1 => 0.0,
+            num_layers => (self.end_position - self.start_position).norm() / (num_layers - 1) as f32,
+        };
+        // Start and end may coincide for a single layer; its rings then lie in the XY plane
+        let normal = (self.end_position - self.start_position).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z);
         let layer_positions: Vec<Point3<f32>> = (0..self.num_layers)
             .map(|layer| self.start_position + normal * (layer as f32 * layer_height))
             .collect();
@@ -171,17 +276,24 @@ impl CAMTask for CircularClearing {
 
         let mut phase = 0;
         loop {
-            let any_valid_ring = self.process_phase(&tri_mesh, &layer_positions, &mut current_radii, &normal);
+            let any_valid_ring = self.process_phase(tri_mesh, &layer_positions, &mut current_radii, &normal, tolerances);
             
             println!("Completed phase {}", phase);
             phase += 1;
+            let completed = self.layer_completed.iter().filter(|&&completed| completed).count();
+            progress(completed as f32 / self.num_layers as f32);
 
-            if !any_valid_ring && self.layer_completed.iter().all(|&completed| completed==true) {
+            if !any_valid_ring && self.layer_completed.iter().all(|&completed| completed) {
                 println!("All layers completed or no valid rings found");
                 break;
             }
         }
 
+        if self.finish_allowance > 0.0 {
+            self.finish_pass(tri_mesh, &layer_positions, &current_radii, &normal, tolerances);
+            println!("Finished {} layers to within {}", self.num_layers, self.clearance);
+        }
+
         println!("Generated {} keypoints for circular clearing", self.keypoints.len());
         Ok(())
     }
@@ -189,4 +301,251 @@ impl CAMTask for CircularClearing {
     fn get_keypoints(&self) -> Vec<Keypoint> {
         self.keypoints.clone()
     }
-}
\ No newline at end of file
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+        self.keypoints = keypoints;
+    }
+}
+
+/// `num_points` evenly spaced points on the circle of `radius` around `center` in the
+/// plane perpendicular to `normal`, each paired with its outward direction.
+pub(crate) fn ring_points(center: &Point3<f32>, radius: f32, normal: &Vector3<f32>, num_points: usize) -> Vec<(Point3<f32>, Vector3<f32>)> {
+    let (v1, v2) = ring_basis(normal);
+
+    (0..num_points)
+        .map(|i| {
+            let angle = i as f32 * 2.0 * std::f32::consts::PI / num_points as f32;
+            let direction = (v1 * angle.cos() + v2 * angle.sin()).normalize();
+            (center + direction * radius, direction)
+        })
+        .collect()
+}
+
+/// Surface normal of the mesh seen from a ring point: a ray is cast from `point`
+/// toward the ring's center, `radius` away along `-outward`, and the normal of the
+/// first face it hits is returned, facing back along the ray. When it hits nothing,
+/// as on rings past the top of the part, `outward` itself is used. The ray stops at
+/// `ray_reach` of the mesh if that comes first.
+pub(crate) fn surface_normal(tri_mesh: &TriMesh<f32>, point: &Point3<f32>, outward: &Vector3<f32>, radius: f32) -> Vector3<f32> {
+    let ray = Ray::new(ncollide3d::math::Point::from(point.coords), -outward);
+    let max_toi = radius.min(mesh_ray_reach(point, tri_mesh));
+    match tri_mesh.toi_and_normal_with_ray(&Isometry3::identity(), &ray, max_toi, true) {
+        Some(hit) if hit.normal.dot(outward) < 0.0 => -hit.normal,
+        Some(hit) => hit.normal,
+        None => *outward,
+    }
+}
+
+/// Two unit vectors spanning the plane perpendicular to `normal`; angle zero on a
+/// ring points along the first.
+pub(crate) fn ring_basis(normal: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let v1 = if normal.x.abs() < normal.y.abs() && normal.x.abs() < normal.z.abs() {
+        Vector3::new(1.0, 0.0, 0.0).cross(normal).normalize()
+    } else {
+        Vector3::new(0.0, 1.0, 0.0).cross(normal).normalize()
+    };
+    let v2 = normal.cross(&v1);
+    (v1, v2)
+}
+
+/// A ring is clear when none of the segments between its adjacent points cross the
+/// mesh and it lies outside the model rather than buried in it. With a clearance, the
+/// ring offset inward by it must be clear as well. Rings smaller than `min_radius` after
+/// the clearance never are.
+pub(crate) fn is_ring_clear(
+    center: &Point3<f32>,
+    radius: f32,
+    normal: &Vector3<f32>,
+    num_points: usize,
+    clearance: f32,
+    min_radius: f32,
+    tri_mesh: &TriMesh<f32>,
+) -> bool {
+    if radius - clearance < min_radius {
+        return false;
+    }
+
+    let mut radii = vec![radius];
+    if clearance > 0.0 {
+        radii.push(radius - clearance);
+    }
+    radii.into_iter().all(|radius| {
+        let points = ring_points(center, radius, normal, num_points);
+        // Without crossings the whole ring is on one side of the surface, so one
+        // point tells whether it is in the open or inside solid material
+        !ring_hits_mesh(&points, tri_mesh)
+            && !points.first().is_some_and(|(point, outward)| is_point_inside_model(point, outward, tri_mesh))
+    })
+}
+
+fn ring_hits_mesh(points: &[(Point3<f32>, Vector3<f32>)], tri_mesh: &TriMesh<f32>) -> bool {
+    let num_points = points.len();
+    (0..num_points).any(|i| {
+        let (current_point, _) = points[i];
+        let (next_point, _) = points[(i + 1) % num_points];
+
+        let segment = next_point - current_point;
+        let length = segment.norm();
+        if length <= f32::EPSILON {
+            return false;
+        }
+        let ray = Ray::new(ncollide3d::math::Point::from(current_point.coords), segment / length);
+        // With a unit direction the toi is a distance, so any hit within the
+        // segment's length means the ring crosses the model
+        tri_mesh.toi_with_ray(&Isometry3::identity(), &ray, length, false).is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cube_mesh, cylinder_mesh, trimesh, tube_mesh};
+
+    // Off the cube's face diagonals so the inside test's rays don't graze an edge
+    fn center() -> Point3<f32> {
+        Point3::new(0.0, 0.7, 1.3)
+    }
+
+    fn clear(radius: f32, clearance: f32, tri_mesh: &TriMesh<f32>) -> bool {
+        is_ring_clear(&center(), radius, &Vector3::z(), 64, clearance, 0.001, tri_mesh)
+    }
+
+    fn radial_distance(keypoint: &Keypoint) -> f32 {
+        keypoint.position.x.hypot(keypoint.position.y)
+    }
+
+    #[test]
+    fn ring_around_cube_is_clear() {
+        let tri_mesh = trimesh(&cube_mesh(20.0));
+        assert!(clear(20.0, 0.0, &tri_mesh));
+    }
+
+    #[test]
+    fn ring_crossing_cube_is_not_clear() {
+        let tri_mesh = trimesh(&cube_mesh(20.0));
+        assert!(!clear(12.0, 0.0, &tri_mesh));
+    }
+
+    #[test]
+    fn ring_inside_cube_is_not_clear() {
+        let tri_mesh = trimesh(&cube_mesh(20.0));
+        assert!(!clear(5.0, 0.0, &tri_mesh));
+    }
+
+    #[test]
+    fn ring_above_cube_is_clear() {
+        let tri_mesh = trimesh(&cube_mesh(20.0));
+        let above = Point3::new(0.0, 0.7, 15.0);
+        assert!(is_ring_clear(&above, 5.0, &Vector3::z(), 64, 0.0, 0.001, &tri_mesh));
+    }
+
+    #[test]
+    fn clearance_checks_ring_offset_inward() {
+        let tri_mesh = trimesh(&cube_mesh(20.0));
+        assert!(clear(17.0, 1.0, &tri_mesh));
+        assert!(!clear(17.0, 3.0, &tri_mesh));
+    }
+
+    #[test]
+    fn clearance_is_set_by_with_clearance() {
+        let task = CircularClearing::new(Point3::origin(), Point3::new(0.0, 0.0, 10.0), 2, 30.0, 32, 5.0, 0.1, 0).with_clearance(2.5);
+        match task.config() {
+            TaskConfig::CircularClearing { clearance, .. } => assert_eq!(clearance, 2.5),
+            _ => panic!("wrong config variant"),
+        }
+    }
+
+    fn clear_cube(start: Point3<f32>, end: Point3<f32>, num_layers: usize) -> Result<Vec<Keypoint>, CAMError> {
+        let mesh = cube_mesh(20.0);
+        let tri_mesh = trimesh(&mesh);
+        let mut task = CircularClearing::new(start, end, num_layers, 30.0, 32, 5.0, 0.1, 0);
+        task.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {})?;
+        Ok(task.get_keypoints())
+    }
+
+    #[test]
+    fn finishing_rings_follow_roughing_and_reach_the_wall() {
+        let mesh = cube_mesh(20.0);
+        let tri_mesh = trimesh(&mesh);
+        let clear = |allowance: f32| {
+            let mut task = CircularClearing::new(Point3::new(0.0, 0.0, -5.0), Point3::new(0.0, 0.0, 5.0), 2, 30.0, 32, 5.0, 0.1, 0)
+                .with_finishing(allowance, 0.25);
+            task.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+            task.get_keypoints()
+        };
+        assert!(clear(0.0).iter().all(|keypoint| !keypoint.finishing));
+
+        let keypoints = clear(1.0);
+        let first_finishing = keypoints.iter().position(|keypoint| keypoint.finishing).expect("no finishing pass");
+        assert!(first_finishing > 0);
+        assert!(keypoints[first_finishing..].iter().all(|keypoint| keypoint.finishing));
+        let closest = |keypoints: &[Keypoint]| keypoints.iter().map(radial_distance).fold(f32::MAX, f32::min);
+        // The cube's corners are 10 * sqrt(2) out: roughing stops the allowance short
+        // of them, finishing comes in to within the search precision
+        let corner = 10.0 * std::f32::consts::SQRT_2;
+        assert!(closest(&keypoints[..first_finishing]) >= corner + 1.0 - 0.01);
+        assert!((closest(&keypoints[first_finishing..]) - corner).abs() < 0.05);
+    }
+
+    #[test]
+    fn zero_layers_is_an_error() {
+        let result = clear_cube(Point3::new(0.0, 0.0, -5.0), Point3::new(0.0, 0.0, 5.0), 0);
+        assert!(matches!(result, Err(CAMError::ProcessingError(_))));
+    }
+
+    #[test]
+    fn single_layer_sits_at_start() {
+        let keypoints = clear_cube(Point3::new(0.0, 0.0, -5.0), Point3::new(0.0, 0.0, 5.0), 1).unwrap();
+        assert!(!keypoints.is_empty());
+        assert!(keypoints.iter().all(|k| (k.position.z + 5.0).abs() < 1e-4));
+    }
+
+    #[test]
+    fn coincident_start_and_end_fall_back_to_z_axis() {
+        let start = Point3::new(0.0, 0.0, 2.0);
+        for num_layers in [1, 3].iter().copied() {
+            let keypoints = clear_cube(start, start, num_layers).unwrap();
+            assert!(!keypoints.is_empty());
+            assert!(keypoints.iter().all(|k| (k.position.z - 2.0).abs() < 1e-4 && k.normal.iter().all(|c| c.is_finite())));
+        }
+    }
+
+    /// Clears a tube with a hole of radius `inner` from a first ring in its wall,
+    /// returning the radial distance of every keypoint.
+    fn clear_tube(inner: f32, min_rings: usize) -> Vec<f32> {
+        let mesh = tube_mesh(inner, 20.0, -10.0, 10.0, 48);
+        let tri_mesh = trimesh(&mesh);
+        let mut task = CircularClearing::new(Point3::new(0.0, 0.0, -5.0), Point3::new(0.0, 0.0, 5.0), 2, 18.0, 48, 5.0, 0.1, 0)
+            .with_min_rings(min_rings);
+        task.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+        task.get_keypoints().iter().map(radial_distance).collect()
+    }
+
+    #[test]
+    fn blocked_first_ring_completes_layer_by_default() {
+        assert!(clear_tube(10.0, 0).is_empty());
+    }
+
+    #[test]
+    fn retry_proceeds_past_blocked_ring_into_narrow_opening() {
+        let radii = clear_tube(10.0, 1);
+        assert!(!radii.is_empty());
+        assert!(radii.iter().all(|&radius| radius < 10.0), "a retried ring was cut in the wall");
+    }
+
+    #[test]
+    fn retry_never_cuts_into_material() {
+        assert!(clear_tube(2.0, 1).iter().all(|&radius| radius < 2.0));
+    }
+
+    #[test]
+    fn rings_stop_at_wall_of_wide_part() {
+        let mesh = cylinder_mesh(33.0, -10.0, 10.0, 48);
+        let tri_mesh = trimesh(&mesh);
+        let mut task = CircularClearing::new(Point3::new(0.0, 0.0, -5.0), Point3::new(0.0, 0.0, 5.0), 3, 75.0, 48, 5.0, 0.1, 0);
+        task.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+
+        let keypoints = task.get_keypoints();
+        assert!(!keypoints.is_empty());
+        assert!(keypoints.iter().all(|k| radial_distance(k) > 33.0), "a ring was cut inside the part");
+    }
+}
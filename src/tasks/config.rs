@@ -0,0 +1,247 @@
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use stl_io::IndexedMesh;
+use crate::cam_job::CAMTask;
+use super::{
+    CircularClearing, ContourTrace, DrillHole, DrillHoles, HeightSampling, Lead, MultiContourTrace, RasterSurface, SpiralClearing, TraceMode,
+};
+
+/// The parameters needed to recreate a task, tagged by task type so a job's
+/// `Vec<Box<dyn CAMTask>>` can round-trip through a job file. Generated keypoints
+/// aren't stored; rebuild the job after loading it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TaskConfig {
+    ContourTrace {
+        num_rays: usize,
+        #[serde(with = "crate::serde_helpers::point3")]
+        position: Point3<f32>,
+        #[serde(with = "crate::serde_helpers::vector3")]
+        normal: Vector3<f32>,
+        mode: TraceMode,
+        #[serde(default)]
+        height_sampling: HeightSampling,
+        #[serde(default)]
+        lead: Lead,
+        tool_id: usize,
+    },
+    MultiContourTrace {
+        #[serde(with = "crate::serde_helpers::point3")]
+        start_position: Point3<f32>,
+        #[serde(with = "crate::serde_helpers::point3")]
+        end_position: Point3<f32>,
+        num_layers: usize,
+        num_rays: usize,
+        mode: TraceMode,
+        #[serde(default)]
+        height_sampling: HeightSampling,
+        #[serde(default)]
+        lead: Lead,
+        tool_id: usize,
+    },
+    CircularClearing {
+        #[serde(with = "crate::serde_helpers::point3")]
+        start_position: Point3<f32>,
+        #[serde(with = "crate::serde_helpers::point3")]
+        end_position: Point3<f32>,
+        num_layers: usize,
+        initial_radius: f32,
+        num_points_per_ring: usize,
+        max_shrink_amount: f32,
+        min_shrink_amount: f32,
+        #[serde(default)]
+        clearance: f32,
+        #[serde(default)]
+        min_rings: usize,
+        #[serde(default)]
+        finish_allowance: f32,
+        #[serde(default)]
+        finish_stepover: f32,
+        tool_id: usize,
+    },
+    SpiralClearing {
+        #[serde(with = "crate::serde_helpers::point3")]
+        start_position: Point3<f32>,
+        #[serde(with = "crate::serde_helpers::point3")]
+        end_position: Point3<f32>,
+        num_layers: usize,
+        initial_radius: f32,
+        num_points_per_turn: usize,
+        radial_step: f32,
+        #[serde(default)]
+        clearance: f32,
+        tool_id: usize,
+    },
+    RasterSurface {
+        stepover: f32,
+        point_spacing: f32,
+        z_start: f32,
+        ray_length: f32,
+        tool_id: usize,
+    },
+    DrillHoles {
+        holes: Vec<DrillHole>,
+        peck_depth: f32,
+        retract_height: f32,
+        tool_id: usize,
+    },
+}
+
+impl TaskConfig {
+    /// The same task with its ray, layer and ring-point counts divided by `divisor` (or
+    /// its raster spacing multiplied by it), keeping the start and end positions so the
+    /// result covers the same extent.
+    pub fn with_reduced_resolution(self, divisor: usize) -> TaskConfig {
+        let reduce = |count: usize, min: usize| (count / divisor.max(1)).max(min);
+        match self {
+            TaskConfig::ContourTrace { num_rays, position, normal, mode, height_sampling, lead, tool_id } => TaskConfig::ContourTrace {
+                num_rays: reduce(num_rays, 4),
+                position,
+                normal,
+                mode,
+                height_sampling,
+                lead,
+                tool_id,
+            },
+            TaskConfig::MultiContourTrace { start_position, end_position, num_layers, num_rays, mode, height_sampling, lead, tool_id } => {
+                TaskConfig::MultiContourTrace {
+                    start_position,
+                    end_position,
+                    num_layers: reduce(num_layers, 1),
+                    num_rays: reduce(num_rays, 4),
+                    mode,
+                    height_sampling,
+                    lead,
+                    tool_id,
+                }
+            }
+            TaskConfig::CircularClearing {
+                start_position,
+                end_position,
+                num_layers,
+                initial_radius,
+                num_points_per_ring,
+                max_shrink_amount,
+                min_shrink_amount,
+                clearance,
+                min_rings,
+                finish_allowance,
+                finish_stepover,
+                tool_id,
+            } => TaskConfig::CircularClearing {
+                start_position,
+                end_position,
+                num_layers: reduce(num_layers, 2),
+                initial_radius,
+                num_points_per_ring: reduce(num_points_per_ring, 4),
+                max_shrink_amount,
+                min_shrink_amount,
+                clearance,
+                min_rings,
+                finish_allowance,
+                finish_stepover,
+                tool_id,
+            },
+            TaskConfig::SpiralClearing {
+                start_position,
+                end_position,
+                num_layers,
+                initial_radius,
+                num_points_per_turn,
+                radial_step,
+                clearance,
+                tool_id,
+            } => TaskConfig::SpiralClearing {
+                start_position,
+                end_position,
+                num_layers: reduce(num_layers, 1),
+                initial_radius,
+                num_points_per_turn: reduce(num_points_per_turn, 4),
+                radial_step,
+                clearance,
+                tool_id,
+            },
+            TaskConfig::RasterSurface { stepover, point_spacing, z_start, ray_length, tool_id } => TaskConfig::RasterSurface {
+                stepover: stepover * divisor.max(1) as f32,
+                point_spacing: point_spacing * divisor.max(1) as f32,
+                z_start,
+                ray_length,
+                tool_id,
+            },
+            // Every hole is needed and pecking is already cheap
+            drill @ TaskConfig::DrillHoles { .. } => drill,
+        }
+    }
+
+    pub fn into_task(self, mesh: &IndexedMesh) -> Box<dyn CAMTask> {
+        match self {
+            TaskConfig::ContourTrace { num_rays, position, normal, mode, height_sampling, lead, tool_id } => Box::new(
+                ContourTrace::new(num_rays, position, normal, mesh, tool_id)
+                    .with_mode(mode)
+                    .with_height_sampling(height_sampling)
+                    .with_lead(lead),
+            ),
+            TaskConfig::MultiContourTrace { start_position, end_position, num_layers, num_rays, mode, height_sampling, lead, tool_id } => {
+                Box::new(
+                    MultiContourTrace::new(start_position, end_position, num_layers, num_rays, tool_id)
+                        .with_mode(mode)
+                        .with_height_sampling(height_sampling)
+                        .with_lead(lead),
+                )
+            }
+            TaskConfig::CircularClearing {
+                start_position,
+                end_position,
+                num_layers,
+                initial_radius,
+                num_points_per_ring,
+                max_shrink_amount,
+                min_shrink_amount,
+                clearance,
+                min_rings,
+                finish_allowance,
+                finish_stepover,
+                tool_id,
+            } => Box::new(CircularClearing::new(
+                start_position,
+                end_position,
+                num_layers,
+                initial_radius,
+                num_points_per_ring,
+                max_shrink_amount,
+                min_shrink_amount,
+                tool_id,
+            )
+            .with_clearance(clearance)
+            .with_min_rings(min_rings)
+            .with_finishing(finish_allowance, finish_stepover)),
+            TaskConfig::SpiralClearing {
+                start_position,
+                end_position,
+                num_layers,
+                initial_radius,
+                num_points_per_turn,
+                radial_step,
+                clearance,
+                tool_id,
+            } => Box::new(
+                SpiralClearing::new(
+                    start_position,
+                    end_position,
+                    num_layers,
+                    initial_radius,
+                    num_points_per_turn,
+                    radial_step,
+                    tool_id,
+                )
+                .with_clearance(clearance),
+            ),
+            TaskConfig::RasterSurface { stepover, point_spacing, z_start, ray_length, tool_id } => Box::new(
+                RasterSurface::new(stepover, z_start, ray_length, mesh, tool_id).with_point_spacing(point_spacing),
+            ),
+            TaskConfig::DrillHoles { holes, peck_depth, retract_height, tool_id } => Box::new(
+                DrillHoles::new(holes, peck_depth, tool_id).with_retract_height(retract_height),
+            ),
+        }
+    }
+}
@@ -1,59 +1,270 @@
-use kiss3d::nalgebra::{Point3, Vector3, Unit, Isometry3};
+use nalgebra::{Point3, Vector3, Isometry3};
 use stl_io::IndexedMesh;
 use crate::cam_job::Keypoint;
 use crate::errors::CAMError;
-use crate::stl_operations::{get_bounds, indexed_mesh_to_trimesh};
-use crate::cam_job::CAMTask;
+use crate::stl_operations::{get_bounds, ray_reach};
+use crate::cam_job::{CAMTask, Units};
+use crate::tasks::TaskConfig;
+use crate::tolerances::Tolerances;
 use ncollide3d::query::{Ray, RayCast};
 use ncollide3d::shape::TriMesh;
 use ncollide3d::math::Point as NCPoint;
+use serde::{Deserialize, Serialize};
 
+/// How rays are laid out around a layer when tracing its contour.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TraceMode {
+    /// Rays point at the layer center from evenly spaced angles on a circle.
+    Polar,
+    /// Parallel rays are cast inward from the four sides of a square around the
+    /// layer, evenly spaced along each side. Samples corners of rectangular parts
+    /// far better than polar rays do.
+    Grid,
+}
+
+/// Where along the layer normal rays are cast from. Sampling a few heights around
+/// the layer and keeping the outermost hit per ray catches surfaces, like an
+/// overhanging lip, that a ray exactly at the layer height would pass under.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HeightSampling {
+    /// Shift of the sampled heights from the layer, along the layer normal.
+    pub offset: f32,
+    /// Number of heights to cast each ray at. One casts only at `offset`.
+    pub samples: usize,
+    /// Heights are spread evenly over `offset - spread ..= offset + spread`.
+    pub spread: f32,
+}
+
+impl Default for HeightSampling {
+    fn default() -> Self {
+        HeightSampling {
+            offset: 0.0,
+            samples: 1,
+            spread: 0.0,
+        }
+    }
+}
+
+impl HeightSampling {
+    /// Offsets along the layer normal to cast rays at.
+    fn heights(&self) -> Vec<f32> {
+        if self.samples <= 1 {
+            return vec![self.offset];
+        }
+        let step = 2.0 * self.spread / (self.samples - 1) as f32;
+        (0..self.samples).map(|i| self.offset - self.spread + i as f32 * step).collect()
+    }
+}
+
+/// Extra keypoints before the first and after the last point of a contour, so the
+/// tool enters and leaves the surface moving rather than dwelling on it. Leads lie in
+/// the layer plane on the outside of the surface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Lead {
+    #[default]
+    None,
+    /// Straight in along the surface normal from `length` away, and back out the same way.
+    Radial { length: f32 },
+    /// A quarter circle of `radius` meeting the contour tangentially, mirrored at the end.
+    Tangential { radius: f32 },
+}
+
+/// Keypoints a tangential lead's quarter circle is split into.
+const LEAD_ARC_SEGMENTS: usize = 4;
+
+#[derive(Clone)]
 pub struct ContourTrace {
     num_rays: usize,
     keypoints: Vec<Keypoint>,
     position: Point3<f32>,
     normal: Vector3<f32>,
+    center: Point3<f32>,
     bounding_radius: f32,
+    /// Extent of the mesh along `normal`: the lowest and highest `vertex · normal`.
+    mesh_span: (f32, f32),
+    mode: TraceMode,
+    height_sampling: HeightSampling,
+    lead: Lead,
+    tool_id: usize,
 }
 
 impl ContourTrace {
-    pub fn new(num_rays: usize, position: Point3<f32>, normal: Vector3<f32>, mesh: &IndexedMesh) -> Self {
+    pub fn new(num_rays: usize, position: Point3<f32>, normal: Vector3<f32>, mesh: &IndexedMesh, tool_id: usize) -> Self {
         let (min_bound, max_bound) = get_bounds(mesh).unwrap();
         let center = (min_bound + max_bound.coords) * 0.5;
         let bounding_radius = (max_bound - min_bound).norm() * 0.5;
+        let normal = normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z);
+        let mesh_span = mesh.vertices.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), v| {
+            let level = Vector3::new(v[0], v[1], v[2]).dot(&normal);
+            (low.min(level), high.max(level))
+        });
 
         ContourTrace {
             num_rays,
             keypoints: Vec::new(),
             position,
-            normal: normal.normalize(),
+            normal,
+            center,
             bounding_radius,
+            mesh_span,
+            mode: TraceMode::Polar,
+            height_sampling: HeightSampling::default(),
+            lead: Lead::None,
+            tool_id,
         }
     }
 
-    fn cast_ray(&self, tri_mesh: &TriMesh<f32>, origin: Point3<f32>, direction: Vector3<f32>) -> Option<Keypoint> {
-        let ray = Ray::new(NCPoint::from(origin.coords), direction);
-        let intersection = tri_mesh.toi_and_normal_with_ray(&Isometry3::identity(), &ray, 100 as f32, true);
+    pub fn with_mode(mut self, mode: TraceMode) -> Self {
+        self.mode = mode;
+        self
+    }
 
-        intersection.map(|intersection| {
-            let point = origin + direction * intersection.toi;
-            Keypoint {
-                position: point,
-                normal: intersection.normal,
+    pub fn with_height_sampling(mut self, height_sampling: HeightSampling) -> Self {
+        self.height_sampling = height_sampling;
+        self
+    }
+
+    pub fn with_lead(mut self, lead: Lead) -> Self {
+        self.lead = lead;
+        self
+    }
+
+    /// Moves the layer to `position`, keeping the normal and everything derived from
+    /// the mesh, so one trace can be reused for several layers of the same mesh.
+    pub fn set_position(&mut self, position: Point3<f32>) {
+        self.position = position;
+    }
+
+    /// Whether a layer at `position` lies so far past the mesh along the normal that no
+    /// sampled height can find a hit within `plane_distance` of it.
+    pub fn misses_mesh_at(&self, position: &Point3<f32>, plane_distance: f32) -> bool {
+        let level = position.coords.dot(&self.normal);
+        let heights = self.height_sampling.heights();
+        let lowest = level + heights.iter().cloned().fold(f32::INFINITY, f32::min);
+        let highest = level + heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        highest < self.mesh_span.0 - plane_distance || lowest > self.mesh_span.1 + plane_distance
+    }
+
+    /// Replaces the ray count with one derived from the spacing wanted between
+    /// neighbouring keypoints: `num_rays = ceil(2 * pi * r / stepover)`, where `r` is
+    /// the radius of the mesh's bounding sphere. That is the largest radius a contour
+    /// can have, so no layer is traced more sparsely than `stepover`, while layers
+    /// closer to the center come out denser.
+    pub fn with_stepover(mut self, stepover: f32) -> Self {
+        let circumference = 2.0 * std::f32::consts::PI * self.bounding_radius;
+        self.num_rays = (circumference / stepover).ceil().max(4.0) as usize;
+        self
+    }
+
+    /// Returns the (origin, direction) of every ray to cast for this layer.
+    /// Origins always sit outside the bounding sphere of the mesh.
+    fn generate_rays(&self, v1: &Vector3<f32>, v2: &Vector3<f32>) -> Vec<(Point3<f32>, Vector3<f32>)> {
+        let extent = self.bounding_radius + 1.0;
+
+        match self.mode {
+            TraceMode::Polar => (0..self.num_rays)
+                .map(|i| {
+                    let angle = i as f32 * 2.0 * std::f32::consts::PI / self.num_rays as f32;
+                    let outward = (v1 * angle.cos() + v2 * angle.sin()).normalize();
+                    (self.position + outward * extent, -outward)
+                })
+                .collect(),
+            TraceMode::Grid => {
+                let rays_per_side = (self.num_rays / 4).max(1);
+                let spacing = 2.0 * extent / rays_per_side as f32;
+                let sides = [(*v1, *v2), (-v1, *v2), (*v2, *v1), (-v2, *v1)];
+
+                sides
+                    .iter()
+                    .flat_map(|(outward, lateral)| {
+                        (0..rays_per_side).map(move |j| {
+                            let offset = -extent + (j as f32 + 0.5) * spacing;
+                            (self.position + outward * extent + lateral * offset, -outward)
+                        })
+                    })
+                    .collect()
             }
-        })
+        }
     }
-}
 
-impl CAMTask for ContourTrace {
-    fn get_tool_id(&self) -> usize {
-        1 as usize
+    /// Lead keypoints entering the contour at `at`, or with `lead_in` unset leaving it
+    /// there. `toward` is the contour's direction of travel at `at`. Returned in travel
+    /// order.
+    fn lead_points(&self, at: &Keypoint, toward: &Vector3<f32>, lead_in: bool) -> Vec<Keypoint> {
+        let in_plane = |v: &Vector3<f32>| (v - self.normal * v.dot(&self.normal)).try_normalize(f32::EPSILON);
+        let outward = match in_plane(&at.normal).or_else(|| in_plane(&(at.position - self.position))) {
+            Some(outward) => outward,
+            None => return Vec::new(),
+        };
+        // Square to the normal, so the arc stays at `radius` and meets the surface tangentially
+        let tangent = match in_plane(&(toward - outward * toward.dot(&outward))) {
+            Some(tangent) => tangent,
+            None => return Vec::new(),
+        };
+        let point = |position: Point3<f32>| Keypoint { position, ..at.clone() };
+
+        let mut points = match self.lead {
+            Lead::None => Vec::new(),
+            Lead::Radial { length } => vec![point(at.position + outward * length)],
+            Lead::Tangential { radius } => {
+                // Arc about a center `radius` outside the surface, from a quarter turn
+                // back along the contour down to (not including) the contour point
+                let center = at.position + outward * radius;
+                let behind = if lead_in { -tangent } else { tangent };
+                (1..=LEAD_ARC_SEGMENTS)
+                    .rev()
+                    .map(|k| {
+                        let angle = std::f32::consts::FRAC_PI_2 * k as f32 / LEAD_ARC_SEGMENTS as f32;
+                        point(center + (-outward * angle.cos() + behind * angle.sin()) * radius)
+                    })
+                    .collect()
+            }
+        };
+        if !lead_in {
+            points.reverse();
+        }
+        points
     }
-    fn process(&mut self, mesh: &IndexedMesh) -> Result<(), CAMError> {
-        println!("Processing contour trace at position: {:?}, normal: {:?}", self.position, self.normal);
-        let tri_mesh = indexed_mesh_to_trimesh(mesh);
 
+    /// Surrounds the traced contour with its lead-in and lead-out keypoints.
+    fn add_leads(&mut self) {
+        let n = self.keypoints.len();
+        if self.lead == Lead::None || n < 2 {
+            return;
+        }
+        let first = &self.keypoints[0];
+        let last = &self.keypoints[n - 1];
+        let lead_in = self.lead_points(first, &(self.keypoints[1].position - first.position), true);
+        let lead_out = self.lead_points(last, &(last.position - self.keypoints[n - 2].position), false);
+
+        let traced = std::mem::take(&mut self.keypoints);
+        self.keypoints = lead_in.into_iter().chain(traced).chain(lead_out).collect();
+    }
+
+    /// Casts one ray, bounded by `ray_reach` like every other tracer. Unusable hits
+    /// are dropped and counted in `discarded`, so the task reports them once rather
+    /// than per ray.
+    fn cast_ray(&self, tri_mesh: &TriMesh<f32>, origin: Point3<f32>, direction: Vector3<f32>, discarded: &mut usize) -> Option<Keypoint> {
+        let max_toi = ray_reach(&origin, &self.center, self.bounding_radius);
+        let ray = Ray::new(NCPoint::from(origin.coords), direction);
+        let intersection = tri_mesh.toi_and_normal_with_ray(&Isometry3::identity(), &ray, max_toi, true)?;
+
+        if !intersection.toi.is_finite() || intersection.toi > max_toi {
+            *discarded += 1;
+            return None;
+        }
+
+        Some(Keypoint::new(origin + direction * intersection.toi, intersection.normal))
+    }
+
+    /// Traces the layer at the current position, replacing the keypoints. Returns how
+    /// many ray hits were discarded.
+    pub(crate) fn trace(&mut self, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances) -> usize {
         self.keypoints.clear();
+        if self.misses_mesh_at(&self.position, tolerances.plane_distance) {
+            println!("Skipping contour trace at {:?}: the layer is outside the mesh", self.position);
+            return 0;
+        }
 
         // Calculate two perpendicular vectors in the plane
         let v1 = if self.normal.x.abs() < self.normal.y.abs() && self.normal.x.abs() < self.normal.z.abs() {
@@ -63,22 +274,93 @@ impl CAMTask for ContourTrace {
         };
         let v2 = self.normal.cross(&v1);
 
-        for i in 0..self.num_rays {
-            let angle = i as f32 * 2.0 * std::f32::consts::PI / self.num_rays as f32;
-            let direction = -(v1 * angle.cos() + v2 * angle.sin()).normalize();
-            
-            // Calculate the origin point outside the bounding sphere
-            let origin = self.position + (v1 * angle.cos() + v2 * angle.sin()) * (self.bounding_radius + 1.0);
-
-            if let Some(keypoint) = self.cast_ray(&tri_mesh, origin, direction) {
-                // Check if the keypoint is close to the plane defined by position and normal
-                let distance_to_plane = (keypoint.position - self.position).dot(&self.normal).abs();
-                if distance_to_plane < 0.1 {
-                    self.keypoints.push(keypoint);
-                }
+        let heights = self.height_sampling.heights();
+        let mut discarded = 0;
+        for (origin, direction) in self.generate_rays(&v1, &v2) {
+            // Cast at every sampled height and keep the hit closest to the ray origin,
+            // i.e. the outermost surface near this layer
+            let outermost = heights
+                .iter()
+                .filter_map(|&height| {
+                    let origin = origin + self.normal * height;
+                    let keypoint = self.cast_ray(tri_mesh, origin, direction, &mut discarded)?;
+                    // Check if the keypoint is close to the plane the ray was cast in
+                    let distance_to_plane = (keypoint.position - origin).dot(&self.normal).abs();
+                    if distance_to_plane < tolerances.plane_distance {
+                        Some(((keypoint.position - origin).norm(), keypoint))
+                    } else {
+                        None
+                    }
+                })
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+            if let Some((_, mut keypoint)) = outermost {
+                // Project back onto the layer so the contour stays planar
+                let height = (keypoint.position - self.position).dot(&self.normal);
+                keypoint.position -= self.normal * height;
+                self.keypoints.push(keypoint);
             }
         }
 
+        if self.mode == TraceMode::Grid {
+            // Grid rays are generated side by side, so put the hits back into contour order
+            let position = self.position;
+            let angle_of = |keypoint: &Keypoint| {
+                let offset = keypoint.position - position;
+                offset.dot(&v2).atan2(offset.dot(&v1))
+            };
+            self.keypoints.sort_by(|a, b| angle_of(a).partial_cmp(&angle_of(b)).unwrap_or(std::cmp::Ordering::Equal));
+        }
+        self.add_leads();
+
+        discarded
+    }
+}
+
+/// Reports the ray hits `task` discarded for landing beyond `ray_reach`, once for the
+/// whole task.
+pub(crate) fn report_discarded_hits(task: &str, discarded: usize) {
+    if discarded > 0 {
+        eprintln!("Warning: {} discarded {} ray hits beyond the mesh's reach", task, discarded);
+    }
+}
+
+impl CAMTask for ContourTrace {
+    fn get_tool_id(&self) -> usize {
+        self.tool_id
+    }
+    fn set_tool_id(&mut self, tool_id: usize) {
+        self.tool_id = tool_id;
+    }
+    fn name(&self) -> &str {
+        "ContourTrace"
+    }
+    fn describe(&self, units: Units) -> String {
+        let position = self.position.coords.map(|value| units.from_mm(value));
+        format!("{}: {} rays ({:?}) at {:?} {}", self.name(), self.num_rays, self.mode, position.as_slice(), units.suffix())
+    }
+    fn estimated_keypoint_count(&self) -> usize {
+        self.num_rays
+    }
+    fn clone_box(&self) -> Box<dyn CAMTask> {
+        Box::new(self.clone())
+    }
+    fn config(&self) -> TaskConfig {
+        TaskConfig::ContourTrace {
+            num_rays: self.num_rays,
+            position: self.position,
+            normal: self.normal,
+            mode: self.mode,
+            height_sampling: self.height_sampling,
+            lead: self.lead,
+            tool_id: self.tool_id,
+        }
+    }
+    fn process(&mut self, _mesh: &IndexedMesh, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances, _progress: &mut dyn FnMut(f32)) -> Result<(), CAMError> {
+        println!("Processing contour trace at position: {:?}, normal: {:?}", self.position, self.normal);
+
+        let discarded = self.trace(tri_mesh, tolerances);
+        report_discarded_hits("ContourTrace", discarded);
         println!("Generated {} keypoints for contour trace", self.keypoints.len());
         Ok(())
     }
@@ -86,4 +368,160 @@ impl CAMTask for ContourTrace {
     fn get_keypoints(&self) -> Vec<Keypoint> {
         self.keypoints.clone()
     }
-}
\ No newline at end of file
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+        self.keypoints = keypoints;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tasks::CircularClearing;
+    use crate::test_support::{box_mesh, cube_mesh, cylinder_mesh, trimesh};
+    use stl_io::IndexedTriangle;
+
+    fn on_cube_surface(keypoint: &Keypoint, half: f32) -> bool {
+        let p = keypoint.position;
+        (p.x.abs().max(p.y.abs()).max(p.z.abs()) - half).abs() < 1e-3
+    }
+
+    #[test]
+    fn tracers_agree_on_reach_from_far_origins() {
+        let mesh = cube_mesh(10.0);
+        let tri_mesh = trimesh(&mesh);
+        let tolerances = Tolerances::default();
+
+        let mut contour = ContourTrace::new(12, Point3::new(0.0, 0.0, 2.0), Vector3::z(), &mesh, 0);
+        assert_eq!(contour.trace(&tri_mesh, &tolerances), 0);
+        let traced = contour.get_keypoints();
+        assert_eq!(traced.len(), 12);
+        assert!(traced.iter().all(|keypoint| on_cube_surface(keypoint, 5.0)));
+
+        // The clearing's first ring is 500 out, far past any fixed ray length, yet
+        // its rays back to the center are bounded by the same reach and find the
+        // faces the contour's rays hit
+        let mut clearing = CircularClearing::new(Point3::new(0.0, 0.0, 2.0), Point3::new(0.0, 0.0, 3.0), 1, 1000.0, 12, 500.0, 0.1, 0);
+        clearing.process(&mesh, &tri_mesh, &tolerances, &mut |_| {}).unwrap();
+        let direction = |keypoint: &Keypoint| Vector3::new(keypoint.position.x, keypoint.position.y, 0.0).normalize();
+        let outer: Vec<Keypoint> = clearing.get_keypoints().into_iter().filter(|keypoint| (keypoint.position.xy().coords.norm() - 500.0).abs() < 1e-2).collect();
+        assert_eq!(outer.len(), 12);
+        for ring_point in &outer {
+            let hit = traced.iter().find(|keypoint| (direction(keypoint) - direction(ring_point)).norm() < 1e-3).expect("no contour hit in the ring point's direction");
+            assert!((hit.normal - ring_point.normal).norm() < 1e-4, "{:?} vs {:?}", hit.normal, ring_point.normal);
+        }
+        // Off the face centers, the face normal isn't the ring's own outward direction
+        assert!(outer.iter().any(|keypoint| (keypoint.normal - direction(keypoint)).norm() > 0.1));
+    }
+
+    #[test]
+    fn grid_mode_traces_box_sides_in_contour_order() {
+        let mesh = box_mesh(Point3::new(-10.0, -5.0, 0.0), Point3::new(10.0, 5.0, 10.0));
+        let tri_mesh = trimesh(&mesh);
+        let mut trace = ContourTrace::new(64, Point3::new(0.0, 0.0, 5.0), Vector3::z(), &mesh, 0).with_mode(TraceMode::Grid);
+        trace.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+        let keypoints = trace.get_keypoints();
+
+        let on_side = |keypoint: &Keypoint| {
+            let p = keypoint.position;
+            (p.x.abs() - 10.0).abs() < 1e-3 || (p.y.abs() - 5.0).abs() < 1e-3
+        };
+        assert!(keypoints.iter().all(|keypoint| on_side(keypoint) && (keypoint.position.z - 5.0).abs() < 1e-4));
+        // Parallel rays reach all four sides, long sides included
+        for (x, y) in [(10.0, 0.0), (-10.0, 0.0), (0.0, 5.0), (0.0, -5.0)].iter().copied() {
+            let normal = Vector3::new(x, y, 0.0).normalize();
+            assert!(keypoints.iter().any(|keypoint| (keypoint.normal - normal).norm() < 1e-3), "no hit facing {:?}", normal);
+        }
+        let angles: Vec<f32> = keypoints.iter().map(|keypoint| keypoint.position.y.atan2(keypoint.position.x)).collect();
+        assert!(angles.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", angles);
+    }
+
+    #[test]
+    fn grid_mode_reaches_corners_polar_rays_skip() {
+        let mesh = box_mesh(Point3::new(-10.0, -2.0, 0.0), Point3::new(10.0, 2.0, 10.0));
+        let tri_mesh = trimesh(&mesh);
+        // Distance from the worst-sampled corner to its nearest keypoint
+        let corner_gap = |mode: TraceMode| {
+            let mut trace = ContourTrace::new(16, Point3::new(0.0, 0.0, 5.0), Vector3::z(), &mesh, 0).with_mode(mode);
+            trace.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+            let keypoints = trace.get_keypoints();
+            [(10.0, 2.0), (-10.0, 2.0), (-10.0, -2.0), (10.0, -2.0)]
+                .iter()
+                .map(|&(x, y)| {
+                    let corner = Point3::new(x, y, 5.0);
+                    keypoints.iter().map(|keypoint| (keypoint.position - corner).norm()).fold(f32::MAX, f32::min)
+                })
+                .fold(0.0, f32::max)
+        };
+
+        // The corners are 11 degrees off the long axis, between two polar rays 22.5 apart
+        assert!(corner_gap(TraceMode::Grid) < 1.0);
+        assert!(corner_gap(TraceMode::Polar) > 1.5);
+    }
+
+    #[test]
+    fn leads_enter_and_leave_the_contour_from_outside() {
+        let mesh = cylinder_mesh(8.0, 0.0, 10.0, 24);
+        let tri_mesh = trimesh(&mesh);
+        let trace = |lead: Lead| {
+            let mut trace = ContourTrace::new(16, Point3::new(0.0, 0.0, 5.0), Vector3::z(), &mesh, 0).with_lead(lead);
+            trace.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+            trace.get_keypoints()
+        };
+        let plain = trace(Lead::None);
+        let n = plain.len();
+        assert_eq!(n, 16);
+        let (first, last) = (&plain[0], &plain[n - 1]);
+        let outward = |keypoint: &Keypoint| Vector3::new(keypoint.normal.x, keypoint.normal.y, 0.0).normalize();
+
+        // Straight in along the surface normal, and back out the same way
+        let radial = trace(Lead::Radial { length: 3.0 });
+        assert_eq!(radial.len(), n + 2);
+        assert!((radial[0].position - (first.position + outward(first) * 3.0)).norm() < 1e-4);
+        assert_eq!(radial[1].position, first.position);
+        assert!((radial[n + 1].position - (last.position + outward(last) * 3.0)).norm() < 1e-4);
+
+        // Quarter circles about a center 2 outside the surface, starting behind the
+        // contour and ending past it, in the layer plane
+        let tangential = trace(Lead::Tangential { radius: 2.0 });
+        assert_eq!(tangential.len(), n + 2 * LEAD_ARC_SEGMENTS);
+        let (lead_in, rest) = tangential.split_at(LEAD_ARC_SEGMENTS);
+        let (traced, lead_out) = rest.split_at(n);
+        assert!(traced.iter().zip(&plain).all(|(a, b)| a.position == b.position));
+        let (center_in, center_out) = (first.position + outward(first) * 2.0, last.position + outward(last) * 2.0);
+        assert!(lead_in.iter().all(|keypoint| ((keypoint.position - center_in).norm() - 2.0).abs() < 1e-4));
+        assert!(lead_out.iter().all(|keypoint| ((keypoint.position - center_out).norm() - 2.0).abs() < 1e-4));
+        assert!(lead_in.iter().chain(lead_out).all(|keypoint| (keypoint.position.z - 5.0).abs() < 1e-4));
+        let travel_in = plain[1].position - first.position;
+        let travel_out = last.position - plain[n - 2].position;
+        assert!((lead_in[0].position - first.position).dot(&travel_in) < 0.0);
+        assert!((lead_out[LEAD_ARC_SEGMENTS - 1].position - last.position).dot(&travel_out) > 0.0);
+        // The arc closes in on the contour point it meets
+        let gap = |keypoint: &Keypoint, at: &Keypoint| (keypoint.position - at.position).norm();
+        assert!(gap(&lead_in[LEAD_ARC_SEGMENTS - 1], first) < gap(&lead_in[0], first));
+        assert!(gap(&lead_out[0], last) < gap(&lead_out[LEAD_ARC_SEGMENTS - 1], last));
+    }
+
+    #[test]
+    fn height_sampling_catches_an_overhanging_lip() {
+        // A 6 wide post up to Z = 10 under a 12 wide cap
+        let mut mesh = box_mesh(Point3::new(-3.0, -3.0, 0.0), Point3::new(3.0, 3.0, 10.0));
+        let cap = box_mesh(Point3::new(-6.0, -6.0, 10.0), Point3::new(6.0, 6.0, 12.0));
+        let base = mesh.vertices.len();
+        mesh.vertices.extend(cap.vertices);
+        mesh.faces.extend(cap.faces.into_iter().map(|face| IndexedTriangle { vertices: face.vertices.map(|i| i + base), ..face }));
+        let tri_mesh = trimesh(&mesh);
+        let half_width = |keypoint: &Keypoint| keypoint.position.x.abs().max(keypoint.position.y.abs());
+
+        let mut at_layer = ContourTrace::new(16, Point3::new(0.0, 0.0, 9.5), Vector3::z(), &mesh, 0);
+        at_layer.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+        assert!(at_layer.get_keypoints().iter().all(|keypoint| (half_width(keypoint) - 3.0).abs() < 1e-3));
+
+        let sampling = HeightSampling { offset: 0.0, samples: 3, spread: 1.0 };
+        let mut sampled = ContourTrace::new(16, Point3::new(0.0, 0.0, 9.5), Vector3::z(), &mesh, 0).with_height_sampling(sampling);
+        sampled.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+        let keypoints = sampled.get_keypoints();
+        assert_eq!(keypoints.len(), 16);
+        // The lip's hits are brought back down to the layer
+        assert!(keypoints.iter().all(|keypoint| (half_width(keypoint) - 6.0).abs() < 1e-3 && (keypoint.position.z - 9.5).abs() < 1e-4));
+    }
+}
@@ -0,0 +1,140 @@
+use nalgebra::{Point3, Vector3};
+use ncollide3d::shape::TriMesh;
+use serde::{Deserialize, Serialize};
+use stl_io::IndexedMesh;
+use crate::cam_job::{CAMTask, Keypoint, Units};
+use crate::errors::CAMError;
+use crate::tasks::TaskConfig;
+use crate::tolerances::Tolerances;
+
+/// A hole to drill straight down (along -Z) from `top`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DrillHole {
+    /// Center of the hole where it meets the surface, in job coordinates.
+    #[serde(with = "crate::serde_helpers::point3")]
+    pub top: Point3<f32>,
+    pub depth: f32,
+}
+
+impl DrillHole {
+    pub fn new(top: Point3<f32>, depth: f32) -> Self {
+        DrillHole { top, depth }
+    }
+}
+
+/// Peck drilling at a list of holes. Each hole is a cycle of keypoints on its axis:
+/// down by `peck_depth` from the previous peck, back up to `retract_height` above
+/// the hole to clear chips, and down again until the full depth is reached. Keypoints
+/// of hole `i` are on layer `i`, and the first one of each hole is a rapid entry.
+#[derive(Clone)]
+pub struct DrillHoles {
+    holes: Vec<DrillHole>,
+    peck_depth: f32,
+    /// Height above each hole's top the tool starts at and retracts to between pecks.
+    retract_height: f32,
+    tool_id: usize,
+    keypoints: Vec<Keypoint>,
+}
+
+impl DrillHoles {
+    pub fn new(holes: Vec<DrillHole>, peck_depth: f32, tool_id: usize) -> Self {
+        DrillHoles {
+            holes,
+            peck_depth,
+            retract_height: 1.0,
+            tool_id,
+            keypoints: Vec::new(),
+        }
+    }
+
+    pub fn with_retract_height(mut self, retract_height: f32) -> Self {
+        self.retract_height = retract_height;
+        self
+    }
+
+    /// Depths below the top of a hole `depth` deep at which each peck stops.
+    fn peck_depths(&self, depth: f32) -> Vec<f32> {
+        let pecks = (depth / self.peck_depth.max(0.001)).ceil().max(1.0) as usize;
+        (1..=pecks).map(|peck| (peck as f32 * self.peck_depth).min(depth)).collect()
+    }
+}
+
+impl CAMTask for DrillHoles {
+    fn get_tool_id(&self) -> usize {
+        self.tool_id
+    }
+    fn set_tool_id(&mut self, tool_id: usize) {
+        self.tool_id = tool_id;
+    }
+    fn name(&self) -> &str {
+        "DrillHoles"
+    }
+    fn describe(&self, units: Units) -> String {
+        format!("{}: {} holes, peck {:.2}{}", self.name(), self.holes.len(), units.from_mm(self.peck_depth), units.suffix())
+    }
+    fn estimated_keypoint_count(&self) -> usize {
+        self.holes.iter().map(|hole| 1 + 2 * self.peck_depths(hole.depth).len()).sum()
+    }
+    fn clone_box(&self) -> Box<dyn CAMTask> {
+        Box::new(self.clone())
+    }
+    fn config(&self) -> TaskConfig {
+        TaskConfig::DrillHoles {
+            holes: self.holes.clone(),
+            peck_depth: self.peck_depth,
+            retract_height: self.retract_height,
+            tool_id: self.tool_id,
+        }
+    }
+    fn process(&mut self, _mesh: &IndexedMesh, _tri_mesh: &TriMesh<f32>, _tolerances: &Tolerances, _progress: &mut dyn FnMut(f32)) -> Result<(), CAMError> {
+        println!("Processing {} drill holes with peck depth {}", self.holes.len(), self.peck_depth);
+        self.keypoints.clear();
+
+        // The tool points straight up out of the hole, like a surface normal would
+        let up = Vector3::z();
+        for (layer, hole) in self.holes.iter().enumerate() {
+            let above = hole.top + up * self.retract_height;
+            let at = |position: Point3<f32>| Keypoint { layer, ..Keypoint::new(position, up) };
+
+            // Reached with a rapid from safe Z, so the move from the previous hole
+            // never feeds through the part
+            self.keypoints.push(Keypoint { rapid: true, ..at(above) });
+            for depth in self.peck_depths(hole.depth) {
+                self.keypoints.push(at(hole.top - up * depth));
+                self.keypoints.push(at(above));
+            }
+        }
+
+        println!("Generated {} keypoints for drill holes", self.keypoints.len());
+        Ok(())
+    }
+
+    fn get_keypoints(&self) -> Vec<Keypoint> {
+        self.keypoints.clone()
+    }
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+        self.keypoints = keypoints;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{box_mesh, trimesh};
+
+    #[test]
+    fn each_hole_is_entered_with_a_rapid() {
+        let mesh = box_mesh(Point3::new(-20.0, -20.0, -10.0), Point3::new(20.0, 20.0, 0.0));
+        let holes = vec![DrillHole::new(Point3::new(-10.0, 0.0, 0.0), 5.0), DrillHole::new(Point3::new(10.0, 0.0, 0.0), 5.0)];
+        let mut drill = DrillHoles::new(holes, 2.0, 0).with_retract_height(1.0);
+        drill.process(&mesh, &trimesh(&mesh), &Tolerances::default(), &mut |_| {}).unwrap();
+
+        let keypoints = drill.get_keypoints();
+        // Entry, then a peck and a retract for each of the 3 pecks
+        assert_eq!(keypoints.len(), 14);
+        let rapids: Vec<usize> = keypoints.iter().enumerate().filter(|(_, keypoint)| keypoint.rapid).map(|(i, _)| i).collect();
+        assert_eq!(rapids, vec![0, 7]);
+        assert_eq!(keypoints[7].position, Point3::new(10.0, 0.0, 1.0));
+        assert!((keypoints[5].position.z + 5.0).abs() < 1e-6);
+    }
+}
@@ -1,7 +1,14 @@
-use crate::prelude::*;
 pub mod contourtrace;
 pub mod multicontourtrace;
 pub mod circular_clearing;
+pub mod spiral_clearing;
+pub mod raster_surface;
+pub mod drill_holes;
+pub mod config;
 pub use crate::tasks::contourtrace::*;
 pub use crate::tasks::multicontourtrace::*;
-pub use crate::tasks::circular_clearing::*;
\ No newline at end of file
+pub use crate::tasks::circular_clearing::*;
+pub use crate::tasks::spiral_clearing::*;
+pub use crate::tasks::raster_surface::*;
+pub use crate::tasks::drill_holes::*;
+pub use crate::tasks::config::*;
\ No newline at end of file
@@ -1,15 +1,26 @@
 
-use kiss3d::nalgebra::{Point3};
+use nalgebra::{Point3, Vector3};
+use std::sync::mpsc;
+use std::thread;
+use rayon::prelude::*;
 use stl_io::IndexedMesh;
-use crate::cam_job::{CAMTask, Keypoint};
+use crate::cam_job::{CAMTask, Keypoint, Units};
 use crate::errors::CAMError;
-use super::ContourTrace;
+use crate::tolerances::Tolerances;
+use ncollide3d::shape::TriMesh;
+use super::contourtrace::report_discarded_hits;
+use super::{ContourTrace, HeightSampling, Lead, TaskConfig, TraceMode};
 
+#[derive(Clone)]
 pub struct MultiContourTrace {
     start_position: Point3<f32>,
     end_position: Point3<f32>,
     num_layers: usize,
     num_rays: usize,
+    mode: TraceMode,
+    height_sampling: HeightSampling,
+    lead: Lead,
+    tool_id: usize,
     keypoints: Vec<Keypoint>,
 }
 
@@ -19,39 +30,133 @@ impl MultiContourTrace {
         end_position: Point3<f32>,
         num_layers: usize,
         num_rays: usize,
+        tool_id: usize,
     ) -> MultiContourTrace {
         MultiContourTrace {
             start_position,
             end_position,
             num_layers,
             num_rays,
+            mode: TraceMode::Polar,
+            height_sampling: HeightSampling::default(),
+            lead: Lead::None,
+            tool_id,
             keypoints: Vec::new(),
         }
     }
+
+    pub fn with_mode(mut self, mode: TraceMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Height sampling used by every layer's `ContourTrace`.
+    pub fn with_height_sampling(mut self, height_sampling: HeightSampling) -> Self {
+        self.height_sampling = height_sampling;
+        self
+    }
+
+    /// Lead-in and lead-out added to every layer.
+    pub fn with_lead(mut self, lead: Lead) -> Self {
+        self.lead = lead;
+        self
+    }
 }
 
 impl CAMTask for MultiContourTrace {
     fn get_tool_id(&self) -> usize {
-        1 as usize
+        self.tool_id
+    }
+    fn set_tool_id(&mut self, tool_id: usize) {
+        self.tool_id = tool_id;
+    }
+    fn name(&self) -> &str {
+        "MultiContourTrace"
+    }
+    fn describe(&self, _units: Units) -> String {
+        format!("{}: {} layers, {} rays ({:?})", self.name(), self.num_layers, self.num_rays, self.mode)
+    }
+    fn estimated_keypoint_count(&self) -> usize {
+        // Layers are traced at both ends of the range
+        (self.num_layers + 1) * self.num_rays
     }
-    fn process(&mut self, mesh: &IndexedMesh) -> Result<(), CAMError> {
+    fn clone_box(&self) -> Box<dyn CAMTask> {
+        Box::new(self.clone())
+    }
+    fn config(&self) -> TaskConfig {
+        TaskConfig::MultiContourTrace {
+            start_position: self.start_position,
+            end_position: self.end_position,
+            num_layers: self.num_layers,
+            num_rays: self.num_rays,
+            mode: self.mode,
+            height_sampling: self.height_sampling,
+            lead: self.lead,
+            tool_id: self.tool_id,
+        }
+    }
+    fn process(&mut self, mesh: &IndexedMesh, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances, progress: &mut dyn FnMut(f32)) -> Result<(), CAMError> {
         println!("Processing multi-contour trace from {:?} to {:?} with {} layers",
                  self.start_position, self.end_position, self.num_layers);
 
         self.keypoints.clear();
+        if self.num_layers == 0 {
+            return Err(CAMError::ProcessingError(format!("{} needs at least one layer", self.name())));
+        }
 
+        let start_position = self.start_position;
         let direction = self.end_position - self.start_position;
-        let normal = direction.normalize();
+        let normal = direction.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z);
+        let num_layers = self.num_layers;
+        // Moved from layer to layer rather than rebuilt, which would recompute the
+        // mesh bounds and reallocate the keypoints every time
+        let contour_trace = ContourTrace::new(self.num_rays, start_position, normal, mesh, self.tool_id)
+            .with_mode(self.mode)
+            .with_height_sampling(self.height_sampling)
+            .with_lead(self.lead);
 
-        for i in 0..=self.num_layers {
-            let t = i as f32 / self.num_layers as f32;
-            let position = self.start_position + direction * t;
+        // Layers past either end of the mesh would cast every ray for nothing
+        let layer_position = move |i: usize| start_position + direction * (i as f32 / num_layers as f32);
+        let traced_layers: Vec<usize> = (0..=num_layers)
+            .filter(|&i| !contour_trace.misses_mesh_at(&layer_position(i), tolerances.plane_distance))
+            .collect();
+        let num_traced = traced_layers.len();
+        if num_traced < num_layers + 1 {
+            println!("Skipping {} of {} layers outside the mesh", num_layers + 1 - num_traced, num_layers + 1);
+        }
 
-            let mut contour_trace = ContourTrace::new(self.num_rays, position, normal, mesh);
+        // Layers only share the read-only mesh, so trace them in parallel. Collecting
+        // an indexed parallel iterator keeps the layers in order. Each worker gets its
+        // own copy of the trace. Workers report each finished layer over a channel, and
+        // this thread forwards them to `progress` while the layers are traced on another.
+        let (finished_sender, finished) = mpsc::channel();
+        let trace_layers = move || {
+            traced_layers
+                .into_par_iter()
+                .map_with((finished_sender, contour_trace), |(finished_sender, contour_trace), i| {
+                    contour_trace.set_position(layer_position(i));
+                    let discarded = contour_trace.trace(tri_mesh, tolerances);
+                    let mut keypoints = contour_trace.get_keypoints();
+                    for keypoint in &mut keypoints {
+                        keypoint.layer = i;
+                    }
+                    // The receiver only goes away once tracing is over
+                    finished_sender.send(()).ok();
+                    (keypoints, discarded)
+                })
+                .collect::<Vec<(Vec<Keypoint>, usize)>>()
+        };
+        let layers = thread::scope(|scope| {
+            let tracing = scope.spawn(trace_layers);
+            // Ends when the last sender is dropped
+            for (done, ()) in finished.iter().enumerate() {
+                progress((done + 1) as f32 / num_traced as f32);
+            }
+            tracing.join().expect("layer tracing panicked")
+        });
 
-            contour_trace.process(mesh)?;
-            self.keypoints.extend(contour_trace.get_keypoints());
-        }
+        report_discarded_hits("MultiContourTrace", layers.iter().map(|(_, discarded)| discarded).sum());
+        self.keypoints = layers.into_iter().flat_map(|(keypoints, _)| keypoints).collect();
 
         println!("Generated {} total keypoints across all layers", self.keypoints.len());
         Ok(())
@@ -60,4 +165,99 @@ impl CAMTask for MultiContourTrace {
     fn get_keypoints(&self) -> Vec<Keypoint> {
         self.keypoints.clone()
     }
-}
\ No newline at end of file
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+        self.keypoints = keypoints;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cube_mesh, cylinder_mesh, trimesh};
+    use nalgebra::Vector3;
+
+    fn trace_cube(start: Point3<f32>, end: Point3<f32>, num_layers: usize) -> Result<Vec<Keypoint>, CAMError> {
+        let mesh = cube_mesh(20.0);
+        let tri_mesh = trimesh(&mesh);
+        let mut task = MultiContourTrace::new(start, end, num_layers, 16, 0);
+        task.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {})?;
+        Ok(task.get_keypoints())
+    }
+
+    #[test]
+    fn parallel_layers_match_tracing_each_layer_in_turn() {
+        let (start, end) = (Point3::new(0.0, 0.0, -8.0), Point3::new(0.0, 0.0, 8.0));
+        let keypoints = trace_cube(start, end, 8).unwrap();
+
+        let mesh = cube_mesh(20.0);
+        let tri_mesh = trimesh(&mesh);
+        let mut expected = Vec::new();
+        for layer in 0..=8 {
+            let mut trace = ContourTrace::new(16, start + (end - start) * (layer as f32 / 8.0), Vector3::z(), &mesh, 0);
+            trace.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+            expected.extend(trace.get_keypoints().into_iter().map(|keypoint| (layer, keypoint.position)));
+        }
+
+        assert_eq!(keypoints.len(), 9 * 16);
+        let traced: Vec<(usize, Point3<f32>)> = keypoints.iter().map(|keypoint| (keypoint.layer, keypoint.position)).collect();
+        assert_eq!(traced, expected);
+    }
+
+    #[test]
+    fn parallel_output_equals_sequential_output() {
+        let (start, end) = (Point3::new(0.0, 0.0, -9.0), Point3::new(0.0, 0.0, 9.0));
+        // One worker traces the layers in turn
+        let trace_with = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let keypoints = pool.install(|| trace_cube(start, end, 12)).unwrap();
+            keypoints.iter().map(|keypoint| (keypoint.layer, keypoint.position, keypoint.normal)).collect::<Vec<_>>()
+        };
+        let sequential = trace_with(1);
+        assert!(!sequential.is_empty());
+        assert_eq!(trace_with(4), sequential);
+        assert!(sequential.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    }
+
+    #[test]
+    fn zero_layers_is_an_error() {
+        let result = trace_cube(Point3::new(0.0, 0.0, -5.0), Point3::new(0.0, 0.0, 5.0), 0);
+        assert!(matches!(result, Err(CAMError::ProcessingError(_))));
+    }
+
+    #[test]
+    fn coincident_start_and_end_fall_back_to_z_axis() {
+        let start = Point3::new(0.0, 0.0, 2.0);
+        let keypoints = trace_cube(start, start, 1).unwrap();
+        assert!(!keypoints.is_empty());
+        assert!(keypoints.iter().all(|k| (k.position.z - 2.0).abs() < 1e-4 && k.position.coords.iter().all(|c| c.is_finite())));
+    }
+
+    #[test]
+    fn reused_trace_matches_a_fresh_trace_per_layer() {
+        // Settings that leave state behind in a trace: leads, grid rays, several heights
+        let sampling = HeightSampling { offset: 0.0, samples: 3, spread: 0.2 };
+        let lead = Lead::Tangential { radius: 2.0 };
+        let mesh = cylinder_mesh(8.0, 0.0, 10.0, 24);
+        let tri_mesh = trimesh(&mesh);
+        let (start, end) = (Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, 9.0));
+        let mut task = MultiContourTrace::new(start, end, 4, 12, 0)
+            .with_mode(TraceMode::Grid)
+            .with_height_sampling(sampling)
+            .with_lead(lead);
+        task.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+
+        let mut expected = Vec::new();
+        for layer in 0..=4 {
+            let mut trace = ContourTrace::new(12, start + (end - start) * (layer as f32 / 4.0), Vector3::z(), &mesh, 0)
+                .with_mode(TraceMode::Grid)
+                .with_height_sampling(sampling)
+                .with_lead(lead);
+            trace.process(&mesh, &tri_mesh, &Tolerances::default(), &mut |_| {}).unwrap();
+            expected.extend(trace.get_keypoints().into_iter().map(|keypoint| (layer, keypoint.position, keypoint.normal)));
+        }
+
+        let traced: Vec<_> = task.get_keypoints().into_iter().map(|keypoint| (keypoint.layer, keypoint.position, keypoint.normal)).collect();
+        assert!(!traced.is_empty());
+        assert_eq!(traced, expected);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::cam_job::{CAMTask, Keypoint, Units};
+use crate::errors::CAMError;
+use crate::stl_operations::{get_bounds, ray_reach};
+use crate::tasks::TaskConfig;
+use crate::tolerances::Tolerances;
+use nalgebra::{Isometry3, Point3, Vector3};
+use ncollide3d::math::Point as NCPoint;
+use ncollide3d::query::{Ray, RayCast};
+use ncollide3d::shape::TriMesh;
+use stl_io::IndexedMesh;
+
+/// Surfacing with parallel passes along X across the mesh's XY bounds. Rays are cast
+/// straight down from `z_start` and each hit becomes a keypoint, so the passes follow
+/// the top of the part. Passes alternate direction to keep travel between them short.
+#[derive(Clone)]
+pub struct RasterSurface {
+    /// Distance between neighbouring passes, along Y.
+    stepover: f32,
+    /// Distance between rays along a pass.
+    point_spacing: f32,
+    z_start: f32,
+    /// How far below `z_start` a ray may hit the surface. Rays never reach further
+    /// than `ray_reach` allows, however long this is.
+    ray_length: f32,
+    min: Point3<f32>,
+    max: Point3<f32>,
+    tool_id: usize,
+    keypoints: Vec<Keypoint>,
+}
+
+impl RasterSurface {
+    pub fn new(stepover: f32, z_start: f32, ray_length: f32, mesh: &IndexedMesh, tool_id: usize) -> Self {
+        let (min, max) = get_bounds(mesh).unwrap();
+
+        RasterSurface {
+            stepover,
+            point_spacing: stepover,
+            z_start,
+            ray_length,
+            min,
+            max,
+            tool_id,
+            keypoints: Vec::new(),
+        }
+    }
+
+    /// Sets the distance between rays along a pass, which defaults to the stepover.
+    pub fn with_point_spacing(mut self, point_spacing: f32) -> Self {
+        self.point_spacing = point_spacing;
+        self
+    }
+
+    /// Evenly spaced positions from `min` to `max`, always including both ends.
+    fn samples(min: f32, max: f32, spacing: f32) -> Vec<f32> {
+        let count = ((max - min) / spacing.max(f32::EPSILON)).ceil().max(1.0) as usize;
+        (0..=count).map(|i| min + (max - min) * i as f32 / count as f32).collect()
+    }
+}
+
+impl CAMTask for RasterSurface {
+    fn get_tool_id(&self) -> usize {
+        self.tool_id
+    }
+    fn set_tool_id(&mut self, tool_id: usize) {
+        self.tool_id = tool_id;
+    }
+    fn name(&self) -> &str {
+        "RasterSurface"
+    }
+    fn describe(&self, units: Units) -> String {
+        format!(
+            "{}: stepover {:.2}{}, from z={:.2}{}",
+            self.name(),
+            units.from_mm(self.stepover),
+            units.suffix(),
+            units.from_mm(self.z_start),
+            units.suffix()
+        )
+    }
+    fn estimated_keypoint_count(&self) -> usize {
+        let xs = Self::samples(self.min.x, self.max.x, self.point_spacing).len();
+        let ys = Self::samples(self.min.y, self.max.y, self.stepover).len();
+        xs * ys
+    }
+    fn clone_box(&self) -> Box<dyn CAMTask> {
+        Box::new(self.clone())
+    }
+    fn config(&self) -> TaskConfig {
+        TaskConfig::RasterSurface {
+            stepover: self.stepover,
+            point_spacing: self.point_spacing,
+            z_start: self.z_start,
+            ray_length: self.ray_length,
+            tool_id: self.tool_id,
+        }
+    }
+    fn process(&mut self, _mesh: &IndexedMesh, tri_mesh: &TriMesh<f32>, _tolerances: &Tolerances, progress: &mut dyn FnMut(f32)) -> Result<(), CAMError> {
+        println!("Processing raster surface from z={} with stepover {}", self.z_start, self.stepover);
+        self.keypoints.clear();
+
+        let xs = Self::samples(self.min.x, self.max.x, self.point_spacing);
+        let ys = Self::samples(self.min.y, self.max.y, self.stepover);
+        let down = Vector3::new(0.0, 0.0, -1.0);
+        let center = nalgebra::center(&self.min, &self.max);
+        let bounding_radius = (self.max - self.min).norm() * 0.5;
+
+        for (pass, &y) in ys.iter().enumerate() {
+            // Boustrophedon: every other pass runs back the way the previous one came
+            let pass_xs: Box<dyn Iterator<Item = &f32>> = if pass % 2 == 0 {
+                Box::new(xs.iter())
+            } else {
+                Box::new(xs.iter().rev())
+            };
+
+            for &x in pass_xs {
+                let origin = Point3::new(x, y, self.z_start);
+                let ray = Ray::new(NCPoint::from(origin.coords), down);
+                let max_toi = self.ray_length.min(ray_reach(&origin, &center, bounding_radius));
+                if let Some(hit) = tri_mesh.toi_and_normal_with_ray(&Isometry3::identity(), &ray, max_toi, true) {
+                    let keypoint = Keypoint::new(origin + down * hit.toi, hit.normal);
+                    self.keypoints.push(Keypoint { layer: pass, ..keypoint });
+                }
+            }
+            progress((pass + 1) as f32 / ys.len() as f32);
+        }
+
+        println!("Generated {} keypoints for raster surface", self.keypoints.len());
+        Ok(())
+    }
+
+    fn get_keypoints(&self) -> Vec<Keypoint> {
+        self.keypoints.clone()
+    }
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+        self.keypoints = keypoints;
+    }
+}
@@ -0,0 +1,202 @@
+use crate::cam_job::{CAMTask, Keypoint, Units};
+use crate::errors::CAMError;
+use crate::tasks::TaskConfig;
+use crate::tolerances::Tolerances;
+use super::circular_clearing::{is_ring_clear, ring_basis};
+use nalgebra::{Point3, Vector3};
+use ncollide3d::shape::TriMesh;
+use stl_io::IndexedMesh;
+
+/// Clearing as one continuous helix instead of separate rings. The path descends from
+/// `start_position` to `end_position` at one turn per layer while its radius shrinks
+/// by `radial_step` per turn, but never below the smallest ring that is still clear
+/// of the mesh at that height, so the whole task is a single connected keypoint stream.
+#[derive(Clone)]
+pub struct SpiralClearing {
+    start_position: Point3<f32>,
+    end_position: Point3<f32>,
+    num_layers: usize,
+    initial_radius: f32,
+    num_points_per_turn: usize,
+    radial_step: f32,
+    /// Minimum distance kept between the path and the mesh, measured inward.
+    clearance: f32,
+    tool_id: usize,
+    keypoints: Vec<Keypoint>,
+}
+
+impl SpiralClearing {
+    pub fn new(
+        start_position: Point3<f32>,
+        end_position: Point3<f32>,
+        num_layers: usize,
+        initial_radius: f32,
+        num_points_per_turn: usize,
+        radial_step: f32,
+        tool_id: usize,
+    ) -> Self {
+        SpiralClearing {
+            start_position,
+            end_position,
+            num_layers,
+            initial_radius,
+            num_points_per_turn,
+            radial_step,
+            clearance: 0.0,
+            tool_id,
+            keypoints: Vec::new(),
+        }
+    }
+
+    pub fn with_clearance(mut self, clearance: f32) -> Self {
+        self.clearance = clearance;
+        self
+    }
+
+    /// Smallest radius, down from `initial_radius`, whose ring around `center` is
+    /// clear of the mesh. Fails if even the `initial_radius` ring isn't clear, since
+    /// the helix can't pass that height without cutting into the part.
+    fn min_clear_radius(&self, center: &Point3<f32>, normal: &Vector3<f32>, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances) -> Result<f32, CAMError> {
+        let is_clear = |radius: f32| {
+            is_ring_clear(center, radius, normal, self.num_points_per_turn, self.clearance, tolerances.min_ring_radius, tri_mesh)
+        };
+
+        let mut radius = self.initial_radius;
+        if !is_clear(radius) {
+            return Err(CAMError::ProcessingError(format!(
+                "{}: the initial ring of radius {} at {:?} is not clear of the mesh",
+                self.name(), radius, center.coords.as_slice()
+            )));
+        }
+        let step = self.radial_step.max(tolerances.search_precision);
+        while is_clear(radius - step) {
+            radius -= step;
+        }
+
+        // `radius` is clear and `radius - step` isn't; narrow down the boundary
+        let mut low = radius - step;
+        let mut high = radius;
+        while high - low > tolerances.search_precision {
+            let mid = (low + high) / 2.0;
+            if is_clear(mid) {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        Ok(high)
+    }
+}
+
+impl CAMTask for SpiralClearing {
+    fn get_tool_id(&self) -> usize {
+        self.tool_id
+    }
+    fn set_tool_id(&mut self, tool_id: usize) {
+        self.tool_id = tool_id;
+    }
+    fn name(&self) -> &str {
+        "SpiralClearing"
+    }
+    fn describe(&self, units: Units) -> String {
+        format!("{}: {} turns, r={:.1}{}", self.name(), self.num_layers, units.from_mm(self.initial_radius), units.suffix())
+    }
+    fn estimated_keypoint_count(&self) -> usize {
+        self.num_layers.max(1) * self.num_points_per_turn.max(3) + 1
+    }
+    fn clone_box(&self) -> Box<dyn CAMTask> {
+        Box::new(self.clone())
+    }
+    fn config(&self) -> TaskConfig {
+        TaskConfig::SpiralClearing {
+            start_position: self.start_position,
+            end_position: self.end_position,
+            num_layers: self.num_layers,
+            initial_radius: self.initial_radius,
+            num_points_per_turn: self.num_points_per_turn,
+            radial_step: self.radial_step,
+            clearance: self.clearance,
+            tool_id: self.tool_id,
+        }
+    }
+    fn process(&mut self, _mesh: &IndexedMesh, tri_mesh: &TriMesh<f32>, tolerances: &Tolerances, progress: &mut dyn FnMut(f32)) -> Result<(), CAMError> {
+        println!("Processing spiral clearing from {:?} to {:?}", self.start_position, self.end_position);
+        self.keypoints.clear();
+        if self.num_layers == 0 {
+            return Err(CAMError::ProcessingError(format!("{} needs at least one layer", self.name())));
+        }
+
+        let axis = self.end_position - self.start_position;
+        let normal = axis.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z);
+        let num_layers = self.num_layers;
+        let points_per_turn = self.num_points_per_turn.max(3);
+        let (v1, v2) = ring_basis(&normal);
+
+        // Smallest clear radius at each layer boundary. Between two boundaries the
+        // larger of the two limits applies.
+        let min_radii: Vec<f32> = (0..=num_layers)
+            .map(|layer| {
+                let center = self.start_position + axis * (layer as f32 / num_layers as f32);
+                let radius = self.min_clear_radius(&center, &normal, tri_mesh, tolerances);
+                progress((layer + 1) as f32 / (num_layers + 1) as f32);
+                radius
+            })
+            .collect::<Result<_, CAMError>>()?;
+
+        let total_points = num_layers * points_per_turn;
+        for n in 0..=total_points {
+            let layer = (n / points_per_turn).min(num_layers - 1);
+            let turns = n as f32 / points_per_turn as f32;
+            let center = self.start_position + axis * (turns / num_layers as f32);
+            let limit = min_radii[layer].max(min_radii[layer + 1]);
+            let radius = (self.initial_radius - self.radial_step * turns).max(limit);
+
+            let angle = turns * 2.0 * std::f32::consts::PI;
+            let direction = (v1 * angle.cos() + v2 * angle.sin()).normalize();
+            self.keypoints.push(Keypoint { layer, ..Keypoint::new(center + direction * radius, direction) });
+        }
+
+        println!("Generated {} keypoints for spiral clearing", self.keypoints.len());
+        Ok(())
+    }
+
+    fn get_keypoints(&self) -> Vec<Keypoint> {
+        self.keypoints.clone()
+    }
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+        self.keypoints = keypoints;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cube_mesh, trimesh};
+
+    fn spiral_around_cube(initial_radius: f32, num_layers: usize) -> Result<Vec<Keypoint>, CAMError> {
+        let mesh = cube_mesh(10.0);
+        let mut task = SpiralClearing::new(Point3::new(0.0, 0.0, 4.0), Point3::new(0.0, 0.0, -4.0), num_layers, initial_radius, 32, 1.0, 0);
+        task.process(&mesh, &trimesh(&mesh), &Tolerances::default(), &mut |_| {})?;
+        Ok(task.get_keypoints())
+    }
+
+    #[test]
+    fn spiral_closes_in_on_the_part_without_cutting_it() {
+        let keypoints = spiral_around_cube(15.0, 3).unwrap();
+        assert_eq!(keypoints.len(), 3 * 32 + 1);
+        // The cube's corners are 5 * sqrt(2) from the axis
+        assert!(keypoints.iter().all(|keypoint| keypoint.position.xy().coords.norm() > 7.07));
+        assert!((keypoints[0].position.xy().coords.norm() - 15.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn blocked_initial_ring_is_an_error() {
+        // Starting inside the cube would gouge it on the very first turn
+        assert!(matches!(spiral_around_cube(4.0, 3), Err(CAMError::ProcessingError(_))));
+    }
+
+    #[test]
+    fn zero_layers_is_an_error() {
+        assert!(matches!(spiral_around_cube(15.0, 0), Err(CAMError::ProcessingError(_))));
+    }
+}
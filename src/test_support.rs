@@ -0,0 +1,144 @@
+//! Small closed meshes for the unit tests, wound counter-clockwise seen from outside
+//! so their normals face out like a well-formed STL, and scratch file paths.
+
+use crate::stl_operations::indexed_mesh_to_trimesh;
+use nalgebra::{Point3, Vector3};
+use ncollide3d::shape::TriMesh;
+use std::f32::consts::PI;
+use std::path::PathBuf;
+use stl_io::{IndexedMesh, IndexedTriangle, Vector};
+
+/// Builds a mesh face by face, flipping any triangle whose winding faces away from
+/// the direction given for it.
+#[derive(Default)]
+pub(crate) struct MeshBuilder {
+    vertices: Vec<Point3<f32>>,
+    faces: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    pub(crate) fn vertex(&mut self, point: Point3<f32>) -> usize {
+        self.vertices.push(point);
+        self.vertices.len() - 1
+    }
+
+    pub(crate) fn face(&mut self, a: usize, b: usize, c: usize, outward: Vector3<f32>) {
+        let (pa, pb, pc) = (self.vertices[a], self.vertices[b], self.vertices[c]);
+        if (pb - pa).cross(&(pc - pa)).dot(&outward) < 0.0 {
+            self.faces.push([a, c, b]);
+        } else {
+            self.faces.push([a, b, c]);
+        }
+    }
+
+    pub(crate) fn quad(&mut self, a: usize, b: usize, c: usize, d: usize, outward: Vector3<f32>) {
+        self.face(a, b, c, outward);
+        self.face(a, c, d, outward);
+    }
+
+    pub(crate) fn build(self) -> IndexedMesh {
+        let vertices = &self.vertices;
+        IndexedMesh {
+            vertices: vertices.iter().map(|v| Vector::new([v.x, v.y, v.z])).collect(),
+            faces: self.faces.iter()
+                .map(|&[a, b, c]| {
+                    let normal = (vertices[b] - vertices[a]).cross(&(vertices[c] - vertices[a])).normalize();
+                    IndexedTriangle { normal: Vector::new([normal.x, normal.y, normal.z]), vertices: [a, b, c] }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Axis-aligned box between `min` and `max`.
+pub(crate) fn box_mesh(min: Point3<f32>, max: Point3<f32>) -> IndexedMesh {
+    let mut builder = MeshBuilder::default();
+    // Bit 0 of the index picks max x, bit 1 max y and bit 2 max z
+    let corners: Vec<usize> = (0..8)
+        .map(|i| {
+            let pick = |bit: usize, low: f32, high: f32| if i & bit == 0 { low } else { high };
+            builder.vertex(Point3::new(pick(1, min.x, max.x), pick(2, min.y, max.y), pick(4, min.z, max.z)))
+        })
+        .collect();
+    let sides = [
+        ([0, 2, 3, 1], -Vector3::z()),
+        ([4, 5, 7, 6], Vector3::z()),
+        ([0, 1, 5, 4], -Vector3::y()),
+        ([2, 6, 7, 3], Vector3::y()),
+        ([0, 4, 6, 2], -Vector3::x()),
+        ([1, 3, 7, 5], Vector3::x()),
+    ];
+    for ([a, b, c, d], outward) in sides.iter().copied() {
+        builder.quad(corners[a], corners[b], corners[c], corners[d], outward);
+    }
+    builder.build()
+}
+
+/// Cube of side `size` centered on the origin.
+pub(crate) fn cube_mesh(size: f32) -> IndexedMesh {
+    let half = size / 2.0;
+    box_mesh(Point3::new(-half, -half, -half), Point3::new(half, half, half))
+}
+
+/// Solid cylinder of `radius` around the Z axis from `z0` to `z1`, as a prism with
+/// `segments` sides.
+pub(crate) fn cylinder_mesh(radius: f32, z0: f32, z1: f32, segments: usize) -> IndexedMesh {
+    let mut builder = MeshBuilder::default();
+    let bottom_center = builder.vertex(Point3::new(0.0, 0.0, z0));
+    let top_center = builder.vertex(Point3::new(0.0, 0.0, z1));
+    let rim = ring(&mut builder, radius, z0, z1, segments);
+    for i in 0..segments {
+        let ((b0, t0), (b1, t1)) = (rim[i], rim[(i + 1) % segments]);
+        let outward = radial(i, segments);
+        builder.quad(b0, b1, t1, t0, outward);
+        builder.face(bottom_center, b1, b0, -Vector3::z());
+        builder.face(top_center, t0, t1, Vector3::z());
+    }
+    builder.build()
+}
+
+/// Tube around the Z axis from `z0` to `z1` whose wall runs from `inner` to `outer`
+/// radius, i.e. a part with a through hole of radius `inner`.
+pub(crate) fn tube_mesh(inner: f32, outer: f32, z0: f32, z1: f32, segments: usize) -> IndexedMesh {
+    let mut builder = MeshBuilder::default();
+    let outside = ring(&mut builder, outer, z0, z1, segments);
+    let inside = ring(&mut builder, inner, z0, z1, segments);
+    for i in 0..segments {
+        let next = (i + 1) % segments;
+        let ((ob0, ot0), (ob1, ot1)) = (outside[i], outside[next]);
+        let ((ib0, it0), (ib1, it1)) = (inside[i], inside[next]);
+        let outward = radial(i, segments);
+        builder.quad(ob0, ob1, ot1, ot0, outward);
+        builder.quad(ib0, it0, it1, ib1, -outward);
+        builder.quad(ot0, ot1, it1, it0, Vector3::z());
+        builder.quad(ob0, ib0, ib1, ob1, -Vector3::z());
+    }
+    builder.build()
+}
+
+pub(crate) fn trimesh(mesh: &IndexedMesh) -> TriMesh<f32> {
+    indexed_mesh_to_trimesh(mesh)
+}
+
+/// Path in the system temp directory for a test's scratch file, unique to `name` and
+/// this test run. Tests remove what they write.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("carver-{}-{}", std::process::id(), name))
+}
+
+/// Bottom and top vertices at each of `segments` angles around the Z axis.
+fn ring(builder: &mut MeshBuilder, radius: f32, z0: f32, z1: f32, segments: usize) -> Vec<(usize, usize)> {
+    (0..segments)
+        .map(|i| {
+            let angle = i as f32 * 2.0 * PI / segments as f32;
+            let (x, y) = (radius * angle.cos(), radius * angle.sin());
+            (builder.vertex(Point3::new(x, y, z0)), builder.vertex(Point3::new(x, y, z1)))
+        })
+        .collect()
+}
+
+/// Outward direction through the middle of side `i` of a prism with `segments` sides.
+fn radial(i: usize, segments: usize) -> Vector3<f32> {
+    let angle = (i as f32 + 0.5) * 2.0 * PI / segments as f32;
+    Vector3::new(angle.cos(), angle.sin(), 0.0)
+}
@@ -0,0 +1,37 @@
+//! Precision settings shared by the job and its tasks, collected so they can be tuned
+//! in one place instead of being hard-coded where they're used.
+
+use serde::{Deserialize, Serialize};
+
+/// Tolerances used while generating toolpaths. Lengths are in millimetres. A job has
+/// one set (`CAMJOB::tolerances`) and tasks can override it individually with
+/// `CAMJOB::set_task_tolerances`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tolerances {
+    /// Interval at which the clearing tasks stop bisecting for the smallest clear ring.
+    pub search_precision: f32,
+    /// Smallest ring radius, after clearance, the clearing tasks will cut.
+    pub min_ring_radius: f32,
+    /// How far a contour hit may lie from the plane its ray was cast in before it's
+    /// treated as belonging to another layer and dropped.
+    pub plane_distance: f32,
+    /// Contact deeper than this fraction of the tool diameter is reported as a gouge,
+    /// so a tool resting exactly on the surface isn't.
+    pub gouge_fraction: f32,
+    /// Keypoints closer than this to the previous keypoint kept on the same layer are
+    /// dropped after a task is processed. Zero keeps every keypoint.
+    pub min_keypoint_spacing: f32,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Tolerances {
+            search_precision: 0.001,
+            min_ring_radius: 0.001,
+            plane_distance: 0.1,
+            gouge_fraction: 1e-3,
+            min_keypoint_spacing: 0.0,
+        }
+    }
+}
@@ -1,67 +1,413 @@
+#[cfg(feature = "viewer")]
 use std::cell::RefCell;
+#[cfg(feature = "viewer")]
 use kiss3d::scene::SceneNode;
+#[cfg(feature = "viewer")]
 use kiss3d::window::Window;
-use kiss3d::nalgebra::{Point3, Vector3};
+#[cfg(feature = "viewer")]
+use nalgebra::Translation3;
+use std::collections::HashMap;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+use crate::errors::CAMError;
+use crate::simulation::ToolCut;
 
+/// Cutting feed rate, in units per minute, for tools that don't set one.
+pub const DEFAULT_FEED_RATE: f32 = 500.0;
+
+/// Shape of a tool's cutting end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ToolGeometry {
+    #[default]
+    FlatEnd,
+    /// A hemispherical tip of the tool's radius.
+    BallNose,
+    /// A conical tip coming to a point, with `angle` the included angle in degrees.
+    Vbit { angle: f32 },
+}
+
+impl ToolGeometry {
+    /// Length of the tip below the full-diameter shank, for a tool of `radius`.
+    pub fn tip_length(&self, radius: f32) -> f32 {
+        match *self {
+            ToolGeometry::FlatEnd => 0.0,
+            ToolGeometry::BallNose => radius,
+            ToolGeometry::Vbit { angle } => radius / (angle.to_radians() / 2.0).tan(),
+        }
+    }
+
+    /// Radius of the cutting end `height` above the tip, for a tool of `radius`.
+    pub fn radius_at(&self, radius: f32, height: f32) -> f32 {
+        match *self {
+            ToolGeometry::FlatEnd => radius,
+            ToolGeometry::BallNose => {
+                let below_center = (radius - height).max(0.0);
+                (radius * radius - below_center * below_center).max(0.0).sqrt()
+            }
+            ToolGeometry::Vbit { angle } => (height * (angle.to_radians() / 2.0).tan()).min(radius),
+        }
+    }
+}
+
+/// Cutter geometry and parameters. The render model is optional so jobs can be
+/// built and exported without a window.
 pub struct Tool {
     pub id: usize,
     pub name: String,
-    pub model: RefCell<SceneNode>,
     pub length: f32,
     pub diameter: f32,
+    /// Cutting feed rate in units per minute.
+    pub feed_rate: f32,
+    pub geometry: ToolGeometry,
+    #[cfg(feature = "viewer")]
+    visual: Option<ToolVisual>,
 }
 
-impl Tool {
-    pub fn new(id: usize, name: String, window: &mut Window, length: f32, diameter: f32) -> Self {
-        let mut model = window.add_cylinder(diameter / 2.0, length);
+/// The scene node drawing a `Tool` in the viewer.
+#[cfg(feature = "viewer")]
+pub struct ToolVisual {
+    model: RefCell<SceneNode>,
+}
+
+#[cfg(feature = "viewer")]
+impl ToolVisual {
+    /// Builds the tool along the model's Y axis, centered on the origin with the tip
+    /// at `-length / 2`: a cylinder for the shank, capped with a sphere for a ball nose
+    /// or a cone for a V-bit.
+    pub fn new(window: &mut Window, length: f32, diameter: f32, geometry: ToolGeometry) -> Self {
+        let radius = diameter / 2.0;
+        let tip_length = geometry.tip_length(radius).min(length);
+        let mut model = window.add_group();
+        model
+            .add_cylinder(radius, length - tip_length)
+            .set_local_translation(Translation3::new(0.0, tip_length / 2.0, 0.0));
+        match geometry {
+            ToolGeometry::FlatEnd => {}
+            ToolGeometry::BallNose => {
+                model
+                    .add_sphere(radius)
+                    .set_local_translation(Translation3::new(0.0, radius - length / 2.0, 0.0));
+            }
+            ToolGeometry::Vbit { .. } => {
+                // kiss3d cones point up +Y, so turn it over to point at the tip
+                let mut cone = model.add_cone(radius, tip_length);
+                cone.set_local_translation(Translation3::new(0.0, (tip_length - length) / 2.0, 0.0));
+                cone.set_local_rotation(UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f32::consts::PI));
+            }
+        }
         model.set_color(0.8, 0.8, 0.8); // Light gray color
         model.set_visible(false);
+        ToolVisual { model: RefCell::new(model) }
+    }
+}
 
+/// The persistent part of a `Tool`. The render model is rebuilt from this on load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolConfig {
+    pub id: usize,
+    pub name: String,
+    pub length: f32,
+    pub diameter: f32,
+    #[serde(default = "default_feed_rate")]
+    pub feed_rate: f32,
+    #[serde(default)]
+    pub geometry: ToolGeometry,
+}
+
+fn default_feed_rate() -> f32 {
+    DEFAULT_FEED_RATE
+}
+
+impl Tool {
+    pub fn new(id: usize, name: String, length: f32, diameter: f32) -> Self {
         Tool {
             id,
             name,
-            model: RefCell::new(model),
             length,
             diameter,
+            feed_rate: DEFAULT_FEED_RATE,
+            geometry: ToolGeometry::FlatEnd,
+            #[cfg(feature = "viewer")]
+            visual: None,
+        }
+    }
+
+    pub fn with_feed_rate(mut self, feed_rate: f32) -> Self {
+        self.feed_rate = feed_rate;
+        self
+    }
+
+    pub fn with_geometry(mut self, geometry: ToolGeometry) -> Self {
+        self.geometry = geometry;
+        self
+    }
+
+    /// Length of the tip below the full-diameter shank, at most the tool's length.
+    pub fn tip_length(&self) -> f32 {
+        self.geometry.tip_length(self.diameter / 2.0).min(self.length)
+    }
+
+    /// The cutter location (CL point) for the tool touching the surface at `contact`
+    /// and standing along `normal`: the center of the ball for a ball nose, otherwise
+    /// the tip itself, which rests on the contact point.
+    pub fn cutter_location(&self, contact: &Point3<f32>, normal: &Vector3<f32>) -> Point3<f32> {
+        match self.geometry {
+            ToolGeometry::BallNose => {
+                let axis = normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros);
+                contact + axis * (self.diameter / 2.0)
+            }
+            ToolGeometry::FlatEnd | ToolGeometry::Vbit { .. } => *contact,
+        }
+    }
+
+    /// The solid the material simulation clears with the tip at `position` and the
+    /// tool standing along `normal`. It follows the tool's geometry, so a flat end
+    /// clears its full width at the tip where a ball nose or V-bit narrows to it.
+    pub fn cut(&self, position: &Point3<f32>, normal: &Vector3<f32>) -> ToolCut {
+        ToolCut {
+            tip: *position,
+            axis: normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros),
+            radius: self.diameter / 2.0,
+            length: self.length,
+            geometry: self.geometry,
+        }
+    }
+
+    /// Adds a render model for this tool to `window`, replacing any previous one.
+    #[cfg(feature = "viewer")]
+    pub fn with_visual(mut self, window: &mut Window) -> Self {
+        self.attach_visual(window);
+        self
+    }
+
+    #[cfg(feature = "viewer")]
+    pub fn attach_visual(&mut self, window: &mut Window) {
+        self.visual = Some(ToolVisual::new(window, self.length, self.diameter, self.geometry));
+    }
+
+    pub fn from_config(config: ToolConfig) -> Self {
+        Tool::new(config.id, config.name, config.length, config.diameter)
+            .with_feed_rate(config.feed_rate)
+            .with_geometry(config.geometry)
+    }
+
+    /// One-line spec for logs and setup sheets, e.g. `T0 End Mill 6mm ⌀6 L50`.
+    pub fn describe(&self) -> String {
+        format!("T{} {} ⌀{} L{}", self.id, self.name, self.diameter, self.length)
+    }
+
+    pub fn config(&self) -> ToolConfig {
+        ToolConfig {
+            id: self.id,
+            name: self.name.clone(),
+            length: self.length,
+            diameter: self.diameter,
+            feed_rate: self.feed_rate,
+            geometry: self.geometry,
         }
     }
 
+    #[cfg(feature = "viewer")]
     pub fn set_position(&self, position: Point3<f32>) {
-        self.model.borrow_mut().set_local_translation(kiss3d::nalgebra::Translation3::from(position.coords));
+        let Some(visual) = &self.visual else { return };
+        visual.model.borrow_mut().set_local_translation(nalgebra::Translation3::from(position.coords));
     }
 
+    #[cfg(feature = "viewer")]
     pub fn set_orientation(&self, direction: Vector3<f32>) {
-        let rotation = kiss3d::nalgebra::UnitQuaternion::rotation_between(
-            &Vector3::new(0.0, 0.0, 1.0),
-            &direction.normalize(),
-        )
-        .unwrap_or_else(kiss3d::nalgebra::UnitQuaternion::identity);
-        self.model.borrow_mut().set_local_rotation(rotation);
+        let Some(visual) = &self.visual else { return };
+        visual.model.borrow_mut().set_local_rotation(tool_rotation(&direction));
     }
 
+    #[cfg(feature = "viewer")]
     pub fn set_visible(&self, visible: bool) {
-        self.model.borrow_mut().set_visible(visible);
+        if let Some(visual) = &self.visual {
+            visual.model.borrow_mut().set_visible(visible);
+        }
     }
 }
 
+/// Rotation taking the tool's local +Y axis, along which `ToolVisual` builds it from
+/// the tip up, onto `direction`. `rotation_between` has no answer when the two point
+/// in opposite directions, so that case is a half turn about X. A zero `direction`
+/// leaves the tool unrotated.
+pub fn tool_rotation(direction: &Vector3<f32>) -> UnitQuaternion<f32> {
+    let direction = match direction.try_normalize(f32::EPSILON) {
+        Some(direction) => direction,
+        None => return UnitQuaternion::identity(),
+    };
+    UnitQuaternion::rotation_between(&Vector3::y(), &direction).unwrap_or_else(|| {
+        UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f32::consts::PI)
+    })
+}
+
+/// The tools of a job, keyed by id.
 pub struct ToolLibrary {
-    tools: Vec<Tool>,
+    tools: HashMap<usize, Tool>,
+}
+
+impl Default for ToolLibrary {
+    fn default() -> Self {
+        ToolLibrary::new()
+    }
 }
 
 impl ToolLibrary {
     pub fn new() -> Self {
-        ToolLibrary { tools: Vec::new() }
+        ToolLibrary { tools: HashMap::new() }
+    }
+
+    /// Adds `tool`, refusing it if the library already has a tool with its id.
+    pub fn add_tool(&mut self, tool: Tool) -> Result<(), CAMError> {
+        if self.tools.contains_key(&tool.id) {
+            return Err(CAMError::ProcessingError(format!("A tool with id {} is already in the library", tool.id)));
+        }
+        self.tools.insert(tool.id, tool);
+        Ok(())
     }
 
-    pub fn add_tool(&mut self, tool: Tool) {
-        self.tools.push(tool);
+    /// Every tool, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = &Tool> {
+        let mut tools: Vec<&Tool> = self.tools.values().collect();
+        tools.sort_by_key(|tool| tool.id);
+        tools.into_iter()
     }
 
     pub fn get_tool(&self, id: usize) -> Option<&Tool> {
-        self.tools.iter().find(|&tool| tool.id == id)
+        self.tools.get(&id)
     }
 
     pub fn get_tool_mut(&mut self, id: usize) -> Option<&mut Tool> {
-        self.tools.iter_mut().find(|tool| tool.id == id)
+        self.tools.get_mut(&id)
+    }
+
+    /// The largest tool whose radius is at most `min_radius`, i.e. the biggest cutter
+    /// that still fits an internal corner of that radius.
+    pub fn select_for_min_radius(&self, min_radius: f32) -> Option<&Tool> {
+        self.iter()
+            .filter(|tool| tool.diameter / 2.0 <= min_radius)
+            .max_by(|a, b| a.diameter.partial_cmp(&b.diameter).unwrap_or(std::cmp::Ordering::Equal))
+    }
+
+    /// Gives every tool a render model in `window`.
+    #[cfg(feature = "viewer")]
+    pub fn attach_visuals(&mut self, window: &mut Window) {
+        for tool in self.tools.values_mut() {
+            tool.attach_visual(window);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(geometry: ToolGeometry) -> Tool {
+        Tool::new(0, "Test".to_string(), 20.0, 6.0).with_geometry(geometry)
+    }
+
+    #[test]
+    fn geometry_defaults_to_flat_end() {
+        assert_eq!(Tool::new(0, "End Mill".to_string(), 20.0, 6.0).geometry, ToolGeometry::FlatEnd);
+        let config: ToolConfig = serde_json::from_str(r#"{"id": 0, "name": "End Mill", "length": 20.0, "diameter": 6.0}"#).unwrap();
+        assert_eq!(config.geometry, ToolGeometry::FlatEnd);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn tip_length_follows_geometry() {
+        assert_eq!(tool(ToolGeometry::FlatEnd).tip_length(), 0.0);
+        assert_eq!(tool(ToolGeometry::BallNose).tip_length(), 3.0);
+        assert!((tool(ToolGeometry::Vbit { angle: 90.0 }).tip_length() - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ball_nose_cutter_location_is_ball_center() {
+        let contact = Point3::new(1.0, 2.0, 3.0);
+        let normal = Vector3::new(0.0, 0.0, 2.0);
+        assert_eq!(tool(ToolGeometry::BallNose).cutter_location(&contact, &normal), Point3::new(1.0, 2.0, 6.0));
+        assert_eq!(tool(ToolGeometry::FlatEnd).cutter_location(&contact, &normal), contact);
+    }
+
+    #[test]
+    fn cuts_stand_on_the_contact_point() {
+        let contact = Point3::new(1.0, 2.0, 3.0);
+        let normal = Vector3::new(1.0, 0.0, 1.0);
+        let axis = normal.normalize();
+        for geometry in [ToolGeometry::FlatEnd, ToolGeometry::BallNose, ToolGeometry::Vbit { angle: 60.0 }].iter().copied() {
+            let cut = tool(geometry).cut(&contact, &normal);
+            assert_eq!((cut.tip, cut.radius, cut.length, cut.geometry), (contact, 3.0, 20.0, geometry));
+            assert!((cut.axis - axis).norm() < 1e-6);
+            // Nothing below the contact point is cut, and the shank is full width
+            assert!(!cut.contains(&(contact - axis * 0.01)), "{:?} cuts below the contact point", geometry);
+            assert!(cut.contains(&(contact + axis * 10.0 + Vector3::y() * 2.9)));
+        }
+    }
+
+    #[test]
+    fn tip_profile_follows_geometry() {
+        assert_eq!(ToolGeometry::FlatEnd.radius_at(3.0, 0.0), 3.0);
+        assert_eq!(ToolGeometry::BallNose.radius_at(3.0, 0.0), 0.0);
+        assert!((ToolGeometry::BallNose.radius_at(3.0, 1.0) - 5f32.sqrt()).abs() < 1e-5);
+        assert_eq!(ToolGeometry::BallNose.radius_at(3.0, 5.0), 3.0);
+        assert!((ToolGeometry::Vbit { angle: 90.0 }.radius_at(3.0, 1.0) - 1.0).abs() < 1e-5);
+        assert_eq!(ToolGeometry::Vbit { angle: 90.0 }.radius_at(3.0, 5.0), 3.0);
+    }
+
+    #[test]
+    fn tool_rotation_turns_tool_axis_onto_direction() {
+        for direction in [Vector3::new(1.0, 2.0, 3.0), Vector3::z(), -Vector3::z(), Vector3::y(), -Vector3::y()].iter() {
+            let turned = tool_rotation(direction) * Vector3::y();
+            assert!((turned - direction.normalize()).norm() < 1e-5, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn tool_pointing_straight_down_is_turned_over() {
+        // Opposite the tool's own axis, where `rotation_between` has no answer
+        let flipped = tool_rotation(&-Vector3::y());
+        assert!((flipped.angle() - std::f32::consts::PI).abs() < 1e-5);
+        assert!((flipped * Vector3::y() + Vector3::y()).norm() < 1e-5);
+        // A downward surface normal points the tool down, not up
+        let down = tool_rotation(&Vector3::new(0.0, 0.0, -1.0));
+        assert!((down * Vector3::y() - Vector3::new(0.0, 0.0, -1.0)).norm() < 1e-5);
+        // Nearly opposite still lands on the direction
+        let nearly = Vector3::new(1e-7, -1.0, 0.0);
+        assert!((tool_rotation(&nearly) * Vector3::y() - nearly.normalize()).norm() < 1e-5);
+        assert_eq!(tool_rotation(&Vector3::zeros()), UnitQuaternion::identity());
+    }
+
+    #[test]
+    fn largest_tool_fitting_the_radius_is_selected() {
+        let mut library = ToolLibrary::new();
+        for (id, diameter) in [(0, 2.0), (1, 6.0), (2, 4.0)].iter().copied() {
+            library.add_tool(Tool::new(id, "Endmill".to_string(), 20.0, diameter)).unwrap();
+        }
+        let selected = |min_radius: f32| library.select_for_min_radius(min_radius).map(|tool| tool.id);
+        assert_eq!(selected(2.5), Some(2));
+        assert_eq!(selected(2.0), Some(2));
+        assert_eq!(selected(10.0), Some(1));
+        assert_eq!(selected(1.0), Some(0));
+        assert_eq!(selected(0.5), None);
+    }
+
+    #[test]
+    fn tools_with_the_same_id_are_rejected() {
+        let mut library = ToolLibrary::new();
+        library.add_tool(Tool::new(3, "Endmill".to_string(), 20.0, 6.0)).unwrap();
+        library.add_tool(Tool::new(1, "Drill".to_string(), 30.0, 3.0)).unwrap();
+        let duplicate = library.add_tool(Tool::new(3, "Ball".to_string(), 25.0, 4.0));
+        assert!(matches!(duplicate, Err(CAMError::ProcessingError(_))));
+        // The first tool with the id is kept, and lookups go by id
+        assert_eq!(library.get_tool(3).map(|tool| tool.diameter), Some(6.0));
+        assert_eq!(library.iter().map(|tool| tool.id).collect::<Vec<_>>(), vec![1, 3]);
+        library.get_tool_mut(1).unwrap().diameter = 2.5;
+        assert_eq!(library.get_tool(1).map(|tool| tool.diameter), Some(2.5));
+        assert!(library.get_tool(2).is_none());
+    }
+
+    #[test]
+    fn describe_lists_the_spec_on_one_line() {
+        assert_eq!(Tool::new(0, "End Mill 6mm".to_string(), 50.0, 6.0).describe(), "T0 End Mill 6mm ⌀6 L50");
+        assert_eq!(Tool::new(12, "Drill".to_string(), 35.5, 3.2).describe(), "T12 Drill ⌀3.2 L35.5");
+    }
+}
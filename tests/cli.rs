@@ -0,0 +1,81 @@
+//! Runs the `watch-stl` binary in its windowless modes on a generated part.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use carver::prelude::*;
+
+mod common;
+
+/// Scratch path for this test run, removed by the test that writes it.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("carver-cli-{}-{}", std::process::id(), name))
+}
+
+/// Writes a cube of side `size` as a binary STL.
+fn write_cube(path: &Path, size: f32) {
+    write_stl(&common::cube(size), path, true).unwrap();
+}
+
+fn watch_stl(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_watch-stl")).args(args).output().unwrap()
+}
+
+#[test]
+fn headless_run_writes_gcode_without_a_window() {
+    let (stl, gcode) = (temp_path("headless.stl"), temp_path("headless.nc"));
+    write_cube(&stl, 20.0);
+    let output = watch_stl(&["--headless", stl.to_str().unwrap(), "--out", gcode.to_str().unwrap()]);
+    let written = std::fs::read_to_string(&gcode);
+    std::fs::remove_file(&stl).ok();
+    std::fs::remove_file(&gcode).ok();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let written = written.unwrap();
+    assert!(written.starts_with("G21 G90 G94"));
+    assert!(written.contains("M6 T0"));
+    assert!(written.contains("M6 T1"));
+    assert!(written.trim_end().ends_with("M30"));
+}
+
+#[test]
+fn headless_needs_an_output_file() {
+    let output = watch_stl(&["--headless", "part.stl"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn stock_dump_encloses_the_part() {
+    let (stl, stock) = (temp_path("stock-part.stl"), temp_path("stock.stl"));
+    write_cube(&stl, 20.0);
+    let output = watch_stl(&[stl.to_str().unwrap(), "--stock", stock.to_str().unwrap()]);
+    let written = load_stl(&stock);
+    std::fs::remove_file(&stl).ok();
+    std::fs::remove_file(&stock).ok();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let (min, max) = get_bounds(&written.unwrap()).unwrap();
+    // The part is centered on the Z axis and sits on Z = 0, as in the other modes
+    let (part_min, part_max) = ([-10.0, -10.0, 0.0], [10.0, 10.0, 20.0]);
+    for axis in 0..3 {
+        assert!(min[axis] <= part_min[axis] && max[axis] >= part_max[axis], "axis {}: {:?} to {:?}", axis, min, max);
+    }
+}
+
+/// Built with `--no-default-features`, the binary has no window to open but still
+/// runs headless.
+#[cfg(not(feature = "viewer"))]
+#[test]
+fn without_the_viewer_feature_only_headless_runs() {
+    let (stl, gcode) = (temp_path("no-viewer.stl"), temp_path("no-viewer.nc"));
+    write_cube(&stl, 20.0);
+    let viewer = watch_stl(&[stl.to_str().unwrap()]);
+    let headless = watch_stl(&["--headless", stl.to_str().unwrap(), "--out", gcode.to_str().unwrap()]);
+    let written = std::fs::read_to_string(&gcode);
+    std::fs::remove_file(&stl).ok();
+    std::fs::remove_file(&gcode).ok();
+
+    assert!(!viewer.status.success());
+    assert!(String::from_utf8_lossy(&viewer.stderr).contains("--headless"));
+    assert!(headless.status.success(), "{}", String::from_utf8_lossy(&headless.stderr));
+    assert!(written.unwrap().trim_end().ends_with("M30"));
+}
@@ -0,0 +1,28 @@
+//! Parts shared by the integration tests.
+
+use carver::prelude::*;
+
+/// Cube of side `size` centered on the origin, with outward normals.
+pub fn cube(size: f32) -> IndexedMesh {
+    let h = size / 2.0;
+    let vertices = (0..8)
+        .map(|i| Vector::new([if i & 1 == 0 { -h } else { h }, if i & 2 == 0 { -h } else { h }, if i & 4 == 0 { -h } else { h }]))
+        .collect();
+    // Two counter-clockwise triangles per side, seen from outside
+    let quads: [([usize; 4], [f32; 3]); 6] = [
+        ([0, 2, 3, 1], [0.0, 0.0, -1.0]),
+        ([4, 5, 7, 6], [0.0, 0.0, 1.0]),
+        ([0, 1, 5, 4], [0.0, -1.0, 0.0]),
+        ([2, 6, 7, 3], [0.0, 1.0, 0.0]),
+        ([0, 4, 6, 2], [-1.0, 0.0, 0.0]),
+        ([1, 3, 7, 5], [1.0, 0.0, 0.0]),
+    ];
+    let faces = quads
+        .iter()
+        .flat_map(|&([a, b, c, d], normal)| {
+            let normal = Vector::new(normal);
+            vec![IndexedTriangle { normal, vertices: [a, b, c] }, IndexedTriangle { normal, vertices: [a, c, d] }]
+        })
+        .collect();
+    IndexedMesh { vertices, faces }
+}
@@ -0,0 +1,89 @@
+//! Uses carver as a dependency would, through the library and its prelude only.
+
+use carver::prelude::*;
+use nalgebra::{Point3, Vector3};
+use ncollide3d::shape::TriMesh;
+
+mod common;
+
+#[test]
+fn job_builds_through_the_library() {
+    let mut job = CAMJOB::new();
+    job.set_mesh(common::cube(20.0)).unwrap();
+    job.add_tool(Tool::new(0, "Endmill".to_string(), 30.0, 6.0)).unwrap();
+    job.add_task(Box::new(MultiContourTrace::new(Point3::new(0.0, 0.0, -8.0), Point3::new(0.0, 0.0, 8.0), 3, 16, 0)));
+    assert!(!job.is_built());
+    job.build().unwrap();
+
+    assert!(job.is_built());
+    let report = job.report();
+    assert_eq!(report.total_keypoints, 4 * 16);
+    let (min, max) = report.bounds.unwrap();
+    assert!((min - Point3::new(-10.0, -10.0, -8.0)).norm() < 1e-3 && (max - Point3::new(10.0, 10.0, 8.0)).norm() < 1e-3);
+    assert!(export_gcode(&job, &GcodeOptions::default()).unwrap().contains("M6 T0"));
+}
+
+/// Touches the middle of the part's top, as a task written outside the crate would.
+#[derive(Clone)]
+struct TopCenter {
+    keypoints: Vec<Keypoint>,
+    tool_id: usize,
+}
+
+impl CAMTask for TopCenter {
+    fn process(
+        &mut self,
+        mesh: &IndexedMesh,
+        _tri_mesh: &TriMesh<f32>,
+        _tolerances: &Tolerances,
+        progress: &mut dyn FnMut(f32),
+    ) -> Result<(), CAMError> {
+        let (min, max) = get_bounds(mesh)?;
+        self.keypoints = vec![Keypoint::new(Point3::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, max.z), Vector3::z())];
+        progress(1.0);
+        Ok(())
+    }
+    fn get_keypoints(&self) -> Vec<Keypoint> {
+        self.keypoints.clone()
+    }
+    fn set_keypoints(&mut self, keypoints: Vec<Keypoint>) {
+        self.keypoints = keypoints;
+    }
+    fn get_tool_id(&self) -> usize {
+        self.tool_id
+    }
+    fn set_tool_id(&mut self, tool_id: usize) {
+        self.tool_id = tool_id;
+    }
+    fn config(&self) -> TaskConfig {
+        // Job files only know the built-in tasks; this one saves as an empty drilling
+        TaskConfig::DrillHoles { holes: Vec::new(), peck_depth: 0.0, retract_height: 0.0, tool_id: self.tool_id }
+    }
+    fn name(&self) -> &str {
+        "TopCenter"
+    }
+    fn describe(&self, _units: Units) -> String {
+        "top center".to_string()
+    }
+    fn estimated_keypoint_count(&self) -> usize {
+        1
+    }
+    fn clone_box(&self) -> Box<dyn CAMTask> {
+        Box::new(self.clone())
+    }
+}
+
+#[test]
+fn tasks_can_be_written_outside_the_crate() {
+    let mut job = CAMJOB::new();
+    job.set_mesh(common::cube(20.0)).unwrap();
+    job.add_tool(Tool::new(0, "Endmill".to_string(), 30.0, 6.0)).unwrap();
+    job.add_task(Box::new(TopCenter { keypoints: Vec::new(), tool_id: 0 }));
+    job.build().unwrap();
+
+    let keypoints = job.gather_keypoints();
+    assert_eq!(keypoints.len(), 1);
+    assert_eq!(keypoints[0].position, Point3::new(0.0, 0.0, 10.0));
+    assert_eq!(keypoints[0].tool_id, Some(0));
+    assert_eq!(job.report().task_keypoints, vec![("TopCenter".to_string(), 1)]);
+}